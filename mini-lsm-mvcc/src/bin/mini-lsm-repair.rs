@@ -0,0 +1,125 @@
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use mini_lsm_mvcc::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use mini_lsm_mvcc::group_commit::WalSyncPolicy;
+use mini_lsm_mvcc::lsm_storage::LsmStorageOptions;
+use mini_lsm_mvcc::repair::repair;
+use mini_lsm_mvcc::table::CompressionType;
+use mini_lsm_mvcc::wal::WalRecoveryMode;
+use mini_lsm_mvcc::write_stall::WriteStallOptions;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, ValueEnum)]
+enum CompactionStrategy {
+    Simple,
+    Leveled,
+    Tiered,
+    None,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Compression {
+    None,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+/// Rebuild the manifest of a database from its SSTs and WALs. Use the same options as the
+/// database is opened with.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long, default_value = "lsm.db")]
+    path: PathBuf,
+    #[arg(long, default_value = "leveled")]
+    compaction: CompactionStrategy,
+    #[arg(long, default_value = "none")]
+    compression: Compression,
+    #[arg(long)]
+    value_separation_threshold: Option<usize>,
+    #[arg(long)]
+    block_hash_index: bool,
+    #[arg(long)]
+    partitioned_index: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let report = repair(
+        &args.path,
+        &LsmStorageOptions {
+            block_size: 4096,
+            target_sst_size: 2 << 20, // 2MB
+            num_memtable_limit: 3,
+            compaction_options: match args.compaction {
+                CompactionStrategy::None => CompactionOptions::NoCompaction,
+                CompactionStrategy::Simple => {
+                    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                        size_ratio_percent: 200,
+                        level0_file_num_compaction_trigger: 2,
+                        max_levels: 4,
+                    })
+                }
+                CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+                    num_tiers: 3,
+                    max_size_amplification_percent: 200,
+                    size_ratio: 1,
+                    min_merge_width: 2,
+                    max_merge_width: None,
+                }),
+                CompactionStrategy::Leveled => {
+                    CompactionOptions::Leveled(LeveledCompactionOptions {
+                        level0_file_num_compaction_trigger: 2,
+                        max_levels: 4,
+                        base_level_size_mb: 128,
+                        level_size_multiplier: 2,
+                    })
+                }
+            },
+            enable_wal: true,
+            serializable: false,
+            compression: match args.compression {
+                Compression::None => CompressionType::None,
+                Compression::Lz4 => CompressionType::Lz4,
+                Compression::Zstd => CompressionType::Zstd,
+                Compression::Snappy => CompressionType::Snappy,
+            },
+            value_separation_threshold: args.value_separation_threshold,
+            max_subcompactions: 1,
+            history_retention: 0,
+            max_manifest_size: 4 << 20,
+            quarantine_orphan_files: false,
+            write_stall: WriteStallOptions::default(),
+            wal_sync: WalSyncPolicy::Never,
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            block_hash_index: args.block_hash_index,
+            partitioned_index: args.partitioned_index,
+            max_open_files: None,
+            block_cache_size: 4 << 30,
+            block_cache: None,
+        },
+    )?;
+
+    for (id, error) in &report.quarantined_ssts {
+        println!("{}.sst is corrupted and moved aside: {}", id, error);
+    }
+    for id in &report.removed_ssts {
+        println!("{}.sst was removed by a compaction and is deleted", id);
+    }
+    for id in &report.corrupted_wals {
+        println!(
+            "{}.wal is corrupted and replayed without the damaged batches",
+            id
+        );
+    }
+    println!(
+        "manifest rebuilt with {} SSTs, {} WALs replayed",
+        report.ssts.len(),
+        report.replayed_wals.len()
+    );
+    Ok(())
+}
@@ -1,5 +1,71 @@
 pub mod mini_lsm_wrapper {
     pub use mini_lsm_mvcc::*;
+
+    /// The shared `mini-lsm-cli` only sets the options every implementation has, so this fills in
+    /// the rest with their defaults. `MINI_LSM_COMPRESSION` (`none`, `lz4`, `zstd` or `snappy`)
+    /// picks the compression of new SSTs.
+    #[allow(dead_code, unused_imports)]
+    pub mod lsm_storage {
+        use std::ops::Deref;
+        use std::path::Path;
+        use std::sync::Arc;
+
+        use anyhow::{bail, Result};
+
+        use mini_lsm_mvcc::compact::CompactionOptions;
+        pub use mini_lsm_mvcc::lsm_storage::*;
+        use mini_lsm_mvcc::table::CompressionType;
+
+        pub struct LsmStorageOptions {
+            pub block_size: usize,
+            pub target_sst_size: usize,
+            pub num_memtable_limit: usize,
+            pub compaction_options: CompactionOptions,
+            pub enable_wal: bool,
+            pub serializable: bool,
+        }
+
+        fn compression() -> Result<CompressionType> {
+            let Ok(compression) = std::env::var("MINI_LSM_COMPRESSION") else {
+                return Ok(CompressionType::None);
+            };
+            Ok(match compression.as_str() {
+                "none" => CompressionType::None,
+                "lz4" => CompressionType::Lz4,
+                "zstd" => CompressionType::Zstd,
+                "snappy" => CompressionType::Snappy,
+                _ => bail!("unknown compression {}", compression),
+            })
+        }
+
+        pub struct MiniLsm(Arc<mini_lsm_mvcc::lsm_storage::MiniLsm>);
+
+        impl MiniLsm {
+            pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+                let mut full_options =
+                    mini_lsm_mvcc::lsm_storage::LsmStorageOptions::default_for_week1_test();
+                full_options.block_size = options.block_size;
+                full_options.target_sst_size = options.target_sst_size;
+                full_options.num_memtable_limit = options.num_memtable_limit;
+                full_options.compaction_options = options.compaction_options;
+                full_options.enable_wal = options.enable_wal;
+                full_options.serializable = options.serializable;
+                full_options.compression = compression()?;
+                Ok(Arc::new(Self(mini_lsm_mvcc::lsm_storage::MiniLsm::open(
+                    path,
+                    full_options,
+                )?)))
+            }
+        }
+
+        impl Deref for MiniLsm {
+            type Target = mini_lsm_mvcc::lsm_storage::MiniLsm;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -1,43 +1,214 @@
 mod builder;
 mod iterator;
 
+use anyhow::{anyhow, bail, Result};
 pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::varint::get_varint;
+
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// Set in the number of restart points of a block that is followed by a hash index.
+const HASH_INDEX_FLAG: u32 = 1 << 31;
+/// A hash index bucket without keys.
+const HASH_BUCKET_EMPTY: u8 = u8::MAX;
+/// A hash index bucket with keys of different restart points.
+const HASH_BUCKET_COLLISION: u8 = u8::MAX - 1;
+/// Blocks with more restart points than this have no hash index, as a bucket stores the restart
+/// index in a byte.
+pub(crate) const HASH_INDEX_MAX_RESTARTS: usize = HASH_BUCKET_COLLISION as usize;
+
+/// The encoding of the entries of a block, which is set by the format version of its SST.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// Keys share a prefix with the first key of the block. Lengths, offsets and their number are
+    /// `u16`, which limits keys and values to 64 KiB. `offsets` has the offset of every entry.
+    Legacy,
+    /// Like `Legacy`, with varint lengths and `u32` offsets.
+    Varint,
+    /// Keys share a prefix with the previous key, except for the entries at restart points, which
+    /// store their full key. `offsets` only has the offsets of the restart points.
+    Restart,
+}
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
+///
+/// Entries are laid out as `overlap | key_len | key | ts (u64) | value_len | value`, where
+/// `overlap` is the length of the prefix shared with the key the entry is delta-encoded against,
+/// followed by the offsets and their number as set by the `BlockFormat`.
+///
+/// A block in the `Restart` format may have a hash index for point lookups between the restart
+/// points and their number: `buckets (u8 each) | num_buckets (u16)`. Each bucket has the index of
+/// the restart point before the first entry of the user keys hashed to it.
 pub struct Block {
     pub(crate) data: Vec<u8>,
-    pub(crate) offsets: Vec<u16>,
+    pub(crate) offsets: Vec<u32>,
+    pub(crate) format: BlockFormat,
+    pub(crate) hash_index: Option<Vec<u8>>,
+}
+
+/// Result of looking up a user key in the hash index of a block.
+enum HashIndexLookup {
+    /// The key is not in the block.
+    Absent,
+    /// The first entry of the key is after the restart point of this index.
+    Restart(usize),
+    /// The block has no hash index, or the key shares a bucket with keys of other restart points.
+    Unknown,
 }
 
 impl Block {
+    /// A block without entries, which is what a corrupted block reads as when salvaging.
+    pub(crate) fn empty() -> Self {
+        Self {
+            data: Vec::new(),
+            offsets: Vec::new(),
+            format: BlockFormat::Restart,
+            hash_index: None,
+        }
+    }
+
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
         let offsets_len = self.offsets.len();
         for offset in &self.offsets {
-            buf.put_u16(*offset);
+            if self.format == BlockFormat::Legacy {
+                buf.put_u16(*offset as u16);
+            } else {
+                buf.put_u32(*offset);
+            }
         }
         // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        if self.format == BlockFormat::Legacy {
+            buf.put_u16(offsets_len as u16);
+        } else if let Some(buckets) = &self.hash_index {
+            buf.put_slice(buckets);
+            buf.put_u16(buckets.len() as u16);
+            buf.put_u32(offsets_len as u32 | HASH_INDEX_FLAG);
+        } else {
+            buf.put_u32(offsets_len as u32);
+        }
         buf.into()
     }
 
+    /// Decode a block in the format used for newly-built blocks.
     pub fn decode(data: &[u8]) -> Self {
+        Self::decode_with_format(data, BlockFormat::Restart)
+    }
+
+    pub fn decode_with_format(data: &[u8], format: BlockFormat) -> Self {
+        Self::try_decode_with_format(data, format).expect("malformed block")
+    }
+
+    /// Decode a block read from an SST, which fails instead of panicking if the block is
+    /// malformed.
+    pub fn try_decode_with_format(data: &[u8], format: BlockFormat) -> Result<Self> {
+        let sizeof_offset = if format == BlockFormat::Legacy {
+            SIZEOF_U16
+        } else {
+            SIZEOF_U32
+        };
+        let get_offset = |mut x: &[u8]| {
+            if format == BlockFormat::Legacy {
+                x.get_u16() as u32
+            } else {
+                x.get_u32()
+            }
+        };
+        if data.len() < sizeof_offset {
+            bail!("block of {} bytes is too short", data.len());
+        }
         // get number of elements in the block
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        let mut entry_offsets_len = get_offset(&data[data.len() - sizeof_offset..]);
+        let mut offsets_end = data.len() - sizeof_offset;
+        let mut hash_index = None;
+        if format == BlockFormat::Restart && entry_offsets_len & HASH_INDEX_FLAG != 0 {
+            entry_offsets_len &= !HASH_INDEX_FLAG;
+            if offsets_end < SIZEOF_U16 {
+                bail!("block hash index is truncated");
+            }
+            let num_buckets = (&data[offsets_end - SIZEOF_U16..]).get_u16() as usize;
+            if num_buckets == 0 {
+                bail!("block hash index has no buckets");
+            }
+            offsets_end = offsets_end
+                .checked_sub(SIZEOF_U16 + num_buckets)
+                .ok_or_else(|| anyhow!("block hash index is truncated"))?;
+            hash_index = Some(data[offsets_end..offsets_end + num_buckets].to_vec());
+        }
+        let entry_offsets_len = entry_offsets_len as usize;
+        let data_end = entry_offsets_len
+            .checked_mul(sizeof_offset)
+            .and_then(|offsets_len| offsets_end.checked_sub(offsets_len))
+            .ok_or_else(|| anyhow!("block has {} offsets past its start", entry_offsets_len))?;
+        let offsets_raw = &data[data_end..offsets_end];
         // get offset array
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
-            .collect();
+        let offsets: Vec<u32> = offsets_raw.chunks(sizeof_offset).map(get_offset).collect();
+        if offsets.iter().any(|offset| *offset as usize > data_end) {
+            bail!("block has an entry offset past its data");
+        }
         // retrieve data
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Ok(Self {
+            data,
+            offsets,
+            format,
+            hash_index,
+        })
+    }
+
+    /// Build the hash index buckets from the hashes of the user keys and the index of the restart
+    /// point before their first entry.
+    pub(crate) fn build_hash_index(keys: &[(u32, u8)]) -> Vec<u8> {
+        let num_buckets = Self::num_hash_buckets(keys.len());
+        let mut buckets = vec![HASH_BUCKET_EMPTY; num_buckets];
+        for (hash, restart) in keys {
+            let bucket = &mut buckets[*hash as usize % num_buckets];
+            if *bucket == HASH_BUCKET_EMPTY {
+                *bucket = *restart;
+            } else if *bucket != *restart {
+                *bucket = HASH_BUCKET_COLLISION;
+            }
+        }
+        buckets
+    }
+
+    /// Number of hash index buckets for `num_keys` user keys, which keeps the buckets at most 3/4
+    /// full.
+    pub(crate) fn num_hash_buckets(num_keys: usize) -> usize {
+        num_keys * 4 / 3 + 1
+    }
+
+    fn hash_index_lookup(&self, key: &[u8]) -> HashIndexLookup {
+        let Some(buckets) = &self.hash_index else {
+            return HashIndexLookup::Unknown;
+        };
+        let hash = farmhash::fingerprint32(key);
+        match buckets[hash as usize % buckets.len()] {
+            HASH_BUCKET_EMPTY => HashIndexLookup::Absent,
+            HASH_BUCKET_COLLISION => HashIndexLookup::Unknown,
+            restart => HashIndexLookup::Restart(restart as usize),
+        }
+    }
+
+    /// Approximate memory used by the block, which it is charged in the block cache.
+    pub(crate) fn charge(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.data.len()
+            + self.offsets.len() * SIZEOF_U32
+            + self.hash_index.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Read a key or value length of an entry.
+    fn get_len(&self, buf: &mut &[u8]) -> usize {
+        if self.format == BlockFormat::Legacy {
+            buf.get_u16() as usize
+        } else {
+            get_varint(buf).expect("malformed block entry") as usize
+        }
     }
 }
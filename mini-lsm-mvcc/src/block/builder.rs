@@ -1,28 +1,39 @@
 use bytes::BufMut;
 
 use crate::key::{KeySlice, KeyVec};
+use crate::varint::{put_varint, varint_len};
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, BlockFormat, HASH_INDEX_MAX_RESTARTS, SIZEOF_U16, SIZEOF_U32};
+
+/// Number of entries between two restart points of a block by default.
+pub(crate) const RESTART_INTERVAL: usize = 16;
 
 /// Builds a block.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of the entries at restart points, which store their full key.
+    restarts: Vec<u32>,
     /// All serialized key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
-    /// The first key in the block
-    first_key: KeyVec,
+    /// The previous key in the block, which the next key is delta-encoded against.
+    last_key: KeyVec,
+    /// Number of entries added since the last restart point.
+    num_since_restart: usize,
+    /// Number of entries between two restart points.
+    restart_interval: usize,
+    /// Hashes of the user keys and the index of the restart point before their first entry, if
+    /// the block has a hash index.
+    hash_keys: Option<Vec<(u32, u8)>>,
 }
 
-fn compute_overlap(first_key: KeySlice, key: KeySlice) -> usize {
+fn compute_overlap(last_key: KeySlice, key: KeySlice) -> usize {
     let mut i = 0;
     loop {
-        if i >= first_key.key_len() || i >= key.key_len() {
+        if i >= last_key.key_len() || i >= key.key_len() {
             break;
         }
-        if first_key.key_ref()[i] != key.key_ref()[i] {
+        if last_key.key_ref()[i] != key.key_ref()[i] {
             break;
         }
         i += 1;
@@ -34,53 +45,99 @@ impl BlockBuilder {
     /// Creates a new block builder.
     pub fn new(block_size: usize) -> Self {
         Self {
-            offsets: Vec::new(),
+            restarts: Vec::new(),
             data: Vec::new(),
             block_size,
-            first_key: KeyVec::new(),
+            last_key: KeyVec::new(),
+            num_since_restart: 0,
+            restart_interval: RESTART_INTERVAL,
+            hash_keys: None,
         }
     }
 
+    /// Start a restart point every `restart_interval` entries. With an interval of 1, every entry
+    /// stores its full key and can be read directly by its index with
+    /// `BlockIterator::create_and_seek_to_restart`.
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        self.restart_interval = restart_interval;
+        self
+    }
+
+    /// Append a hash index of the user keys to the block for point lookups. It is left out if the
+    /// block has too many restart points.
+    pub fn with_hash_index(mut self, hash_index: bool) -> Self {
+        self.hash_keys = hash_index.then(Vec::new);
+        self
+    }
+
     fn estimated_size(&self) -> usize {
-        SIZEOF_U16 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U16 /* offsets */ + self.data.len()
-        // key-value pairs
+        SIZEOF_U32 /* number of restart points in the block */ +  self.restarts.len() * SIZEOF_U32 /* restart offsets */ + self.data.len() /* key-value pairs */ + self.hash_index_size()
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    fn hash_index_size(&self) -> usize {
+        match &self.hash_keys {
+            Some(keys) => Block::num_hash_buckets(keys.len()) + SIZEOF_U16,
+            None => 0,
+        }
+    }
+
+    /// Adds a key-value pair to the block. Returns false when the block is full. An entry larger
+    /// than the block size is only added to an empty block, which then holds no other entry.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
-            && !self.is_empty()
-        {
+        let restart = self.is_empty() || self.num_since_restart >= self.restart_interval;
+        let overlap = if restart {
+            0
+        } else {
+            compute_overlap(self.last_key.as_key_slice(), key)
+        };
+        let rest_len = key.key_len() - overlap;
+        let entry_size = varint_len(overlap as u64)
+            + varint_len(rest_len as u64)
+            + rest_len
+            + std::mem::size_of::<u64>()
+            + varint_len(value.len() as u64)
+            + value.len();
+        let restart_size = if restart { SIZEOF_U32 } else { 0 };
+        if self.estimated_size() + entry_size + restart_size > self.block_size && !self.is_empty() {
             return false;
         }
-        // Add the offset of the data into the offset array.
-        self.offsets.push(self.data.len() as u16);
-        let overlap = compute_overlap(self.first_key.as_key_slice(), key);
+        if restart {
+            // Add the offset of the data into the restart array.
+            self.restarts.push(self.data.len() as u32);
+            self.num_since_restart = 0;
+        }
+        self.num_since_restart += 1;
+        if let Some(keys) = &mut self.hash_keys {
+            // Only the first entry of a user key is indexed, which is where lookups start.
+            if keys.is_empty() || self.last_key.key_ref() != key.key_ref() {
+                let restart = (self.restarts.len() - 1).min(u8::MAX as usize) as u8;
+                keys.push((farmhash::fingerprint32(key.key_ref()), restart));
+            }
+        }
         // Encode key overlap.
-        self.data.put_u16(overlap as u16);
+        put_varint(&mut self.data, overlap as u64);
         // Encode key length.
-        self.data.put_u16((key.key_len() - overlap) as u16);
+        put_varint(&mut self.data, rest_len as u64);
         // Encode key content.
         self.data.put(&key.key_ref()[overlap..]);
         // Encode key ts
         self.data.put_u64(key.ts());
         // Encode value length.
-        self.data.put_u16(value.len() as u16);
+        put_varint(&mut self.data, value.len() as u64);
         // Encode value content.
         self.data.put(value);
 
-        if self.first_key.is_empty() {
-            self.first_key = key.to_key_vec();
-        }
+        self.last_key.set_from_slice(key);
 
         true
     }
 
     /// Check if there are no key-value pairs in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.restarts.is_empty()
     }
 
     /// Finalize the block.
@@ -90,7 +147,12 @@ impl BlockBuilder {
         }
         Block {
             data: self.data,
-            offsets: self.offsets,
+            hash_index: self
+                .hash_keys
+                .filter(|_| self.restarts.len() <= HASH_INDEX_MAX_RESTARTS)
+                .map(|keys| Block::build_hash_index(&keys)),
+            offsets: self.restarts,
+            format: BlockFormat::Restart,
         }
     }
 }
@@ -2,12 +2,9 @@ use std::sync::Arc;
 
 use bytes::Buf;
 
-use crate::{
-    block::SIZEOF_U16,
-    key::{KeySlice, KeyVec},
-};
+use crate::key::{KeySlice, KeyVec};
 
-use super::Block;
+use super::{Block, BlockFormat, HashIndexLookup};
 
 /// Iterates on a block.
 pub struct BlockIterator {
@@ -17,17 +14,20 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the current value range in the block.data, corresponds to the current key
     value_range: (usize, usize),
-    /// the current index at the iterator position
-    idx: usize,
+    /// the offset of the current entry in the block.data
+    offset: usize,
     /// the first key in the block
     first_key: KeyVec,
 }
 
 impl Block {
     fn get_first_key(&self) -> KeyVec {
+        if self.data.is_empty() {
+            return KeyVec::new();
+        }
         let mut buf = &self.data[..];
-        buf.get_u16();
-        let key_len = buf.get_u16() as usize;
+        self.get_len(&mut buf);
+        let key_len = self.get_len(&mut buf);
         let key = &buf[..key_len];
         buf.advance(key_len);
         KeyVec::from_vec_with_ts(key.to_vec(), buf.get_u64())
@@ -41,7 +41,18 @@ impl BlockIterator {
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
-            idx: 0,
+            offset: 0,
+        }
+    }
+
+    /// Creates an iterator that is never valid, e.g., for an SST without data blocks.
+    pub(crate) fn empty() -> Self {
+        Self {
+            block: Arc::new(Block::empty()),
+            key: KeyVec::new(),
+            value_range: (0, 0),
+            offset: 0,
+            first_key: KeyVec::new(),
         }
     }
 
@@ -59,8 +70,50 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator for a point lookup and seek to the first key that >= `key`, which
+    /// starts from the restart point found in the hash index of the block if it has one. Returns
+    /// `None` if the hash index tells that the block has no entry of the user key of `key`.
+    pub(crate) fn create_and_seek_to_key_for_get(block: Arc<Block>, key: KeySlice) -> Option<Self> {
+        let mut iter = Self::new(block);
+        match iter.block.hash_index_lookup(key.key_ref()) {
+            HashIndexLookup::Absent => return None,
+            HashIndexLookup::Restart(idx) => {
+                iter.seek_to_offset(iter.block.offsets[idx] as usize);
+                while iter.is_valid() && iter.key() < key {
+                    iter.next();
+                }
+            }
+            HashIndexLookup::Unknown => iter.seek_to_key(key),
+        }
+        Some(iter)
+    }
+
+    /// Creates a block iterator and seek to the entry at the `idx`-th restart point.
+    pub(crate) fn create_and_seek_to_restart(block: Arc<Block>, idx: usize) -> Self {
+        let mut iter = Self::new(block);
+        match iter.block.offsets.get(idx) {
+            Some(offset) => iter.seek_to_offset(*offset as usize),
+            None => iter.invalidate(),
+        }
+        iter
+    }
+
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
+    /// Creates a block iterator and seek to the last key that <= `key`.
+    pub fn create_and_seek_for_prev(block: Arc<Block>, key: KeySlice) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_for_prev(key);
+        iter
+    }
+
     /// Returns the key of the current entry.
-    pub fn key(&self) -> KeySlice {
+    pub fn key(&self) -> KeySlice<'_> {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
         self.key.as_key_slice()
     }
@@ -71,6 +124,15 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the index of the last restart point at or before the current entry.
+    pub(crate) fn restart_idx(&self) -> usize {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.block
+            .offsets
+            .partition_point(|offset| *offset as usize <= self.offset)
+            - 1
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -78,58 +140,96 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        self.seek_to_offset(0);
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
-        if idx >= self.block.offsets.len() {
-            self.key.clear();
-            self.value_range = (0, 0);
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        let Some(&last) = self.block.offsets.last() else {
+            self.invalidate();
             return;
+        };
+        self.seek_to_offset(last as usize);
+        while self.value_range.1 < self.block.data.len() {
+            self.next();
         }
-        let offset = self.block.offsets[idx] as usize;
-        self.seek_to_offset(offset);
-        self.idx = idx;
     }
 
-    /// Move to the next key in the block.
+    fn invalidate(&mut self) {
+        self.key.clear();
+        self.value_range = (0, 0);
+    }
+
+    /// Move to the next key in the block. Before the first entry, e.g., after `prev` moved past
+    /// it, this moves to the first entry.
     pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+        if !self.is_valid() && self.offset >= self.block.data.len() {
+            return;
+        }
+        // Entries are contiguous, so the next one starts where the current value ends.
+        self.seek_to_offset(self.value_range.1);
     }
 
-    /// Seek to the specified position and update the current `key` and `value`
-    /// Index update will be handled by caller
+    /// Move to the previous key in the block. The previous key may be delta-encoded against the
+    /// keys before it, so it is decoded from the closest offset before the current entry.
+    pub fn prev(&mut self) {
+        let current = self.offset;
+        let idx = self
+            .block
+            .offsets
+            .partition_point(|offset| (*offset as usize) < current);
+        if idx == 0 {
+            self.invalidate();
+            self.offset = 0;
+            return;
+        }
+        self.seek_to_offset(self.block.offsets[idx - 1] as usize);
+        while self.value_range.1 < current {
+            self.next();
+        }
+    }
+
+    /// Seek to the entry at the specified offset and update the current `key` and `value`. The
+    /// current key must be the previous entry unless the entry stores its full key, i.e., it is
+    /// at a restart point or the block is not in the `Restart` format.
     fn seek_to_offset(&mut self, offset: usize) {
+        if offset >= self.block.data.len() {
+            // After the last entry, from which `prev` moves back to it.
+            self.invalidate();
+            self.offset = self.block.data.len();
+            return;
+        }
         let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
-        // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
-        let key_len = entry.get_u16() as usize;
+        // Reading the lengths moves `entry` past them, so we don't need to manually advance it
+        let overlap_len = self.block.get_len(&mut entry);
+        let key_len = self.block.get_len(&mut entry);
         let key = &entry[..key_len];
-        self.key.clear();
-        self.key.append(&self.first_key.key_ref()[..overlap_len]);
+        if self.block.format == BlockFormat::Restart {
+            self.key.truncate(overlap_len);
+        } else {
+            self.key.clear();
+            self.key.append(&self.first_key.key_ref()[..overlap_len]);
+        }
         self.key.append(key);
         entry.advance(key_len);
         let ts = entry.get_u64();
         self.key.set_ts(ts);
-        let value_len = entry.get_u16() as usize;
-        // REMEMBER TO CHANGE THIS every time you change the encoding!
-        let value_offset_begin =
-            offset + SIZEOF_U16 + SIZEOF_U16 + std::mem::size_of::<u64>() + key_len + SIZEOF_U16;
+        let value_len = self.block.get_len(&mut entry);
+        // The lengths have variable sizes, so the value starts where `entry` has been advanced to.
+        let value_offset_begin = self.block.data.len() - entry.len();
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
-        entry.advance(value_len);
+        self.offset = offset;
     }
 
-    /// Seek to the first key that is >= `key`.
+    /// Seek to the first key that is >= `key`. Binary searches the offsets, whose entries store
+    /// their full key, for the last one before `key`, and then scans forward from it.
     pub fn seek_to_key(&mut self, key: KeySlice) {
         let mut low = 0;
         let mut high = self.block.offsets.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
+            self.seek_to_offset(self.block.offsets[mid] as usize);
             assert!(self.is_valid());
             match self.key().cmp(&key) {
                 std::cmp::Ordering::Less => low = mid + 1,
@@ -137,6 +237,23 @@ impl BlockIterator {
                 std::cmp::Ordering::Equal => return,
             }
         }
-        self.seek_to(low);
+        if low == 0 {
+            self.seek_to_first();
+            return;
+        }
+        self.seek_to_offset(self.block.offsets[low - 1] as usize);
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
+    }
+
+    /// Seek to the last key that is <= `key`.
+    pub fn seek_for_prev(&mut self, key: KeySlice) {
+        self.seek_to_key(key);
+        if !self.is_valid() {
+            self.seek_to_last();
+        } else if self.key() > key {
+            self.prev();
+        }
     }
 }
@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::block::Block;
+use crate::table::bloom::Bloom;
+
+/// Number of shards of a cache is `1 << DEFAULT_NUM_SHARD_BITS` by default.
+const DEFAULT_NUM_SHARD_BITS: usize = 4;
+/// Share of the capacity reserved for high-priority blocks by default.
+const DEFAULT_HIGH_PRIORITY_RATIO: f64 = 0.5;
+
+/// Priority of a block in the cache. Low-priority blocks are evicted first, while high-priority
+/// blocks take no more than the high-priority share of the capacity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachePriority {
+    /// Index and filter partitions, which are needed by every read of the blocks they cover.
+    High,
+    /// Data blocks.
+    Low,
+}
+
+/// Hit and miss counts and the memory usage of a block cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of blocks evicted to make room for others.
+    pub evictions: u64,
+    /// Bytes charged for the cached blocks.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+/// The kind of a cached block, which is part of its key so that blocks of different kinds at the
+/// same index of an SST do not collide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+    Data,
+    /// A partition of a partitioned index.
+    IndexPartition,
+    /// A partition of a partitioned bloom filter.
+    FilterPartition,
+}
+
+/// A cache key: the namespace of the storage, the SST id, the kind of block and its index among
+/// the blocks of that kind.
+type CacheKey = (usize, usize, BlockKind, usize);
+
+/// A cached block, or a filter partition, which is cached decoded.
+#[derive(Clone)]
+enum CacheValue {
+    Block(Arc<Block>),
+    Filter(Arc<Bloom>),
+}
+
+impl CacheValue {
+    fn charge(&self) -> usize {
+        match self {
+            Self::Block(block) => block.charge(),
+            Self::Filter(bloom) => bloom.charge(),
+        }
+    }
+}
+
+struct CacheEntry {
+    value: CacheValue,
+    charge: usize,
+    priority: CachePriority,
+    /// Position of the entry in the LRU list of its priority.
+    tick: u64,
+}
+
+/// A value being loaded into the cache. The thread loading it holds the lock until it is loaded,
+/// and the threads waiting on the lock take the loaded value, or load it themselves if loading
+/// failed.
+type InFlightLoad = Arc<Mutex<Option<CacheValue>>>;
+
+/// A shard of the cache, with an LRU list per priority. The lists map the time an entry was last
+/// used to its key, so that the first entry of a list is the least recently used.
+struct LruShard {
+    capacity: usize,
+    high_priority_capacity: usize,
+    usage: usize,
+    high_priority_usage: usize,
+    entries: HashMap<CacheKey, CacheEntry>,
+    high_priority_lru: BTreeMap<u64, CacheKey>,
+    low_priority_lru: BTreeMap<u64, CacheKey>,
+    next_tick: u64,
+    /// Values being loaded, so that a missing value is only loaded by one thread at a time.
+    in_flight: HashMap<CacheKey, InFlightLoad>,
+}
+
+impl LruShard {
+    fn new(capacity: usize, high_priority_ratio: f64) -> Self {
+        Self {
+            capacity,
+            high_priority_capacity: (capacity as f64 * high_priority_ratio) as usize,
+            usage: 0,
+            high_priority_usage: 0,
+            entries: HashMap::new(),
+            high_priority_lru: BTreeMap::new(),
+            low_priority_lru: BTreeMap::new(),
+            next_tick: 0,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    fn lru(&mut self, priority: CachePriority) -> &mut BTreeMap<u64, CacheKey> {
+        match priority {
+            CachePriority::High => &mut self.high_priority_lru,
+            CachePriority::Low => &mut self.low_priority_lru,
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<CacheValue> {
+        let tick = self.next_tick;
+        let entry = self.entries.get_mut(key)?;
+        self.next_tick += 1;
+        let (old_tick, priority) = (entry.tick, entry.priority);
+        entry.tick = tick;
+        let value = entry.value.clone();
+        let lru = self.lru(priority);
+        lru.remove(&old_tick);
+        lru.insert(tick, *key);
+        Some(value)
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru(entry.priority).remove(&entry.tick);
+        self.usage -= entry.charge;
+        if entry.priority == CachePriority::High {
+            self.high_priority_usage -= entry.charge;
+        }
+        Some(entry)
+    }
+
+    /// Insert a block, evicting the least recently used blocks to keep the usage within the
+    /// capacity. Blocks larger than the capacity are not cached. Returns the number of evicted
+    /// blocks.
+    fn insert(&mut self, key: CacheKey, value: CacheValue, priority: CachePriority) -> u64 {
+        let charge = value.charge();
+        self.remove(&key);
+        if charge > self.capacity {
+            return 0;
+        }
+        let mut evictions = 0;
+        while self.usage + charge > self.capacity {
+            // Low-priority blocks go first, unless high-priority blocks exceed their share.
+            let lru = if self.low_priority_lru.is_empty()
+                || self.high_priority_usage > self.high_priority_capacity
+            {
+                &self.high_priority_lru
+            } else {
+                &self.low_priority_lru
+            };
+            let (_, victim) = lru.first_key_value().expect("usage without cached blocks");
+            let victim = *victim;
+            self.remove(&victim);
+            evictions += 1;
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru(priority).insert(tick, key);
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                charge,
+                priority,
+                tick,
+            },
+        );
+        self.usage += charge;
+        if priority == CachePriority::High {
+            self.high_priority_usage += charge;
+        }
+        evictions
+    }
+}
+
+/// A sharded LRU cache of blocks, whose capacity is in bytes. Each shard holds an equal share of
+/// the capacity, and its usage never exceeds it. The cache may be shared by several storages,
+/// each reading it through its own `BlockCache`.
+pub struct ShardedBlockCache {
+    shards: Vec<Mutex<LruShard>>,
+    hasher: RandomState,
+    capacity: usize,
+    /// The namespace of the next `BlockCache` reading this cache.
+    next_namespace: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ShardedBlockCache {
+    /// Create a cache of `capacity` bytes with the default number of shards and high-priority
+    /// share.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_config(
+            capacity,
+            DEFAULT_NUM_SHARD_BITS,
+            DEFAULT_HIGH_PRIORITY_RATIO,
+        )
+    }
+
+    /// Create a cache of `capacity` bytes with `1 << num_shard_bits` shards, where high-priority
+    /// blocks take at most `high_priority_ratio` of the capacity when the cache is full.
+    pub fn with_config(capacity: usize, num_shard_bits: usize, high_priority_ratio: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&high_priority_ratio),
+            "high priority ratio must be in [0, 1]"
+        );
+        let num_shards = 1 << num_shard_bits;
+        Self {
+            shards: (0..num_shards)
+                .map(|_| Mutex::new(LruShard::new(capacity / num_shards, high_priority_ratio)))
+                .collect(),
+            hasher: RandomState::new(),
+            capacity,
+            next_namespace: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Mutex<LruShard> {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<CacheValue> {
+        let value = self.shard(key).lock().get(key);
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    fn insert(&self, key: CacheKey, value: CacheValue, priority: CachePriority) {
+        let evictions = self.shard(&key).lock().insert(key, value, priority);
+        self.evictions.fetch_add(evictions, Ordering::Relaxed);
+    }
+
+    fn contains_key(&self, key: &CacheKey) -> bool {
+        self.shard(key).lock().entries.contains_key(key)
+    }
+
+    /// Get a cached value, or load it with `init` and cache it. Threads missing the same key at the
+    /// same time wait for the first one to load it instead of each loading it.
+    fn try_get_with(
+        &self,
+        key: CacheKey,
+        priority: CachePriority,
+        init: impl FnOnce() -> Result<CacheValue>,
+    ) -> Result<CacheValue> {
+        let load = {
+            let mut shard = self.shard(&key).lock();
+            if let Some(value) = shard.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(value);
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            shard.in_flight.entry(key).or_default().clone()
+        };
+        let mut loaded = load.lock();
+        if let Some(value) = loaded.as_ref() {
+            return Ok(value.clone());
+        }
+        let result = init();
+        if let Ok(value) = &result {
+            self.insert(key, value.clone(), priority);
+            *loaded = Some(value.clone());
+        }
+        let mut shard = self.shard(&key).lock();
+        if shard
+            .in_flight
+            .get(&key)
+            .is_some_and(|in_flight| Arc::ptr_eq(in_flight, &load))
+        {
+            shard.in_flight.remove(&key);
+        }
+        result
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            usage: self.shards.iter().map(|shard| shard.lock().usage).sum(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl std::fmt::Debug for ShardedBlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardedBlockCache")
+            .field("num_shards", &self.shards.len())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// The block cache of a storage, keyed by SST id and block index. It reads a `ShardedBlockCache`
+/// in a namespace of its own, so that storages sharing the cache do not see each other's blocks.
+/// Data blocks are keyed by `(sst_id, block_idx)`, and partitions by `(sst_id, partition_idx)`.
+pub struct BlockCache {
+    cache: Arc<ShardedBlockCache>,
+    namespace: usize,
+}
+
+impl BlockCache {
+    /// Create a block cache of `capacity` bytes that is not shared.
+    pub fn new(capacity: usize) -> Self {
+        Self::shared(Arc::new(ShardedBlockCache::new(capacity)))
+    }
+
+    /// Read a cache that may be shared with other storages.
+    pub fn shared(cache: Arc<ShardedBlockCache>) -> Self {
+        let namespace = cache.next_namespace.fetch_add(1, Ordering::Relaxed);
+        Self { cache, namespace }
+    }
+
+    fn key(&self, kind: BlockKind, (id, idx): (usize, usize)) -> CacheKey {
+        (self.namespace, id, kind, idx)
+    }
+
+    /// Get a cached data block, which is counted as a hit or a miss.
+    pub fn get(&self, key: &(usize, usize)) -> Option<Arc<Block>> {
+        match self.cache.get(&self.key(BlockKind::Data, *key)) {
+            Some(CacheValue::Block(block)) => Some(block),
+            _ => None,
+        }
+    }
+
+    /// Whether a data block is cached, without counting it as a hit or a miss.
+    pub fn contains_key(&self, key: &(usize, usize)) -> bool {
+        self.contains(BlockKind::Data, key)
+    }
+
+    /// Whether a block of the given kind is cached, without counting it as a hit or a miss.
+    pub fn contains(&self, kind: BlockKind, key: &(usize, usize)) -> bool {
+        self.cache.contains_key(&self.key(kind, *key))
+    }
+
+    /// Cache a data block.
+    pub fn insert(&self, key: (usize, usize), block: Arc<Block>) {
+        self.insert_with_priority(key, block, CachePriority::Low);
+    }
+
+    pub fn insert_with_priority(
+        &self,
+        key: (usize, usize),
+        block: Arc<Block>,
+        priority: CachePriority,
+    ) {
+        self.cache.insert(
+            self.key(BlockKind::Data, key),
+            CacheValue::Block(block),
+            priority,
+        );
+    }
+
+    /// Get a cached data block, or read it with `init` and cache it with the given priority. Only
+    /// one thread reads a missing block, while the others wait for it.
+    pub fn try_get_with(
+        &self,
+        key: (usize, usize),
+        priority: CachePriority,
+        init: impl FnOnce() -> Result<Arc<Block>>,
+    ) -> Result<Arc<Block>> {
+        self.try_get_block_with(BlockKind::Data, key, priority, init)
+    }
+
+    /// Get a cached index partition, or read it with `init` and cache it with high priority.
+    pub fn try_get_index_partition_with(
+        &self,
+        key: (usize, usize),
+        init: impl FnOnce() -> Result<Arc<Block>>,
+    ) -> Result<Arc<Block>> {
+        self.try_get_block_with(BlockKind::IndexPartition, key, CachePriority::High, init)
+    }
+
+    fn try_get_block_with(
+        &self,
+        kind: BlockKind,
+        key: (usize, usize),
+        priority: CachePriority,
+        init: impl FnOnce() -> Result<Arc<Block>>,
+    ) -> Result<Arc<Block>> {
+        let value = self.cache.try_get_with(self.key(kind, key), priority, || {
+            Ok(CacheValue::Block(init()?))
+        })?;
+        match value {
+            CacheValue::Block(block) => Ok(block),
+            CacheValue::Filter(_) => unreachable!("filter cached as a {:?} block", kind),
+        }
+    }
+
+    /// Get a cached filter partition, or read it with `init` and cache it with high priority.
+    pub(crate) fn try_get_filter_partition_with(
+        &self,
+        key: (usize, usize),
+        init: impl FnOnce() -> Result<Arc<Bloom>>,
+    ) -> Result<Arc<Bloom>> {
+        let value = self.cache.try_get_with(
+            self.key(BlockKind::FilterPartition, key),
+            CachePriority::High,
+            || Ok(CacheValue::Filter(init()?)),
+        )?;
+        match value {
+            CacheValue::Filter(bloom) => Ok(bloom),
+            CacheValue::Block(_) => unreachable!("block cached as a filter partition"),
+        }
+    }
+
+    /// The cache read by this block cache, which may be shared.
+    pub fn cache(&self) -> &Arc<ShardedBlockCache> {
+        &self.cache
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        self.cache.stats()
+    }
+}
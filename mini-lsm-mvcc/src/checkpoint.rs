@@ -0,0 +1,94 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use parking_lot::MutexGuard;
+
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::manifest::{Manifest, ManifestRecord};
+
+/// Link `src` to `dst`, or copy it if they are on different file systems.
+fn link_or_copy(src: &Path, dst: &Path) -> Result<()> {
+    if std::fs::hard_link(src, dst).is_err() {
+        std::fs::copy(src, dst)
+            .with_context(|| format!("failed to copy {} to checkpoint", src.display()))?;
+    }
+    Ok(())
+}
+
+impl LsmStorageInner {
+    /// Create a checkpoint of the database in `dir`, which must not exist. Writes are blocked while
+    /// the memtables are flushed, after which the live SSTs and value logs are hard-linked into
+    /// `dir` and a compacted manifest with only the current LSM structure is written.
+    pub fn create_checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        assert_eq!(self.column_family_id, DEFAULT_COLUMN_FAMILY_ID);
+        self.check_writable()?;
+        let dir = dir.as_ref();
+        if dir.exists() {
+            bail!("checkpoint directory {} already exists", dir.display());
+        }
+
+        let _lck = self.mvcc().write_lock.lock();
+        self.flush_all_memtables()?;
+
+        std::fs::create_dir_all(dir).context("failed to create checkpoint dir")?;
+        let manifest = Manifest::create(dir)?;
+        // Hold the state locks so that compaction cannot remove the files being linked.
+        let state_lock = self.state_lock.lock();
+        self.checkpoint_state(&state_lock, dir, &manifest)?;
+        let column_families = self.column_families.read();
+        for column_family in column_families.values() {
+            let inner = &column_family.inner;
+            manifest.add_record_when_init(ManifestRecord::CreateColumnFamily {
+                id: column_family.id,
+                name: column_family.name.clone(),
+                compaction_options: inner.options.compaction_options.clone(),
+            })?;
+            let state_lock = inner.state_lock.lock();
+            inner.checkpoint_state(
+                &state_lock,
+                dir,
+                &manifest.for_column_family(column_family.id),
+            )?;
+        }
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Link the SSTs of this column family into `dir` and record its LSM structure.
+    fn checkpoint_state(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        dir: &Path,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        let snapshot = self.state.read().clone();
+        assert!(snapshot.imm_memtables.is_empty(), "memtables not flushed?");
+        let LsmStorageState {
+            l0_sstables,
+            levels,
+            sstables,
+            ..
+        } = snapshot.as_ref();
+        let mut value_logs = BTreeSet::new();
+        for sst_id in l0_sstables
+            .iter()
+            .chain(levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            link_or_copy(
+                &self.path_of_sst(*sst_id),
+                &Self::path_of_sst_static(dir, *sst_id),
+            )?;
+            value_logs.extend(sstables[sst_id].value_log_refs().keys().copied());
+        }
+        for value_log in value_logs {
+            link_or_copy(
+                &self.path_of_vlog(value_log),
+                &Self::path_of_vlog_static(dir, value_log),
+            )?;
+        }
+        manifest.add_record_when_init(self.snapshot_record(&snapshot))
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use crate::lsm_storage::LsmStorageInner;
+
+/// The id of the column family that always exists and owns the WAL.
+pub const DEFAULT_COLUMN_FAMILY_ID: usize = 0;
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// A handle to a column family, which is a separate LSM tree with its own memtables, SSTs and
+/// compaction strategy. All column families of a storage share the same WAL, manifest and
+/// timestamps, so that a write batch spanning several column families is atomic.
+#[derive(Clone)]
+pub struct ColumnFamily {
+    pub(crate) id: usize,
+    pub(crate) name: String,
+    pub(crate) inner: Arc<LsmStorageInner>,
+}
+
+impl ColumnFamily {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -2,26 +2,31 @@ mod leveled;
 mod simple_leveled;
 mod tiered;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
 
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
+use crate::key::{self, KeySlice};
 use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
 use crate::manifest::ManifestRecord;
+use crate::range_tombstone::{RangeTombstone, RangeTombstones};
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::value_log::{ValueLogFile, ValuePointer, VALUE_TAG_POINTER};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum CompactionTask {
@@ -43,6 +48,36 @@ impl CompactionTask {
             CompactionTask::Tiered(task) => task.bottom_tier_included,
         }
     }
+
+    pub(crate) fn input_sst_ids(&self) -> Box<dyn Iterator<Item = &usize> + '_> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => Box::new(l0_sstables.iter().chain(l1_sstables)),
+            CompactionTask::Leveled(task) => Box::new(
+                task.upper_level_sst_ids
+                    .iter()
+                    .chain(&task.lower_level_sst_ids),
+            ),
+            CompactionTask::Simple(task) => Box::new(
+                task.upper_level_sst_ids
+                    .iter()
+                    .chain(&task.lower_level_sst_ids),
+            ),
+            CompactionTask::Tiered(task) => Box::new(task.tiers.iter().flat_map(|(_, ssts)| ssts)),
+        }
+    }
+
+    /// The SSTs moved to the lower level as-is if the task is a trivial move.
+    pub(crate) fn trivially_moved_ssts(&self) -> Option<&[usize]> {
+        match self {
+            CompactionTask::Leveled(task) if task.is_trivial_move => {
+                Some(&task.upper_level_sst_ids)
+            }
+            _ => None,
+        }
+    }
 }
 
 pub(crate) enum CompactionController {
@@ -68,6 +103,16 @@ impl CompactionController {
         }
     }
 
+    /// Estimate the bytes that compaction has to rewrite to catch up with the LSM tree.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl.pending_compaction_bytes(snapshot),
+            CompactionController::Simple(ctrl) => ctrl.pending_compaction_bytes(snapshot),
+            CompactionController::Tiered(ctrl) => ctrl.pending_compaction_bytes(snapshot),
+            CompactionController::NoCompaction => 0,
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -99,7 +144,7 @@ impl CompactionController {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionOptions {
     /// Leveled compaction with partial compaction + dynamic level support (= RocksDB's Leveled
     /// Compaction)
@@ -112,11 +157,176 @@ pub enum CompactionOptions {
     NoCompaction,
 }
 
+/// Collect the value log handles held by the given SSTs.
+fn collect_value_logs<'a>(
+    sstables: impl IntoIterator<Item = &'a Arc<SsTable>>,
+) -> HashMap<usize, Arc<ValueLogFile>> {
+    let mut value_logs = HashMap::new();
+    for sst in sstables {
+        for (id, value_log) in sst.value_logs() {
+            value_logs.insert(*id, value_log.clone());
+        }
+    }
+    value_logs
+}
+
+/// Value logs referred to by the `removed` SSTs but not by any SST in `state`. They can be deleted
+/// once `state` is installed.
+fn unreferenced_value_logs(state: &LsmStorageState, removed: &[Arc<SsTable>]) -> Vec<usize> {
+    let mut value_logs = removed
+        .iter()
+        .flat_map(|sst| sst.value_log_refs().keys().copied())
+        .collect::<HashSet<_>>();
+    for sst in state.sstables.values() {
+        for id in sst.value_log_refs().keys() {
+            value_logs.remove(id);
+        }
+    }
+    value_logs.into_iter().collect()
+}
+
+/// Create an iterator over an SST for compaction, starting at the first version of `lower`.
+fn open_sst_for_compaction(
+    sst: Arc<SsTable>,
+    lower: Option<&[u8]>,
+    tagged: bool,
+) -> Result<SsTableIterator> {
+    match lower {
+        Some(key) => SsTableIterator::create_and_seek_to_key_for_compaction(
+            sst,
+            KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+            tagged,
+        ),
+        None => SsTableIterator::create_and_seek_to_first_for_compaction(sst, tagged),
+    }
+}
+
+/// Create an iterator over a sorted run for compaction, starting at the first version of `lower`.
+fn open_ssts_for_compaction(
+    ssts: Vec<Arc<SsTable>>,
+    lower: Option<&[u8]>,
+    tagged: bool,
+) -> Result<SstConcatIterator> {
+    match lower {
+        Some(key) => SstConcatIterator::create_and_seek_to_key_for_compaction(
+            ssts,
+            KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+            tagged,
+        ),
+        None => SstConcatIterator::create_and_seek_to_first_for_compaction(ssts, tagged),
+    }
+}
+
+/// Range tombstones used by a compaction task.
+struct CompactionRangeTombstones {
+    /// Tombstones below the watermark in all SSTs. Versions below the watermark covered by them
+    /// are dropped.
+    covering: RangeTombstones,
+    /// Tombstones of the input SSTs that are written to the first output SST.
+    retained: Vec<RangeTombstone>,
+}
+
+impl CompactionRangeTombstones {
+    /// A tombstone of the input SSTs is dropped when compacting to the bottom level if it is below
+    /// the watermark and no SST outside of the task overlaps with it, as all versions it covers
+    /// are dropped by the task.
+    fn new(
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        watermark: u64,
+    ) -> Result<CompactionRangeTombstones> {
+        let mut covering = Vec::new();
+        for sst in snapshot.sstables.values() {
+            if sst.tombstone_range().is_some() {
+                covering.push(sst.fragmented_range_tombstones()?);
+            }
+        }
+        let input_ids = task.input_sst_ids().copied().collect::<HashSet<_>>();
+        let other_ssts = snapshot
+            .sstables
+            .values()
+            .filter(|sst| !input_ids.contains(&sst.sst_id()) && sst.has_keys())
+            .collect::<Vec<_>>();
+        let mut retained = Vec::new();
+        for id in &input_ids {
+            retained.extend(snapshot.sstables[id].read_range_tombstones()?);
+        }
+        retained.retain(|tombstone| {
+            !task.compact_to_bottom_level()
+                || tombstone.ts > watermark
+                || other_ssts.iter().any(|sst| {
+                    tombstone.overlaps(sst.first_key().key_ref(), sst.last_key().key_ref())
+                })
+        });
+        Ok(CompactionRangeTombstones {
+            covering: RangeTombstones::new(watermark, covering),
+            retained,
+        })
+    }
+}
+
+/// Replace `l0_sstables` and `l1_sstables` with the `output` of a full compaction, which runs
+/// without a compaction controller.
+pub(crate) fn apply_full_compaction_result(
+    state: &mut LsmStorageState,
+    l0_sstables: &[usize],
+    l1_sstables: &[usize],
+    output: &[usize],
+) {
+    assert_eq!(l1_sstables, state.levels[0].1);
+    state.levels[0].1 = output.to_vec();
+    let mut l0_sstables_map = l0_sstables.iter().copied().collect::<HashSet<_>>();
+    state.l0_sstables.retain(|x| !l0_sstables_map.remove(x));
+    assert!(l0_sstables_map.is_empty());
+}
+
+/// Value logs with at least this ratio of garbage are rewritten by the compaction thread.
+const VALUE_LOG_GC_DISCARD_RATIO: f64 = 0.5;
+
 impl LsmStorageInner {
+    fn build_compacted_sst(
+        &self,
+        builder: SsTableBuilder,
+        value_logs: &HashMap<usize, Arc<ValueLogFile>>,
+    ) -> Result<Arc<SsTable>> {
+        let sst_id = self.next_sst_id();
+        let mut sst = builder.build(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(sst_id),
+        )?;
+        sst.attach_value_logs(value_logs)?;
+        self.cache_new_sst(sst)
+    }
+
+    /// Create a builder for a compaction output SST, with the range tombstones taken from
+    /// `range_tombstones`.
+    fn new_compaction_sst_builder(
+        &self,
+        tagged: bool,
+        range_tombstones: &mut Vec<RangeTombstone>,
+    ) -> SsTableBuilder {
+        let mut builder = self.new_sst_builder();
+        if tagged {
+            builder = builder.with_tagged_values();
+        }
+        for tombstone in range_tombstones.drain(..) {
+            builder.add_range_tombstone(tombstone);
+        }
+        builder
+    }
+
+    /// If any value log exists, `iter` yields values in the tagged format, and they are copied
+    /// as-is so that values in value logs are not read again. Keys at or after `upper` are not
+    /// compacted. The `retained` range tombstones are written to the first output SST.
     fn compact_generate_sst_from_iter(
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+        upper: Option<&[u8]>,
         compact_to_bottom_level: bool,
+        value_logs: &HashMap<usize, Arc<ValueLogFile>>,
+        covering: &RangeTombstones,
+        retained: &[RangeTombstone],
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
         let mut new_sst = Vec::new();
@@ -124,9 +334,16 @@ impl LsmStorageInner {
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
+        let tagged = !value_logs.is_empty();
+        let mut retained = retained.to_vec();
         'outer: while iter.is_valid() {
+            if let Some(upper) = upper {
+                if iter.key().key_ref() >= upper {
+                    break;
+                }
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_compaction_sst_builder(tagged, &mut retained));
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -139,6 +356,8 @@ impl LsmStorageInner {
                 && iter.key().ts() <= watermark
                 && iter.value().is_empty()
             {
+                // Values that fail to be read from a value log are empty as well.
+                iter.status()?;
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
                 iter.next()?;
@@ -154,6 +373,13 @@ impl LsmStorageInner {
 
                 first_key_below_watermark = false;
 
+                if covering.covers(iter.key().key_ref(), iter.key().ts()) {
+                    last_key.clear();
+                    last_key.extend(iter.key().key_ref());
+                    iter.next()?;
+                    continue;
+                }
+
                 if !compaction_filters.is_empty() {
                     for filter in &compaction_filters {
                         match filter {
@@ -171,19 +397,19 @@ impl LsmStorageInner {
             let builder_inner = builder.as_mut().unwrap();
 
             if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
-                let sst_id = self.next_sst_id();
                 let old_builder = builder.take().unwrap();
-                let sst = Arc::new(old_builder.build(
-                    sst_id,
-                    Some(self.block_cache.clone()),
-                    self.path_of_sst(sst_id),
-                )?);
-                new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                new_sst.push(self.build_compacted_sst(old_builder, value_logs)?);
+                builder = Some(self.new_compaction_sst_builder(tagged, &mut retained));
             }
 
             let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            if tagged {
+                builder_inner.add_raw(iter.key(), iter.value());
+            } else {
+                let value = iter.value();
+                iter.status()?;
+                builder_inner.add(iter.key(), value);
+            }
 
             if !same_as_last_key {
                 last_key.clear();
@@ -192,43 +418,141 @@ impl LsmStorageInner {
 
             iter.next()?;
         }
+        if builder.is_none() && !retained.is_empty() {
+            builder = Some(self.new_compaction_sst_builder(tagged, &mut retained));
+        }
         if let Some(builder) = builder {
-            let sst_id = self.next_sst_id(); // lock dropped here
-            let sst = Arc::new(builder.build(
-                sst_id,
-                Some(self.block_cache.clone()),
-                self.path_of_sst(sst_id),
-            )?);
-            new_sst.push(sst);
+            if !builder.is_empty() {
+                new_sst.push(self.build_compacted_sst(builder, value_logs)?);
+            }
         }
         Ok(new_sst)
     }
 
+    /// Pick user keys splitting a large compaction task into subcompactions of similar sizes,
+    /// from the split keys of the input SSTs. All versions of a key are in the same
+    /// subcompaction.
+    fn subcompaction_boundaries(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Result<Vec<Bytes>> {
+        if self.subcompaction_pool.is_none() {
+            return Ok(Vec::new());
+        }
+        let total_size = snapshot.sst_bytes(&task.input_sst_ids().copied().collect::<Vec<_>>());
+        let num_subcompactions = self
+            .options
+            .max_subcompactions
+            .min((total_size / self.options.target_sst_size as u64) as usize);
+        if num_subcompactions <= 1 {
+            return Ok(Vec::new());
+        }
+        let ssts = task
+            .input_sst_ids()
+            .map(|id| snapshot.sstables[id].open_table())
+            .collect::<Result<Vec<_>>>()?;
+        let mut keys = ssts
+            .iter()
+            .flat_map(|sst| sst.split_keys())
+            .map(|key| key.key_ref())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        // SSTs holding only range tombstones have no data blocks to split at.
+        if keys.len() < 2 {
+            return Ok(Vec::new());
+        }
+        let mut boundaries = (1..num_subcompactions)
+            .map(|i| keys[i * keys.len() / num_subcompactions])
+            .filter(|key| *key > keys[0])
+            .collect::<Vec<_>>();
+        boundaries.dedup();
+        Ok(boundaries.into_iter().map(Bytes::copy_from_slice).collect())
+    }
+
     fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
+        let value_logs = collect_value_logs(snapshot.sstables.values());
+        let range_tombstones =
+            CompactionRangeTombstones::new(&snapshot, task, self.mvcc().watermark())?;
+        let boundaries = self.subcompaction_boundaries(&snapshot, task)?;
+        if boundaries.is_empty() {
+            return self.compact_range(&snapshot, task, None, None, &value_logs, &range_tombstones);
+        }
+
+        let mut ranges = Vec::with_capacity(boundaries.len() + 1);
+        let mut lower = None;
+        for boundary in &boundaries {
+            ranges.push((lower, Some(&boundary[..])));
+            lower = Some(&boundary[..]);
+        }
+        ranges.push((lower, None));
+        println!("running {} subcompactions", ranges.len());
+        let outputs = self.subcompaction_pool.as_ref().unwrap().install(|| {
+            ranges
+                .par_iter()
+                .map(|(lower, upper)| {
+                    self.compact_range(
+                        &snapshot,
+                        task,
+                        *lower,
+                        *upper,
+                        &value_logs,
+                        &range_tombstones,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        Ok(outputs.into_iter().flatten().collect())
+    }
+
+    /// Compact the keys of the task in `[lower, upper)`. The retained range tombstones are
+    /// written by the first range.
+    fn compact_range(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+        value_logs: &HashMap<usize, Arc<ValueLogFile>>,
+        range_tombstones: &CompactionRangeTombstones,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let tagged = !value_logs.is_empty();
+        let covering = &range_tombstones.covering;
+        let retained = match lower {
+            Some(_) => &[][..],
+            None => &range_tombstones.retained[..],
+        };
+        let ssts = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>()
+        };
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
                 l1_sstables,
             } => {
                 let mut l0_iters = Vec::with_capacity(l0_sstables.len());
-                for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
-                }
-                let mut l1_iters = Vec::with_capacity(l1_sstables.len());
-                for id in l1_sstables.iter() {
-                    l1_iters.push(snapshot.sstables.get(id).unwrap().clone());
+                for sst in ssts(l0_sstables) {
+                    l0_iters.push(Box::new(open_sst_for_compaction(sst, lower, tagged)?));
                 }
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    open_ssts_for_compaction(ssts(l1_sstables), lower, tagged)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    upper,
+                    task.compact_to_bottom_level(),
+                    value_logs,
+                    covering,
+                    retained,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -245,52 +569,53 @@ impl LsmStorageInner {
                 ..
             }) => match upper_level {
                 Some(_) => {
-                    let mut upper_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
-                    let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let upper_iter =
+                        open_ssts_for_compaction(ssts(upper_level_sst_ids), lower, tagged)?;
+                    let lower_iter =
+                        open_ssts_for_compaction(ssts(lower_level_sst_ids), lower, tagged)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
+                        upper,
                         task.compact_to_bottom_level(),
+                        value_logs,
+                        covering,
+                        retained,
                     )
                 }
                 None => {
                     let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
+                    for sst in ssts(upper_level_sst_ids) {
+                        upper_iters.push(Box::new(open_sst_for_compaction(sst, lower, tagged)?));
                     }
                     let upper_iter = MergeIterator::create(upper_iters);
-                    let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter =
+                        open_ssts_for_compaction(ssts(lower_level_sst_ids), lower, tagged)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
+                        upper,
                         task.compact_to_bottom_level(),
+                        value_logs,
+                        covering,
+                        retained,
                     )
                 }
             },
             CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
                 let mut iters = Vec::with_capacity(tiers.len());
                 for (_, tier_sst_ids) in tiers {
-                    let mut ssts = Vec::with_capacity(tier_sst_ids.len());
-                    for id in tier_sst_ids.iter() {
-                        ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+                    iters.push(Box::new(open_ssts_for_compaction(
+                        ssts(tier_sst_ids),
+                        lower,
+                        tagged,
+                    )?));
                 }
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
+                    upper,
                     task.compact_to_bottom_level(),
+                    value_logs,
+                    covering,
+                    retained,
                 )
             }
         }
@@ -300,6 +625,8 @@ impl LsmStorageInner {
         let CompactionOptions::NoCompaction = self.options.compaction_options else {
             panic!("full compaction can only be called with compaction is not enabled")
         };
+        self.check_writable()?;
+        let _compaction_lock = self.compaction_lock.lock();
 
         let snapshot = {
             let state = self.state.read();
@@ -316,47 +643,47 @@ impl LsmStorageInner {
         println!("force full compaction: {:?}", compaction_task);
 
         let sstables = self.compact(&compaction_task)?;
+        let sst_metas = sstables.iter().map(|sst| sst.meta()).collect();
         let mut ids = Vec::with_capacity(sstables.len());
 
-        {
+        let (ssts_to_remove, value_logs_to_remove) = {
             let state_lock = self.state_lock.lock();
             let mut state = self.state.read().as_ref().clone();
+            let mut ssts_to_remove = Vec::new();
             for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
                 let result = state.sstables.remove(sst);
                 assert!(result.is_some());
+                ssts_to_remove.push(result.unwrap());
             }
             for new_sst in sstables {
                 ids.push(new_sst.sst_id());
                 let result = state.sstables.insert(new_sst.sst_id(), new_sst);
                 assert!(result.is_none());
             }
-            assert_eq!(l1_sstables, state.levels[0].1);
-            state.levels[0].1.clone_from(&ids);
-            let mut l0_sstables_map = l0_sstables.iter().copied().collect::<HashSet<_>>();
-            state.l0_sstables = state
-                .l0_sstables
-                .iter()
-                .filter(|x| !l0_sstables_map.remove(x))
-                .copied()
-                .collect::<Vec<_>>();
-            assert!(l0_sstables_map.is_empty());
+            apply_full_compaction_result(&mut state, &l0_sstables, &l1_sstables, &ids);
+            let value_logs_to_remove = unreferenced_value_logs(&state, &ssts_to_remove);
             *self.state.write() = Arc::new(state);
+            self.write_stall_signal.notify();
             self.sync_dir()?;
-            self.manifest.as_ref().unwrap().add_record(
+            self.manifest.as_ref().unwrap().add_records(
                 &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
+                vec![
+                    ManifestRecord::NewSsts(sst_metas),
+                    ManifestRecord::Compaction(compaction_task, ids.clone()),
+                ],
             )?;
-        }
-        for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-            std::fs::remove_file(self.path_of_sst(*sst))?;
-        }
+            (ssts_to_remove, value_logs_to_remove)
+        };
+        drop(snapshot);
+        self.remove_obsolete_files(ssts_to_remove, &value_logs_to_remove)?;
 
         println!("force full compaction done, new SSTs: {:?}", ids);
 
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.lock();
         let snapshot = {
             let state = self.state.read();
             state.clone()
@@ -368,10 +695,14 @@ impl LsmStorageInner {
             return Ok(());
         };
         self.dump_structure();
+        if task.trivially_moved_ssts().is_some() {
+            return self.trivial_move(task);
+        }
         println!("running compaction task: {:?}", task);
         let sstables = self.compact(&task)?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
-        let ssts_to_remove = {
+        let sst_metas = sstables.iter().map(|sst| sst.meta()).collect();
+        let (ssts_to_remove, value_logs_to_remove) = {
             let state_lock = self.state_lock.lock();
             let mut snapshot = self.state.read().as_ref().clone();
             let mut new_sst_ids = Vec::new();
@@ -390,13 +721,20 @@ impl LsmStorageInner {
                 assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
                 ssts_to_remove.push(result.unwrap());
             }
+            let value_logs_to_remove = unreferenced_value_logs(&snapshot, &ssts_to_remove);
             let mut state = self.state.write();
             *state = Arc::new(snapshot);
             drop(state);
+            self.write_stall_signal.notify();
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
-            ssts_to_remove
+            self.manifest().add_records(
+                &state_lock,
+                vec![
+                    ManifestRecord::NewSsts(sst_metas),
+                    ManifestRecord::Compaction(task, new_sst_ids),
+                ],
+            )?;
+            (ssts_to_remove, value_logs_to_remove)
         };
         println!(
             "compaction finished: {} files removed, {} files added, output={:?}",
@@ -404,10 +742,147 @@ impl LsmStorageInner {
             output.len(),
             output
         );
-        for sst in ssts_to_remove {
-            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+        drop(snapshot);
+        self.remove_obsolete_files(ssts_to_remove, &value_logs_to_remove)?;
+
+        Ok(())
+    }
+
+    /// Move SSTs to the lower level by only updating the LSM structure and the manifest.
+    fn trivial_move(&self, task: CompactionTask) -> Result<()> {
+        println!("running trivial move: {:?}", task);
+        let moved = task.trivially_moved_ssts().unwrap().to_vec();
+        let state_lock = self.state_lock.lock();
+        let snapshot = self.state.read().as_ref().clone();
+        let (snapshot, _) = self
+            .compaction_controller
+            .apply_compaction_result(&snapshot, &task, &moved, false);
+        *self.state.write() = Arc::new(snapshot);
+        self.write_stall_signal.notify();
+        self.manifest()
+            .add_record(&state_lock, ManifestRecord::TrivialMove(task))?;
+        println!("trivial move finished: {:?}", moved);
+        Ok(())
+    }
+
+    /// Copy an SST, moving the values stored in the `victims` value logs into a new value log.
+    fn rewrite_sst_for_value_log_gc(
+        &self,
+        sst: &Arc<SsTable>,
+        victims: &HashSet<usize>,
+    ) -> Result<Arc<SsTable>> {
+        let sst_id = self.next_sst_id();
+        let mut builder = self
+            .new_sst_builder_with_value_log(sst_id)
+            .with_tagged_values();
+        for tombstone in sst.read_range_tombstones()? {
+            builder.add_range_tombstone(tombstone);
+        }
+        let mut iter = SsTableIterator::create_and_seek_to_first_for_compaction(sst.clone(), true)?;
+        while iter.is_valid() {
+            let value = iter.value();
+            if value.first() == Some(&VALUE_TAG_POINTER) {
+                let ptr = ValuePointer::decode(value)?;
+                if victims.contains(&ptr.file_id) {
+                    builder.add(iter.key(), &sst.read_value(&ptr)?);
+                    iter.next()?;
+                    continue;
+                }
+            }
+            builder.add_raw(iter.key(), value);
+            iter.next()?;
+        }
+        let mut new_sst = builder.build(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(sst_id),
+        )?;
+        new_sst.attach_value_logs(sst.value_logs())?;
+        self.cache_new_sst(new_sst)
+    }
+
+    pub fn force_value_log_gc(&self, discard_ratio: f64) -> Result<()> {
+        self.check_writable()?;
+        let _compaction_lock = self.compaction_lock.lock();
+        let snapshot = {
+            let state = self.state.read();
+            state.clone()
+        };
+
+        let mut live_bytes = HashMap::<usize, u64>::new();
+        for sst in snapshot.sstables.values() {
+            for (id, bytes) in sst.value_log_refs() {
+                *live_bytes.entry(*id).or_default() += bytes;
+            }
+        }
+        let victims = collect_value_logs(snapshot.sstables.values())
+            .values()
+            .filter(|value_log| {
+                let size = value_log.size();
+                let garbage = size.saturating_sub(live_bytes[&value_log.id()]);
+                size > 0 && garbage as f64 >= discard_ratio * size as f64
+            })
+            .map(|value_log| value_log.id())
+            .collect::<HashSet<_>>();
+        if victims.is_empty() {
+            return Ok(());
+        }
+
+        let mut input_ssts = snapshot
+            .sstables
+            .values()
+            .filter(|sst| sst.value_log_refs().keys().any(|id| victims.contains(id)))
+            .map(|sst| sst.sst_id())
+            .collect::<Vec<_>>();
+        input_ssts.sort();
+        println!(
+            "running value log gc: value_logs={:?}, ssts={:?}",
+            victims, input_ssts
+        );
+        let mut sstables = Vec::with_capacity(input_ssts.len());
+        for id in &input_ssts {
+            sstables.push(self.rewrite_sst_for_value_log_gc(&snapshot.sstables[id], &victims)?);
         }
-        self.sync_dir()?;
+        let output_ssts = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
+        let sst_metas = sstables.iter().map(|sst| sst.meta()).collect();
+
+        let (ssts_to_remove, value_logs_to_remove) = {
+            let state_lock = self.state_lock.lock();
+            let mut state = self.state.read().as_ref().clone();
+            let mut ssts_to_remove = Vec::with_capacity(input_ssts.len());
+            for id in &input_ssts {
+                let result = state.sstables.remove(id);
+                assert!(result.is_some(), "cannot remove {}.sst", id);
+                ssts_to_remove.push(result.unwrap());
+            }
+            for sst in sstables {
+                let result = state.sstables.insert(sst.sst_id(), sst);
+                assert!(result.is_none());
+            }
+            state.replace_sstables(&input_ssts, &output_ssts);
+            let value_logs_to_remove = unreferenced_value_logs(&state, &ssts_to_remove);
+            *self.state.write() = Arc::new(state);
+            self.write_stall_signal.notify();
+            self.sync_dir()?;
+            self.manifest().add_records(
+                &state_lock,
+                vec![
+                    ManifestRecord::NewSsts(sst_metas),
+                    ManifestRecord::ValueLogGc {
+                        input_ssts,
+                        output_ssts: output_ssts.clone(),
+                    },
+                ],
+            )?;
+            (ssts_to_remove, value_logs_to_remove)
+        };
+        println!(
+            "value log gc finished: {} value logs removed, output={:?}",
+            value_logs_to_remove.len(),
+            output_ssts
+        );
+        drop(snapshot);
+        self.remove_obsolete_files(ssts_to_remove, &value_logs_to_remove)?;
 
         Ok(())
     }
@@ -425,8 +900,17 @@ impl LsmStorageInner {
                 let ticker = crossbeam_channel::tick(Duration::from_millis(50));
                 loop {
                     crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
+                        recv(ticker) -> _ => {
+                            if this.background_error().is_some() {
+                                continue;
+                            }
+                            if let Err(e) = this.trigger_compaction() {
+                                this.set_background_error("compaction", e);
+                            } else if this.options.value_separation_threshold.is_some() {
+                                if let Err(e) = this.force_value_log_gc(VALUE_LOG_GC_DISCARD_RATIO) {
+                                    this.set_background_error("value log gc", e);
+                                }
+                            }
                         },
                         recv(rx) -> _ => return
                     }
@@ -437,6 +921,15 @@ impl LsmStorageInner {
         Ok(None)
     }
 
+    /// Delete the SSTs and value logs replaced by a compaction once no reader holds them.
+    fn remove_obsolete_files(&self, ssts: Vec<Arc<SsTable>>, value_logs: &[usize]) -> Result<()> {
+        self.obsolete_files.add(ssts, value_logs)?;
+        if self.obsolete_files.purge()? > 0 {
+            self.sync_dir()?;
+        }
+        Ok(())
+    }
+
     fn trigger_flush(&self) -> Result<()> {
         let res = {
             let state = self.state.read();
@@ -445,6 +938,11 @@ impl LsmStorageInner {
         if res {
             self.force_flush_next_imm_memtable()?;
         }
+        if self.obsolete_files.purge()? > 0 {
+            self.sync_dir()?;
+        }
+        self.maybe_rotate_manifest()?;
+        self.sync_wal_on_interval()?;
 
         Ok(())
     }
@@ -458,8 +956,10 @@ impl LsmStorageInner {
             let ticker = crossbeam_channel::tick(Duration::from_millis(50));
             loop {
                 crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("flush failed: {}", e);
+                    recv(ticker) -> _ => if this.background_error().is_none() {
+                        if let Err(e) = this.trigger_flush() {
+                            this.set_background_error("flush", e);
+                        }
                     },
                     recv(rx) -> _ => return
                 }
@@ -467,4 +967,52 @@ impl LsmStorageInner {
         });
         Ok(Some(handle))
     }
+
+    /// The error that stopped background work and writes, if any.
+    pub fn background_error(&self) -> Option<String> {
+        self.background_error.lock().clone()
+    }
+
+    /// Stop background work and writes after `task` failed. Only the first error is kept, as the
+    /// later ones are likely caused by it.
+    pub(crate) fn set_background_error(&self, task: &str, e: anyhow::Error) {
+        eprintln!("{} failed: {:#}", task, e);
+        let mut background_error = self.background_error.lock();
+        if background_error.is_none() {
+            *background_error = Some(format!("{} failed: {:#}", task, e));
+        }
+        drop(background_error);
+        // Blocked writes fail instead of waiting for background work that is stopped.
+        self.write_stall_signal.notify();
+    }
+
+    /// Clear the background error and retry the flushes and compactions of all column families.
+    /// The error is set again if they still fail.
+    pub fn resume(&self) -> Result<()> {
+        assert_eq!(self.column_family_id, DEFAULT_COLUMN_FAMILY_ID);
+        if self.background_error.lock().take().is_none() {
+            return Ok(());
+        }
+        let column_families = self.column_families.read();
+        let storages = std::iter::once(self).chain(
+            column_families
+                .values()
+                .map(|column_family| column_family.inner.as_ref()),
+        );
+        for storage in storages {
+            let result =
+                storage
+                    .trigger_flush()
+                    .and_then(|_| match storage.options.compaction_options {
+                        CompactionOptions::NoCompaction => Ok(()),
+                        _ => storage.trigger_compaction(),
+                    });
+            if let Err(e) = result {
+                let error = format!("{:#}", e);
+                storage.set_background_error("resume", e);
+                bail!("failed to resume: {}", error);
+            }
+        }
+        Ok(())
+    }
 }
@@ -12,9 +12,13 @@ pub struct LeveledCompactionTask {
     pub lower_level: usize,
     pub lower_level_sst_ids: Vec<usize>,
     pub is_lower_level_bottom_level: bool,
+    /// The upper level SSTs overlap neither each other nor the lower level, so they are moved to
+    /// the lower level without being rewritten.
+    #[serde(default)]
+    pub is_trivial_move: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionOptions {
     pub level_size_multiplier: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -61,11 +65,20 @@ impl LeveledCompactionController {
         overlap_ssts
     }
 
-    pub fn generate_compaction_task(
-        &self,
-        snapshot: &LsmStorageState,
-    ) -> Option<LeveledCompactionTask> {
-        // step 1: compute target level size
+    /// Whether the SSTs can be moved into a sorted level as-is, i.e., no two of them overlap.
+    fn non_overlapping(snapshot: &LsmStorageState, sst_ids: &[usize]) -> bool {
+        let mut ssts = sst_ids
+            .iter()
+            .map(|id| &snapshot.sstables[id])
+            .collect::<Vec<_>>();
+        ssts.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+        ssts.windows(2)
+            .all(|pair| pair[0].last_key().key_ref() < pair[1].first_key().key_ref())
+    }
+
+    /// Compute the target and real size in bytes of each level except L0, and the base level that
+    /// L0 is compacted into.
+    fn level_sizes(&self, snapshot: &LsmStorageState) -> (Vec<usize>, Vec<usize>, usize) {
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
         let mut real_level_size = Vec::with_capacity(self.options.max_levels);
         let mut base_level = self.options.max_levels;
@@ -93,19 +106,42 @@ impl LeveledCompactionController {
                 base_level = i + 1;
             }
         }
+        (target_level_size, real_level_size, base_level)
+    }
+
+    /// Estimate the bytes to compact to bring L0 below the compaction trigger and every level
+    /// below its target size.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let (target_level_size, real_level_size, _) = self.level_sizes(snapshot);
+        let mut pending = 0;
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            pending += snapshot.sst_bytes(&snapshot.l0_sstables);
+        }
+        for (target, real) in target_level_size.iter().zip(&real_level_size) {
+            pending += real.saturating_sub(*target) as u64;
+        }
+        pending
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        // step 1: compute target level size
+        let (target_level_size, real_level_size, base_level) = self.level_sizes(snapshot);
 
         // Flush L0 SST is the top priority
         if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
             println!("flush L0 SST to base level {}", base_level);
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, base_level);
             return Some(LeveledCompactionTask {
                 upper_level: None,
                 upper_level_sst_ids: snapshot.l0_sstables.clone(),
                 lower_level: base_level,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &snapshot.l0_sstables,
-                    base_level,
-                ),
+                is_trivial_move: lower_level_sst_ids.is_empty()
+                    && Self::non_overlapping(snapshot, &snapshot.l0_sstables),
+                lower_level_sst_ids,
                 is_lower_level_bottom_level: base_level == self.options.max_levels,
             });
         }
@@ -140,15 +176,14 @@ impl LeveledCompactionController {
                 "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
                 priorities
             );
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
             return Some(LeveledCompactionTask {
                 upper_level: Some(level),
                 upper_level_sst_ids: vec![selected_sst],
                 lower_level: level + 1,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &[selected_sst],
-                    level + 1,
-                ),
+                is_trivial_move: lower_level_sst_ids.is_empty(),
+                lower_level_sst_ids,
                 is_lower_level_bottom_level: level + 1 == self.options.max_levels,
             });
         }
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionOptions {
     pub size_ratio_percent: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -30,6 +30,22 @@ impl SimpleLeveledCompactionController {
         Self { options }
     }
 
+    /// Estimate the bytes to compact, which are the upper levels of all pairs of levels that
+    /// exceed the size ratio or the L0 trigger.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let mut pending = 0;
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            pending += snapshot.sst_bytes(&snapshot.l0_sstables);
+        }
+        for pair in snapshot.levels.windows(2) {
+            let size_ratio = pair[1].1.len() as f64 / pair[0].1.len() as f64;
+            if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
+                pending += snapshot.sst_bytes(&pair[0].1);
+            }
+        }
+        pending
+    }
+
     /// Generates a compaction task.
     ///
     /// Returns `None` if no compaction needs to be scheduled. The order of SSTs in the compaction task id vector matters.
@@ -10,7 +10,7 @@ pub struct TieredCompactionTask {
     pub bottom_tier_included: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
     pub max_size_amplification_percent: usize,
@@ -28,6 +28,18 @@ impl TieredCompactionController {
         Self { options }
     }
 
+    /// Estimate the bytes to compact, which are all tiers above the bottom one once there are
+    /// enough tiers to trigger a compaction.
+    pub fn pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        if snapshot.levels.len() < self.options.num_tiers {
+            return 0;
+        }
+        snapshot.levels[..snapshot.levels.len() - 1]
+            .iter()
+            .map(|(_, ssts)| snapshot.sst_bytes(ssts))
+            .sum()
+    }
+
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
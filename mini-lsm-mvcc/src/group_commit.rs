@@ -0,0 +1,208 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageInner;
+
+/// When writes are synced to the WAL on disk before they return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// Every write is durable when it returns.
+    Always,
+    /// Sync once every this many writes.
+    EveryN(usize),
+    /// Sync once this many milliseconds have passed since the last sync, checked on each write and
+    /// by the flush thread.
+    Interval(u64),
+    /// Only sync when `sync` is called or the memtable is frozen.
+    Never,
+}
+
+/// Progress of syncing the WAL, which is shared by all writers so that one fsync covers every
+/// write appended before it.
+pub(crate) struct WalSyncState {
+    /// Writes since the last sync.
+    unsynced_writes: usize,
+    last_sync: Instant,
+    /// Number of fsyncs issued by writers.
+    pub(crate) num_syncs: u64,
+}
+
+impl WalSyncState {
+    pub(crate) fn new() -> Self {
+        Self {
+            unsynced_writes: 0,
+            last_sync: Instant::now(),
+            num_syncs: 0,
+        }
+    }
+}
+
+/// A write batch waiting to be committed by the leader of its group. Empty values are deletes.
+pub(crate) struct PendingWrite {
+    pub(crate) entries: Vec<(Bytes, Bytes)>,
+    pub(crate) sync: bool,
+    pub(crate) disable_wal: bool,
+}
+
+#[derive(Default)]
+struct WriteQueueState {
+    /// Writes waiting to be committed with the tickets of their writers. The writer of the first
+    /// one leads the next group, which commits all writes queued by the time it gets the write
+    /// lock.
+    pending: VecDeque<(u64, PendingWrite)>,
+    /// Commit timestamps of the writes committed by a leader on behalf of their writers, or the
+    /// error which failed their group.
+    done: HashMap<u64, Result<u64, String>>,
+    next_ticket: u64,
+}
+
+/// The queue of concurrent writers, which are committed in groups sharing one WAL append and one
+/// sync.
+#[derive(Default)]
+pub(crate) struct WriteQueue {
+    state: Mutex<WriteQueueState>,
+    cvar: Condvar,
+}
+
+impl WriteQueue {
+    /// Number of writes waiting to be committed.
+    #[cfg(test)]
+    pub(crate) fn num_pending(&self) -> usize {
+        self.state.lock().pending.len()
+    }
+}
+
+impl LsmStorageInner {
+    /// Commit `write` with the other writes queued meanwhile, and return its commit timestamp.
+    /// The first writer in the queue leads the group: it appends all batches of the group to the
+    /// WAL at once, syncs it as required by the writers and the WAL sync policy, and then inserts
+    /// the batches into the memtable and publishes their commit timestamps.
+    pub(crate) fn commit_write(&self, write: PendingWrite) -> Result<u64> {
+        let queue = &self.write_queue;
+        let mut state = queue.state.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.pending.push_back((ticket, write));
+        loop {
+            if let Some(result) = state.done.remove(&ticket) {
+                return result.map_err(|e| anyhow!(e));
+            }
+            if state.pending.front().map(|(first, _)| *first) == Some(ticket) {
+                break;
+            }
+            queue.cvar.wait(&mut state);
+        }
+        drop(state);
+
+        let _lck = self.mvcc().write_lock.lock();
+        let group = std::mem::take(&mut queue.state.lock().pending);
+        let result = self.commit_write_group(&group);
+        let mut state = queue.state.lock();
+        for (idx, (ticket, _)) in group.iter().enumerate().skip(1) {
+            let result = match &result {
+                Ok(first_ts) => Ok(first_ts + idx as u64),
+                Err(e) => Err(format!("{:#}", e)),
+            };
+            state.done.insert(*ticket, result);
+        }
+        queue.cvar.notify_all();
+        result
+    }
+
+    /// Commit the writes in `group` with consecutive timestamps under the write lock, and return
+    /// the timestamp of the first one.
+    fn commit_write_group(&self, group: &VecDeque<(u64, PendingWrite)>) -> Result<u64> {
+        let first_ts = self.mvcc().latest_commit_ts() + 1;
+        let batches = group
+            .iter()
+            .enumerate()
+            .map(|(idx, (_, write))| {
+                let ts = first_ts + idx as u64;
+                write
+                    .entries
+                    .iter()
+                    .map(|(key, value)| (KeySlice::from_slice(key, ts), &value[..]))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let logged = group
+            .iter()
+            .zip(&batches)
+            .filter(|((_, write), _)| !write.disable_wal)
+            .map(|(_, batch)| &batch[..])
+            .collect::<Vec<_>>();
+        let sync = group
+            .iter()
+            .any(|(_, write)| write.sync && !write.disable_wal);
+        let size;
+        {
+            let guard = self.state.read();
+            if let Some(wal) = guard.memtable.wal() {
+                wal.put_batches(&logged)?;
+            }
+            for batch in &batches {
+                guard.memtable.put_batch_without_wal(batch);
+            }
+            size = guard.memtable.approximate_size();
+        }
+        if !logged.is_empty() {
+            self.sync_wal_for_writes(logged.len(), sync)?;
+        }
+        self.try_freeze(size)?;
+        self.mvcc()
+            .update_commit_ts(first_ts + group.len() as u64 - 1);
+        Ok(first_ts)
+    }
+
+    /// Make the `num_writes` writes just appended to the WAL durable as required by the WAL sync
+    /// policy, or right away if `force`d. This is called before the commit timestamps of the
+    /// writes are published, so that writes are not visible before they are durable.
+    pub(crate) fn sync_wal_for_writes(&self, num_writes: usize, force: bool) -> Result<()> {
+        if !self.owns_wal() {
+            return Ok(());
+        }
+        let mut state = self.wal_sync.lock();
+        state.unsynced_writes += num_writes;
+        let sync = force
+            || match self.options.wal_sync {
+                WalSyncPolicy::Always => true,
+                WalSyncPolicy::EveryN(n) => state.unsynced_writes >= n,
+                WalSyncPolicy::Interval(ms) => {
+                    state.last_sync.elapsed() >= Duration::from_millis(ms)
+                }
+                WalSyncPolicy::Never => false,
+            };
+        if sync {
+            self.sync_wal_locked(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Sync the writes left unsynced for longer than the interval of `WalSyncPolicy::Interval`.
+    pub(crate) fn sync_wal_on_interval(&self) -> Result<()> {
+        let WalSyncPolicy::Interval(ms) = self.options.wal_sync else {
+            return Ok(());
+        };
+        if !self.owns_wal() {
+            return Ok(());
+        }
+        let mut state = self.wal_sync.lock();
+        if state.unsynced_writes > 0 && state.last_sync.elapsed() >= Duration::from_millis(ms) {
+            self.sync_wal_locked(&mut state)?;
+        }
+        Ok(())
+    }
+
+    fn sync_wal_locked(&self, state: &mut WalSyncState) -> Result<()> {
+        self.sync()?;
+        state.unsynced_writes = 0;
+        state.last_sync = Instant::now();
+        state.num_syncs += 1;
+        Ok(())
+    }
+}
@@ -3,11 +3,14 @@ pub mod merge_iterator;
 pub mod two_merge_iterator;
 
 pub trait StorageIterator {
-    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord + Copy
     where
         Self: 'a;
 
-    /// Get the current value.
+    /// Get the current value. Iterators over SSTs read values stored in a value log lazily here,
+    /// and a value that fails to be read is empty, exactly like a delete. Callers that look at the
+    /// value of such an iterator must call `status` right after `value` and stop on error;
+    /// otherwise the value is silently dropped.
     fn value(&self) -> &[u8];
 
     /// Get the current key.
@@ -19,6 +22,29 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Move to the previous position. Iterators may change direction at any time: merging
+    /// iterators re-seek their children around the current key to do so.
+    fn prev(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("reverse iteration is not supported")
+    }
+
+    /// Move to the first position with a key >= `key`, e.g., to reposition the children of a
+    /// merging iterator when it changes direction.
+    fn seek_to_key(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        anyhow::bail!("seeking is not supported")
+    }
+
+    /// Move to the last position with a key <= `key`.
+    fn seek_for_prev(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        anyhow::bail!("seeking is not supported")
+    }
+
+    /// Check the error of the last call to `value`. It must be called after every `value` whose
+    /// result is used, as an empty value may be a failed read rather than a delete.
+    fn status(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
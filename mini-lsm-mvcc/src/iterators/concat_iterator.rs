@@ -4,6 +4,7 @@ use anyhow::Result;
 
 use crate::{
     key::KeySlice,
+    options::ReadOptions,
     table::{SsTable, SsTableIterator},
 };
 
@@ -15,9 +16,18 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    /// Whether to yield values in the tagged on-disk format.
+    tagged: bool,
+    options: ReadOptions,
 }
 
 impl SstConcatIterator {
+    /// Skip SSTs with only range tombstones, which have no keys and an empty key range.
+    fn skip_empty_ssts(mut sstables: Vec<Arc<SsTable>>) -> Vec<Arc<SsTable>> {
+        sstables.retain(|sst| sst.has_keys());
+        sstables
+    }
+
     fn check_sst_valid(sstables: &[Arc<SsTable>]) {
         for sst in sstables {
             assert!(sst.first_key() <= sst.last_key());
@@ -29,50 +39,227 @@ impl SstConcatIterator {
         }
     }
 
+    fn seek_to_first_inner(
+        sstables: Vec<Arc<SsTable>>,
+        tagged: bool,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let sstables = Self::skip_empty_ssts(sstables);
+        Self::check_sst_valid(&sstables);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: 0,
+            sstables,
+            tagged,
+            options,
+        };
+        if !iter.sstables.is_empty() {
+            iter.current = Some(iter.open_sst(0)?);
+            iter.next_sst_idx = 1;
+            iter.move_until_valid()?;
+        }
+        Ok(iter)
+    }
+
     pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::seek_to_first_inner(sstables, false, ReadOptions::default())
+    }
+
+    /// Create an iterator reading blocks as set in `options`, see
+    /// `SsTableIterator::create_and_seek_to_first_with_options`.
+    pub(crate) fn create_and_seek_to_first_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        Self::seek_to_first_inner(sstables, false, options)
+    }
+
+    /// Create an iterator for compaction, see `SsTableIterator::create_and_seek_to_first_for_compaction`.
+    pub(crate) fn create_and_seek_to_first_for_compaction(
+        sstables: Vec<Arc<SsTable>>,
+        tagged: bool,
+    ) -> Result<Self> {
+        Self::seek_to_first_inner(sstables, tagged, ReadOptions::default())
+    }
+
+    fn open_sst(&self, idx: usize) -> Result<SsTableIterator> {
+        let sst = self.sstables[idx].clone();
+        if self.tagged {
+            SsTableIterator::create_and_seek_to_first_for_compaction(sst, true)
+        } else {
+            SsTableIterator::create_and_seek_to_first_with_options(sst, self.options.clone())
+        }
+    }
+
+    fn seek_to_key_inner(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        tagged: bool,
+        options: ReadOptions,
+        for_get: bool,
+    ) -> Result<Self> {
+        let sstables = Self::skip_empty_ssts(sstables);
         Self::check_sst_valid(&sstables);
-        if sstables.is_empty() {
+        let idx: usize = sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        if idx >= sstables.len() {
             return Ok(Self {
                 current: None,
-                next_sst_idx: 0,
+                next_sst_idx: sstables.len(),
                 sstables,
+                tagged,
+                options,
             });
         }
+        let sst = sstables[idx].clone();
+        let current = if tagged {
+            SsTableIterator::create_and_seek_to_key_for_compaction(sst, key, true)?
+        } else if for_get {
+            SsTableIterator::create_and_seek_to_key_for_get(sst, key, options.clone())?
+        } else {
+            SsTableIterator::create_and_seek_to_key_with_options(sst, key, options.clone())?
+        };
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_first(
-                sstables[0].clone(),
-            )?),
-            next_sst_idx: 1,
+            current: Some(current),
+            next_sst_idx: idx + 1,
             sstables,
+            tagged,
+            options,
         };
         iter.move_until_valid()?;
         Ok(iter)
     }
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
+        Self::seek_to_key_inner(sstables, key, false, ReadOptions::default(), false)
+    }
+
+    /// Create an iterator reading blocks as set in `options`, see
+    /// `SsTableIterator::create_and_seek_to_key_with_options`.
+    pub(crate) fn create_and_seek_to_key_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        Self::seek_to_key_inner(sstables, key, false, options, false)
+    }
+
+    /// Create an iterator for a point lookup, see `SsTableIterator::create_and_seek_to_key_for_get`.
+    pub(crate) fn create_and_seek_to_key_for_get(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        Self::seek_to_key_inner(sstables, key, false, options, true)
+    }
+
+    /// Create an iterator for compaction, see `SsTableIterator::create_and_seek_to_key_for_compaction`.
+    pub(crate) fn create_and_seek_to_key_for_compaction(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        tagged: bool,
+    ) -> Result<Self> {
+        Self::seek_to_key_inner(sstables, key, tagged, ReadOptions::default(), false)
+    }
+
+    /// Create an iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_and_seek_to_last_with_options(sstables, ReadOptions::default())
+    }
+
+    /// Create an iterator reading blocks as set in `options`, see
+    /// `SsTableIterator::create_and_seek_to_last_with_options`.
+    pub(crate) fn create_and_seek_to_last_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let sstables = Self::skip_empty_ssts(sstables);
         Self::check_sst_valid(&sstables);
-        let idx: usize = sstables
-            .partition_point(|table| table.first_key().as_key_slice() <= key)
-            .saturating_sub(1);
-        if idx >= sstables.len() {
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: sstables.len(),
+            sstables,
+            tagged: false,
+            options,
+        };
+        if let Some(sst) = iter.sstables.last() {
+            iter.current = Some(SsTableIterator::create_and_seek_to_last_with_options(
+                sst.clone(),
+                iter.options.clone(),
+            )?);
+            iter.move_until_valid_rev()?;
+        }
+        Ok(iter)
+    }
+
+    /// Create an iterator and seek to the last key-value pair which <= `key`.
+    pub fn create_and_seek_for_prev(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
+        Self::seek_for_prev_inner(sstables, key, ReadOptions::default())
+    }
+
+    /// Create an iterator reading blocks as set in `options`, see
+    /// `SsTableIterator::create_and_seek_for_prev_with_options`.
+    pub(crate) fn create_and_seek_for_prev_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        Self::seek_for_prev_inner(sstables, key, options)
+    }
+
+    fn seek_for_prev_inner(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let sstables = Self::skip_empty_ssts(sstables);
+        Self::check_sst_valid(&sstables);
+        let idx = sstables.partition_point(|table| table.first_key().as_key_slice() <= key);
+        if idx == 0 {
             return Ok(Self {
                 current: None,
-                next_sst_idx: sstables.len(),
+                next_sst_idx: 0,
                 sstables,
+                tagged: false,
+                options,
             });
         }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
+            current: Some(SsTableIterator::create_and_seek_for_prev_with_options(
+                sstables[idx - 1].clone(),
                 key,
+                options.clone(),
             )?),
-            next_sst_idx: idx + 1,
+            next_sst_idx: idx,
             sstables,
+            tagged: false,
+            options,
         };
-        iter.move_until_valid()?;
+        iter.move_until_valid_rev()?;
         Ok(iter)
     }
 
+    /// Move to the last key of the previous SSTs until the iterator is valid.
+    fn move_until_valid_rev(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_mut() {
+            if iter.is_valid() {
+                break;
+            }
+            // The current SST is the one before `next_sst_idx`.
+            if self.next_sst_idx <= 1 {
+                self.current = None;
+            } else {
+                self.next_sst_idx -= 1;
+                self.current = Some(SsTableIterator::create_and_seek_to_last_with_options(
+                    self.sstables[self.next_sst_idx - 1].clone(),
+                    self.options.clone(),
+                )?);
+            }
+        }
+        Ok(())
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
@@ -81,9 +268,7 @@ impl SstConcatIterator {
             if self.next_sst_idx >= self.sstables.len() {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
-                    self.sstables[self.next_sst_idx].clone(),
-                )?);
+                self.current = Some(self.open_sst(self.next_sst_idx)?);
                 self.next_sst_idx += 1;
             }
         }
@@ -94,7 +279,7 @@ impl SstConcatIterator {
 impl StorageIterator for SstConcatIterator {
     type KeyType<'a> = KeySlice<'a>;
 
-    fn key(&self) -> KeySlice {
+    fn key(&self) -> KeySlice<'_> {
         self.current.as_ref().unwrap().key()
     }
 
@@ -102,6 +287,13 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn status(&self) -> Result<()> {
+        match &self.current {
+            Some(current) => current.status(),
+            None => Ok(()),
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
@@ -117,6 +309,28 @@ impl StorageIterator for SstConcatIterator {
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        self.current.as_mut().unwrap().prev()?;
+        self.move_until_valid_rev()?;
+        Ok(())
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        *self = Self::seek_to_key_inner(
+            self.sstables.clone(),
+            key,
+            self.tagged,
+            self.options.clone(),
+            false,
+        )?;
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        *self = Self::seek_for_prev_inner(self.sstables.clone(), key, self.options.clone())?;
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         1
     }
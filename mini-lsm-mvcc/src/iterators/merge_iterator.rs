@@ -8,7 +8,9 @@ use crate::key::KeySlice;
 
 use super::StorageIterator;
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator in the heap with its index. The top of the heap is the iterator with the smallest
+/// key, or the largest key if `reverse` is set.
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub bool);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -26,11 +28,12 @@ impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
 
 impl<I: StorageIterator> Ord for HeapWrapper<I> {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.1
-            .key()
-            .cmp(&other.1.key())
-            .then(self.0.cmp(&other.0))
-            .reverse()
+        let ordering = self.1.key().cmp(&other.1.key());
+        if self.2 {
+            ordering.then(self.0.cmp(&other.0).reverse())
+        } else {
+            ordering.then(self.0.cmp(&other.0)).reverse()
+        }
     }
 }
 
@@ -39,92 +42,143 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// Iterators that are no longer valid in the current direction, which are kept so that they
+    /// can be re-seeked when the direction changes.
+    exhausted: Vec<HeapWrapper<I>>,
+    /// Whether the iterator moves backward, from larger keys to smaller keys.
+    reverse: bool,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
+        Self::create_inner(iters, false)
+    }
 
-        let mut heap = BinaryHeap::new();
+    /// Merge iterators positioned at their last keys, e.g., by seeking to the last key. The merged
+    /// iterator starts by moving backward with `prev`.
+    pub fn create_rev(iters: Vec<Box<I>>) -> Self {
+        Self::create_inner(iters, true)
+    }
 
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+    fn create_inner(iters: Vec<Box<I>>, reverse: bool) -> Self {
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: Vec::new(),
+            reverse,
+        };
+        iter.rebuild(
+            iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter, reverse))
+                .collect(),
+        );
+        iter
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+    /// Rebuild the heap from all iterators in the current direction, and select the current one.
+    fn rebuild(&mut self, iters: Vec<HeapWrapper<I>>) {
+        for mut iter in iters {
+            iter.2 = self.reverse;
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
-
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
-        }
-    }
-}
-
-impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
-    for MergeIterator<I>
-{
-    type KeyType<'a> = KeySlice<'a>;
-
-    fn key(&self) -> KeySlice {
-        self.current.as_ref().unwrap().1.key()
+        // If all iterators are invalid, select one of them as the current.
+        self.current = self.iters.pop().or_else(|| self.exhausted.pop());
     }
 
-    fn value(&self) -> &[u8] {
-        self.current.as_ref().unwrap().1.value()
+    /// Take all iterators out of the heap, with the current one first.
+    fn take_all(&mut self) -> Vec<HeapWrapper<I>> {
+        self.current
+            .take()
+            .into_iter()
+            .chain(std::mem::take(&mut self.iters).into_vec())
+            .chain(std::mem::take(&mut self.exhausted))
+            .collect()
     }
+}
 
-    fn is_valid(&self) -> bool {
-        self.current
-            .as_ref()
-            .map(|x| x.1.is_valid())
-            .unwrap_or(false)
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Change the direction at the current key. The other iterators are positioned at their
+    /// first key after it in the new direction, and the current one moves one step.
+    fn switch_direction(&mut self, reverse: bool) -> Result<()> {
+        let key = self.key().to_key_vec();
+        let key = key.as_key_slice();
+        let mut iters = self.take_all();
+        self.reverse = reverse;
+        let (current, others) = iters.split_first_mut().unwrap();
+        for HeapWrapper(_, iter, _) in others {
+            if reverse {
+                iter.seek_for_prev(key)?;
+                if iter.is_valid() && iter.key() == key {
+                    iter.prev()?;
+                }
+            } else {
+                iter.seek_to_key(key)?;
+                if iter.is_valid() && iter.key() == key {
+                    iter.next()?;
+                }
+            }
+        }
+        if reverse {
+            current.1.prev()?;
+        } else {
+            current.1.next()?;
+        }
+        self.rebuild(iters);
+        Ok(())
     }
 
-    fn next(&mut self) -> Result<()> {
+    /// Move the current iterator one step in the current direction, together with the iterators
+    /// at the same key.
+    fn advance(&mut self) -> Result<()> {
+        let reverse = self.reverse;
         let current = self.current.as_mut().unwrap();
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
             debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
+                if reverse {
+                    inner_iter.1.key() <= current.1.key()
+                } else {
+                    inner_iter.1.key() >= current.1.key()
+                },
                 "heap invariant violated"
             );
             if inner_iter.1.key() == current.1.key() {
+                let result = if reverse {
+                    inner_iter.1.prev()
+                } else {
+                    inner_iter.1.next()
+                };
                 // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
-                    PeekMut::pop(inner_iter);
+                if let e @ Err(_) = result {
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next()?;
+        if reverse {
+            current.1.prev()?;
+        } else {
+            current.1.next()?;
+        }
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -139,6 +193,75 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         Ok(())
     }
 
+    /// Seek all iterators, and rebuild the heap in the given direction.
+    fn seek(&mut self, key: KeySlice, reverse: bool) -> Result<()> {
+        let mut iters = self.take_all();
+        self.reverse = reverse;
+        let mut result = Ok(());
+        for HeapWrapper(_, iter, _) in &mut iters {
+            result = if reverse {
+                iter.seek_for_prev(key)
+            } else {
+                iter.seek_to_key(key)
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        self.rebuild(iters);
+        result
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for MergeIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> KeySlice<'_> {
+        self.current.as_ref().unwrap().1.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.value()
+    }
+
+    fn status(&self) -> Result<()> {
+        match &self.current {
+            Some(current) => current.1.status(),
+            None => Ok(()),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current
+            .as_ref()
+            .map(|x| x.1.is_valid())
+            .unwrap_or(false)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.reverse {
+            return self.switch_direction(false);
+        }
+        self.advance()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.reverse {
+            return self.switch_direction(true);
+        }
+        self.advance()
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        self.seek(key, false)
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.seek(key, true)
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iters
             .iter()
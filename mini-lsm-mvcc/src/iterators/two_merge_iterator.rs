@@ -8,6 +8,8 @@ pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    /// Whether the iterator moves backward, from larger keys to smaller keys.
+    reverse: bool,
 }
 
 impl<
@@ -15,33 +17,92 @@ impl<
         B: 'static + for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
     > TwoMergeIterator<A, B>
 {
-    fn choose_a(a: &A, b: &B) -> bool {
+    fn choose_a(a: &A, b: &B, reverse: bool) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        if reverse {
+            a.key() > b.key()
+        } else {
+            a.key() < b.key()
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() && self.b.is_valid() && self.b.key() == self.a.key() {
-            self.b.next()?;
+            if self.reverse {
+                self.b.prev()?;
+            } else {
+                self.b.next()?;
+            }
         }
         Ok(())
     }
 
     pub fn create(a: A, b: B) -> Result<Self> {
+        Self::create_inner(a, b, false)
+    }
+
+    /// Merge two iterators positioned at their last keys. The merged iterator starts by moving
+    /// backward with `prev`.
+    pub fn create_rev(a: A, b: B) -> Result<Self> {
+        Self::create_inner(a, b, true)
+    }
+
+    fn create_inner(a: A, b: B, reverse: bool) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
             a,
             b,
+            reverse,
         };
         iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.choose_a = Self::choose_a(&iter.a, &iter.b, reverse);
         Ok(iter)
     }
+
+    /// Change the direction at the current key. The other iterator is positioned at its first key
+    /// after it in the new direction, and the current one moves one step.
+    fn switch_direction(&mut self, reverse: bool) -> Result<()> {
+        if self.choose_a {
+            let key = self.a.key();
+            if reverse {
+                self.b.seek_for_prev(key)?;
+                if self.b.is_valid() && self.b.key() == self.a.key() {
+                    self.b.prev()?;
+                }
+                self.a.prev()?;
+            } else {
+                self.b.seek_to_key(key)?;
+                if self.b.is_valid() && self.b.key() == self.a.key() {
+                    self.b.next()?;
+                }
+                self.a.next()?;
+            }
+        } else {
+            let key = self.b.key();
+            if reverse {
+                self.a.seek_for_prev(key)?;
+                if self.a.is_valid() && self.a.key() == self.b.key() {
+                    self.a.prev()?;
+                }
+                self.b.prev()?;
+            } else {
+                self.a.seek_to_key(key)?;
+                if self.a.is_valid() && self.a.key() == self.b.key() {
+                    self.a.next()?;
+                }
+                self.b.next()?;
+            }
+        }
+        self.reverse = reverse;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, reverse);
+        Ok(())
+    }
 }
 
 impl<
@@ -69,6 +130,14 @@ impl<
         }
     }
 
+    fn status(&self) -> Result<()> {
+        if self.choose_a {
+            self.a.status()
+        } else {
+            self.b.status()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -78,13 +147,48 @@ impl<
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.reverse {
+            return self.switch_direction(false);
+        }
         if self.choose_a {
             self.a.next()?;
         } else {
             self.b.next()?;
         }
         self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
+        self.choose_a = Self::choose_a(&self.a, &self.b, false);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.reverse {
+            return self.switch_direction(true);
+        }
+        if self.choose_a {
+            self.a.prev()?;
+        } else {
+            self.b.prev()?;
+        }
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, true);
+        Ok(())
+    }
+
+    fn seek_to_key(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.a.seek_to_key(key)?;
+        self.b.seek_to_key(key)?;
+        self.reverse = false;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, false);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.a.seek_for_prev(key)?;
+        self.b.seek_for_prev(key)?;
+        self.reverse = true;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, true);
         Ok(())
     }
 
@@ -65,6 +65,11 @@ impl Key<Vec<u8>> {
         self.1 = ts;
     }
 
+    /// Shortens the key to its first `len` bytes, keeping the ts.
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len)
+    }
+
     /// Set the key from a slice without re-allocating.
     pub fn set_from_slice(&mut self, key_slice: KeySlice) {
         self.0.clear();
@@ -72,7 +77,7 @@ impl Key<Vec<u8>> {
         self.1 = key_slice.1;
     }
 
-    pub fn as_key_slice(&self) -> KeySlice {
+    pub fn as_key_slice(&self) -> KeySlice<'_> {
         Key(self.0.as_slice(), self.1)
     }
 
@@ -102,7 +107,7 @@ impl Key<Bytes> {
         Self(Bytes::new(), TS_DEFAULT)
     }
 
-    pub fn as_key_slice(&self) -> KeySlice {
+    pub fn as_key_slice(&self) -> KeySlice<'_> {
         Key(&self.0, self.1)
     }
 
@@ -1,6 +1,10 @@
 pub mod block;
+pub mod block_cache;
+pub mod checkpoint;
+pub mod column_family;
 pub mod compact;
 pub mod debug;
+pub mod group_commit;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
@@ -8,8 +12,16 @@ pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
 pub mod mvcc;
+pub(crate) mod obsolete_files;
+pub mod options;
+pub mod range_tombstone;
+pub mod repair;
+pub mod salvage;
 pub mod table;
+pub mod value_log;
+pub(crate) mod varint;
 pub mod wal;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
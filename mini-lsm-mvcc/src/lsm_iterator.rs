@@ -7,7 +7,9 @@ use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_RANGE_BEGIN, TS_RANGE_END};
 use crate::mem_table::MemTableIterator;
+use crate::range_tombstone::RangeTombstones;
 use crate::table::SsTableIterator;
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
@@ -18,94 +20,295 @@ type LsmIteratorInner = TwoMergeIterator<
 
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    /// The lower bound of the keys, checked when moving backward.
+    begin_bound: Bound<Bytes>,
+    /// The upper bound of the keys, checked when moving forward.
     end_bound: Bound<Bytes>,
+    /// Whether `inner` is valid and within the bound in the current direction.
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    /// Range tombstones visible at `read_ts`.
+    range_tombstones: RangeTombstones,
+    /// Whether the iterator moves backward, from larger keys to smaller keys.
+    reverse: bool,
+    /// The current key-value pair when moving backward, as `inner` has already moved past all
+    /// versions of the key to find the latest visible one.
+    current: Option<(Bytes, Bytes)>,
 }
 
 impl LsmIterator {
+    /// Create an iterator moving forward from the first key of `iter`, which must be positioned at
+    /// the first version of the first key in the range.
     pub(crate) fn new(
         iter: LsmIteratorInner,
+        begin_bound: Bound<Bytes>,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        range_tombstones: RangeTombstones,
     ) -> Result<Self> {
+        let mut iter = Self::create_inner(
+            iter,
+            begin_bound,
+            end_bound,
+            read_ts,
+            range_tombstones,
+            false,
+        );
+        iter.move_to_key()?;
+        Ok(iter)
+    }
+
+    /// Create an iterator moving backward from the last key of `iter`, which must be positioned
+    /// at the last version of the last key in the range, e.g., with iterators created for reverse
+    /// iteration.
+    pub(crate) fn new_rev(
+        iter: LsmIteratorInner,
+        begin_bound: Bound<Bytes>,
+        end_bound: Bound<Bytes>,
+        read_ts: u64,
+        range_tombstones: RangeTombstones,
+    ) -> Result<Self> {
+        let mut iter = Self::create_inner(
+            iter,
+            begin_bound,
+            end_bound,
+            read_ts,
+            range_tombstones,
+            true,
+        );
+        iter.move_to_prev_key()?;
+        Ok(iter)
+    }
+
+    fn create_inner(
+        inner: LsmIteratorInner,
+        begin_bound: Bound<Bytes>,
+        end_bound: Bound<Bytes>,
+        read_ts: u64,
+        range_tombstones: RangeTombstones,
+        reverse: bool,
+    ) -> Self {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
-            inner: iter,
+            inner,
+            begin_bound,
             end_bound,
+            is_valid: false,
             read_ts,
             prev_key: Vec::new(),
+            range_tombstones,
+            reverse,
+            current: None,
         };
-        iter.move_to_key()?;
-        Ok(iter)
+        iter.update_is_valid();
+        iter
     }
 
-    fn next_inner(&mut self) -> Result<()> {
-        self.inner.next()?;
-        if !self.inner.is_valid() {
-            self.is_valid = false;
-            return Ok(());
+    /// Check that `inner` is valid and has not moved past the bound in the current direction.
+    fn update_is_valid(&mut self) {
+        self.is_valid = self.inner.is_valid();
+        if !self.is_valid {
+            return;
         }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
+        let key = self.inner.key().key_ref();
+        self.is_valid = if self.reverse {
+            match self.begin_bound.as_ref() {
+                Bound::Unbounded => true,
+                Bound::Included(begin) => key >= begin.as_ref(),
+                Bound::Excluded(begin) => key > begin.as_ref(),
+            }
+        } else {
+            match self.end_bound.as_ref() {
+                Bound::Unbounded => true,
+                Bound::Included(end) => key <= end.as_ref(),
+                Bound::Excluded(end) => key < end.as_ref(),
+            }
+        };
+    }
+
+    fn prev_inner(&mut self) -> Result<()> {
+        self.inner.prev()?;
+        self.update_is_valid();
+        Ok(())
+    }
+
+    /// Move to the previous key with a visible version. Versions of a key are visited from the
+    /// earliest to the latest when moving backward, so the version to return is the last one
+    /// visible at `read_ts` before the key changes.
+    fn move_to_prev_key(&mut self) -> Result<()> {
+        self.current = None;
+        while self.is_valid {
+            self.prev_key.clear();
+            self.prev_key.extend(self.inner.key().key_ref());
+            let mut visible = None;
+            while self.is_valid && self.inner.key().key_ref() == self.prev_key {
+                let ts = self.inner.key().ts();
+                if ts <= self.read_ts {
+                    visible = Some((ts, Bytes::copy_from_slice(self.inner.value())));
+                    self.inner.status()?;
+                }
+                self.prev_inner()?;
+            }
+            if let Some((ts, value)) = visible {
+                if !value.is_empty() && !self.range_tombstones.covers(&self.prev_key, ts) {
+                    self.current = Some((Bytes::copy_from_slice(&self.prev_key), value));
+                    break;
+                }
+            }
         }
         Ok(())
     }
 
+    fn next_inner(&mut self) -> Result<()> {
+        self.inner.next()?;
+        self.update_is_valid();
+        Ok(())
+    }
+
+    /// Move to the first visible version of a key other than `prev_key`.
     fn move_to_key(&mut self) -> Result<()> {
         loop {
-            while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+            while self.is_valid && self.inner.key().key_ref() == self.prev_key {
                 self.next_inner()?;
             }
-            if !self.inner.is_valid() {
+            if !self.is_valid {
                 break;
             }
             self.prev_key.clear();
             self.prev_key.extend(self.inner.key().key_ref());
-            while self.inner.is_valid()
+            while self.is_valid
                 && self.inner.key().key_ref() == self.prev_key
                 && self.inner.key().ts() > self.read_ts
             {
                 self.next_inner()?;
             }
-            if !self.inner.is_valid() {
+            if !self.is_valid {
                 break;
             }
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
+            // Only the visible version is read if its value is stored in a value log.
+            let is_delete = self.inner.value().is_empty();
+            self.inner.status()?;
+            if !is_delete
+                && !self
+                    .range_tombstones
+                    .covers(self.inner.key().key_ref(), self.inner.key().ts())
+            {
                 break;
             }
         }
         Ok(())
     }
+
+    /// Start moving backward from the current key. `inner` is at the visible version of the key,
+    /// and moves back past its newer versions in place.
+    fn switch_to_reverse(&mut self) -> Result<()> {
+        let key = Bytes::copy_from_slice(self.inner.key().key_ref());
+        self.reverse = true;
+        self.prev_inner()?;
+        while self.is_valid && self.inner.key().key_ref() == key {
+            self.prev_inner()?;
+        }
+        self.move_to_prev_key()
+    }
+
+    /// Start moving forward from the current key. `inner` is right before the first version of
+    /// the key, or invalid if there is nothing before it, in which case it is re-seeked to it.
+    fn switch_to_forward(&mut self) -> Result<()> {
+        let (key, _) = self.current.take().unwrap();
+        self.reverse = false;
+        if self.inner.is_valid() {
+            self.next_inner()?;
+        } else {
+            self.inner
+                .seek_to_key(KeySlice::from_slice(&key, TS_RANGE_BEGIN))?;
+            self.update_is_valid();
+        }
+        self.prev_key.clear();
+        self.prev_key.extend(&key);
+        self.move_to_key()
+    }
 }
 
 impl StorageIterator for LsmIterator {
     type KeyType<'a> = &'a [u8];
 
     fn is_valid(&self) -> bool {
-        self.is_valid
+        if self.reverse {
+            self.current.is_some()
+        } else {
+            self.is_valid
+        }
     }
 
     fn key(&self) -> &[u8] {
-        self.inner.key().key_ref()
+        match &self.current {
+            Some((key, _)) => key,
+            None => self.inner.key().key_ref(),
+        }
     }
 
+    /// The value is checked when the iterator moves to the key, so a failed value log read is
+    /// returned by `next`, `prev` or the seek, and `status` never fails.
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.current {
+            Some((_, value)) => value,
+            None => self.inner.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.reverse {
+            return self.switch_to_forward();
+        }
         self.next_inner()?;
         self.move_to_key()?;
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        if !self.reverse {
+            return self.switch_to_reverse();
+        }
+        self.move_to_prev_key()
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        self.current = None;
+        self.reverse = false;
+        self.prev_key.clear();
+        // Keys before the lower bound are not yielded.
+        let key = match self.begin_bound.as_ref() {
+            Bound::Included(begin) if key < begin.as_ref() => begin.as_ref(),
+            Bound::Excluded(begin) if key <= begin.as_ref() => {
+                self.prev_key.extend(begin.as_ref());
+                begin.as_ref()
+            }
+            _ => key,
+        };
+        self.inner
+            .seek_to_key(KeySlice::from_slice(key, TS_RANGE_BEGIN))?;
+        self.update_is_valid();
+        self.move_to_key()
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.reverse = true;
+        // Keys after the upper bound are not yielded.
+        let key = match self.end_bound.as_ref() {
+            Bound::Included(end) if key > end.as_ref() => KeySlice::from_slice(end, TS_RANGE_END),
+            Bound::Excluded(end) if key >= end.as_ref() => {
+                KeySlice::from_slice(end, TS_RANGE_BEGIN)
+            }
+            _ => KeySlice::from_slice(key, TS_RANGE_END),
+        };
+        self.inner.seek_for_prev(key)?;
+        self.update_is_valid();
+        self.move_to_prev_key()
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.inner.num_active_iterators()
     }
@@ -145,6 +348,8 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.key()
     }
 
+    /// Like the value of the wrapped iterator, this may be empty because a value log read failed,
+    /// which `status` then returns.
     fn value(&self) -> &[u8] {
         if !self.is_valid() {
             panic!("invalid access to the underlying iterator");
@@ -152,6 +357,10 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn status(&self) -> Result<()> {
+        self.iter.status()
+    }
+
     fn next(&mut self) -> Result<()> {
         // only move when the iterator is valid and not errored
         if self.has_errored {
@@ -166,6 +375,41 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if self.iter.is_valid() {
+            if let Err(e) = self.iter.prev() {
+                self.has_errored = true;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn seek_to_key(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_to_key(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_for_prev(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iter.num_active_iterators()
     }
@@ -1,19 +1,22 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
-use crate::block::Block;
+use crate::block_cache::ShardedBlockCache;
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    apply_full_compaction_result, CompactionController, CompactionOptions, CompactionTask,
+    LeveledCompactionController, LeveledCompactionOptions, SimpleLeveledCompactionController,
+    SimpleLeveledCompactionOptions, TieredCompactionController,
 };
+use crate::group_commit::{PendingWrite, WalSyncPolicy, WalSyncState, WriteQueue};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
@@ -22,11 +25,23 @@ use crate::key::{self, KeySlice};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
 use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
+use crate::mvcc::snapshot::Snapshot;
 use crate::mvcc::txn::{Transaction, TxnIterator};
 use crate::mvcc::LsmMvccInner;
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::obsolete_files::ObsoleteFiles;
+use crate::options::{ReadOptions, WriteOptions};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstones};
+use crate::salvage::SalvageReport;
+use crate::table::{
+    CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator, TableCache,
+};
+use crate::value_log::ValueLogFile;
+use crate::wal::{Wal, WalRecoveryMode};
+use crate::write_stall::{
+    WriteStallCounters, WriteStallOptions, WriteStallSignal, WriteStallStats,
+};
 
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+pub use crate::block_cache::BlockCache;
 
 /// Represents the state of the storage engine.
 #[derive(Clone)]
@@ -50,7 +65,15 @@ pub enum WriteBatchRecord<T: AsRef<[u8]>> {
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
+    /// Total size in bytes of the SSTs `sst_ids`.
+    pub(crate) fn sst_bytes(&self, sst_ids: &[usize]) -> u64 {
+        sst_ids
+            .iter()
+            .map(|id| self.sstables[id].table_size())
+            .sum()
+    }
+
+    pub(crate) fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
@@ -68,6 +91,67 @@ impl LsmStorageState {
             sstables: Default::default(),
         }
     }
+
+    /// Range tombstones visible at `read_ts` that may delete keys in the range. Only the SSTs whose
+    /// tombstones overlap with the range are read.
+    pub(crate) fn range_tombstones(
+        &self,
+        read_ts: u64,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<RangeTombstones> {
+        let overlaps = |start: &[u8], end: &[u8]| {
+            let after_lower = match lower {
+                Bound::Included(key) | Bound::Excluded(key) => key < end,
+                Bound::Unbounded => true,
+            };
+            let before_upper = match upper {
+                Bound::Included(key) => start <= key,
+                Bound::Excluded(key) => start < key,
+                Bound::Unbounded => true,
+            };
+            after_lower && before_upper
+        };
+        let mut sources = Vec::new();
+        for memtable in std::iter::once(&self.memtable).chain(self.imm_memtables.iter()) {
+            let tombstones = memtable
+                .range_tombstones()
+                .filter(|tombstone| overlaps(&tombstone.start, &tombstone.end))
+                .collect::<Vec<_>>();
+            if !tombstones.is_empty() {
+                sources.push(Arc::new(FragmentedRangeTombstones::new(tombstones)));
+            }
+        }
+        for sst in self.sstables.values() {
+            if sst
+                .tombstone_range()
+                .is_some_and(|(start, end)| overlaps(&start, &end))
+            {
+                sources.push(sst.fragmented_range_tombstones()?);
+            }
+        }
+        Ok(RangeTombstones::new(read_ts, sources))
+    }
+
+    /// Replace each SST in `old_ids` with the SST at the same position in `new_ids`, keeping its
+    /// place in L0 or in the levels.
+    pub(crate) fn replace_sstables(&mut self, old_ids: &[usize], new_ids: &[usize]) {
+        assert_eq!(old_ids.len(), new_ids.len());
+        let replacement = old_ids
+            .iter()
+            .copied()
+            .zip(new_ids.iter().copied())
+            .collect::<HashMap<_, _>>();
+        for id in self
+            .l0_sstables
+            .iter_mut()
+            .chain(self.levels.iter_mut().flat_map(|(_, ssts)| ssts.iter_mut()))
+        {
+            if let Some(new_id) = replacement.get(id) {
+                *id = *new_id;
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -81,6 +165,38 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Codec used to compress each data block of newly-built SSTs
+    pub compression: CompressionType,
+    // Values of at least this many bytes are moved into a value log when flushing memtables
+    pub value_separation_threshold: Option<usize>,
+    // Maximum number of key ranges a large compaction task is split into and compacted in parallel
+    pub max_subcompactions: usize,
+    // Number of most recent commit timestamps kept readable by `get_at` and `scan_at`
+    pub history_retention: u64,
+    // Rotate to a new manifest file starting with a snapshot of the LSM structure once the
+    // manifest exceeds this size in bytes, and twice its size after the last rotation
+    pub max_manifest_size: u64,
+    // Move files left behind by a crash to the `lost` directory on open instead of deleting them
+    pub quarantine_orphan_files: bool,
+    // Limits on the flush and compaction backlog at which writes are delayed or blocked
+    pub write_stall: WriteStallOptions,
+    // When writes are synced to the WAL before they return
+    pub wal_sync: WalSyncPolicy,
+    // How corrupted WAL records are handled on open
+    pub wal_recovery_mode: WalRecoveryMode,
+    // Append a hash index of the user keys to each data block of newly-built SSTs, so that point
+    // lookups skip the in-block search
+    pub block_hash_index: bool,
+    // Split the index and bloom filter of newly-built SSTs into partitions read through the block
+    // cache, so that only a small top-level index of each SST stays in memory
+    pub partitioned_index: bool,
+    // Open SSTs through a table cache that keeps at most this many files open, instead of holding
+    // the files of all SSTs open
+    pub max_open_files: Option<usize>,
+    // Capacity of the block cache in bytes
+    pub block_cache_size: usize,
+    // A block cache shared with other storages, used instead of a cache of `block_cache_size`
+    pub block_cache: Option<Arc<ShardedBlockCache>>,
 }
 
 impl LsmStorageOptions {
@@ -92,6 +208,20 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            compression: CompressionType::None,
+            value_separation_threshold: None,
+            max_subcompactions: 1,
+            history_retention: 0,
+            max_manifest_size: 4 << 20,
+            quarantine_orphan_files: false,
+            write_stall: WriteStallOptions::default(),
+            wal_sync: WalSyncPolicy::Never,
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            block_hash_index: false,
+            partitioned_index: false,
+            max_open_files: None,
+            block_cache_size: 4 << 30,
+            block_cache: None,
         }
     }
 
@@ -103,6 +233,20 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            value_separation_threshold: None,
+            max_subcompactions: 1,
+            history_retention: 0,
+            max_manifest_size: 4 << 20,
+            quarantine_orphan_files: false,
+            write_stall: WriteStallOptions::default(),
+            wal_sync: WalSyncPolicy::Never,
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            block_hash_index: false,
+            partitioned_index: false,
+            max_open_files: None,
+            block_cache_size: 4 << 30,
+            block_cache: None,
         }
     }
 
@@ -114,6 +258,36 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            value_separation_threshold: None,
+            max_subcompactions: 1,
+            history_retention: 0,
+            max_manifest_size: 4 << 20,
+            quarantine_orphan_files: false,
+            write_stall: WriteStallOptions::default(),
+            wal_sync: WalSyncPolicy::Never,
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            block_hash_index: false,
+            partitioned_index: false,
+            max_open_files: None,
+            block_cache_size: 4 << 30,
+            block_cache: None,
+        }
+    }
+
+    /// Create the block cache of a storage, which reads the shared cache if there is one.
+    pub(crate) fn new_block_cache(&self) -> Arc<BlockCache> {
+        Arc::new(match &self.block_cache {
+            Some(cache) => BlockCache::shared(cache.clone()),
+            None => BlockCache::new(self.block_cache_size),
+        })
+    }
+
+    /// Options of a column family, which only differ in the compaction strategy.
+    pub fn for_column_family(&self, compaction_options: CompactionOptions) -> Self {
+        Self {
+            compaction_options,
+            ..self.clone()
         }
     }
 }
@@ -158,14 +332,38 @@ pub enum CompactionFilter {
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
-    next_sst_id: AtomicUsize,
+    /// Holds the files of the SSTs open, if `max_open_files` is set.
+    pub(crate) table_cache: Option<Arc<TableCache>>,
+    /// Shared by all column families, so that SST, memtable and WAL ids are unique in the directory.
+    next_sst_id: Arc<AtomicUsize>,
     pub(crate) options: Arc<LsmStorageOptions>,
     pub(crate) compaction_controller: CompactionController,
     pub(crate) manifest: Option<Manifest>,
-    pub(crate) mvcc: Option<LsmMvccInner>,
+    pub(crate) mvcc: Option<Arc<LsmMvccInner>>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// Serializes compactions and value log garbage collections, which both replace SSTs.
+    pub(crate) compaction_lock: Mutex<()>,
+    /// Runs the subcompactions of a compaction task. Only created if `max_subcompactions > 1`.
+    pub(crate) subcompaction_pool: Option<Arc<rayon::ThreadPool>>,
+    pub(crate) column_family_id: usize,
+    /// Column families other than the default one. Only populated in the default column family.
+    pub(crate) column_families: RwLock<BTreeMap<usize, ColumnFamily>>,
+    /// SSTs and value logs replaced by compactions, shared by all column families.
+    pub(crate) obsolete_files: Arc<ObsoleteFiles>,
+    /// Where damage found when reading is reported. Set when opened with `open_read_only_salvage`,
+    /// in which case all writes fail.
+    pub(crate) salvage_report: Option<Arc<Mutex<SalvageReport>>>,
+    /// The error that stopped background flushes and compactions, shared by all column families.
+    pub(crate) background_error: Arc<Mutex<Option<String>>>,
+    pub(crate) write_stall_counters: Arc<WriteStallCounters>,
+    pub(crate) write_stall_signal: Arc<WriteStallSignal>,
+    pub(crate) wal_sync: Mutex<WalSyncState>,
+    /// Writers waiting to be committed in groups.
+    pub(crate) write_queue: WriteQueue,
+    /// Files left behind by a crash that were deleted or moved to `lost` on open.
+    pub(crate) orphan_files: Vec<PathBuf>,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -179,6 +377,8 @@ pub struct MiniLsm {
     compaction_notifier: crossbeam_channel::Sender<()>,
     /// The handle for the compaction thread. (In week 2)
     compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// The flush and compaction threads of the other column families.
+    column_family_workers: Mutex<Vec<MiniLsm>>,
 }
 
 impl Drop for MiniLsm {
@@ -190,6 +390,12 @@ impl Drop for MiniLsm {
 
 impl MiniLsm {
     pub fn close(&self) -> Result<()> {
+        if self.inner.read_only() {
+            return Ok(());
+        }
+        for worker in self.column_family_workers.lock().drain(..) {
+            worker.close()?;
+        }
         self.inner.sync_dir()?;
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
@@ -236,17 +442,157 @@ impl MiniLsm {
     /// not exist.
     pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
         let inner = Arc::new(LsmStorageInner::open(path, options)?);
+        let storage = Self::start(inner.clone())?;
+        for column_family in inner.column_families.read().values() {
+            storage.start_column_family(column_family)?;
+        }
+        Ok(Arc::new(storage))
+    }
+
+    /// Open a possibly damaged database for reading whatever is still intact. Nothing in the
+    /// directory is modified and no background threads are started; all writes fail. The report
+    /// lists the damage found on open, and `salvage_report` also lists damaged blocks and value log
+    /// records found by reads since.
+    pub fn open_read_only_salvage(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<(Arc<Self>, SalvageReport)> {
+        let (inner, report) = LsmStorageInner::open_read_only_salvage(path, options)?;
+        let (flush_notifier, _) = crossbeam_channel::unbounded();
+        let (compaction_notifier, _) = crossbeam_channel::unbounded();
+        let storage = Self {
+            inner: Arc::new(inner),
+            flush_notifier,
+            flush_thread: Mutex::new(None),
+            compaction_notifier,
+            compaction_thread: Mutex::new(None),
+            column_family_workers: Mutex::new(Vec::new()),
+        };
+        Ok((Arc::new(storage), report))
+    }
+
+    /// Spawn the flush and compaction threads of a storage.
+    fn start(inner: Arc<LsmStorageInner>) -> Result<Self> {
         let (tx1, rx) = crossbeam_channel::unbounded();
         let compaction_thread = inner.spawn_compaction_thread(rx)?;
         let (tx2, rx) = crossbeam_channel::unbounded();
         let flush_thread = inner.spawn_flush_thread(rx)?;
-        Ok(Arc::new(Self {
+        Ok(Self {
             inner,
             flush_notifier: tx2,
             flush_thread: Mutex::new(flush_thread),
             compaction_notifier: tx1,
             compaction_thread: Mutex::new(compaction_thread),
-        }))
+            column_family_workers: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn start_column_family(&self, column_family: &ColumnFamily) -> Result<()> {
+        let worker = Self::start(column_family.inner.clone())?;
+        self.column_family_workers.lock().push(worker);
+        Ok(())
+    }
+
+    /// Create a column family with its own compaction strategy. It shares the WAL, manifest and
+    /// all other options with the default column family.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        compaction_options: CompactionOptions,
+    ) -> Result<ColumnFamily> {
+        let column_family = self.inner.create_column_family(name, compaction_options)?;
+        self.start_column_family(&column_family)?;
+        Ok(column_family)
+    }
+
+    /// Get a column family by name.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamily> {
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            return Some(self.default_column_family());
+        }
+        self.inner.column_family(name)
+    }
+
+    pub fn default_column_family(&self) -> ColumnFamily {
+        self.inner.default_column_family()
+    }
+
+    pub fn get_cf(&self, column_family: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_cf(column_family, key)
+    }
+
+    pub fn get_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        self.inner.get_cf_with_options(column_family, key, options)
+    }
+
+    /// Atomically apply writes to several column families.
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        self.inner.write_batch_cf(batch)
+    }
+
+    pub fn write_batch_cf_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_cf_with_options(batch, options)
+    }
+
+    pub fn put_cf(&self, column_family: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put_cf(column_family, key, value)
+    }
+
+    pub fn put_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner
+            .put_cf_with_options(column_family, key, value, options)
+    }
+
+    pub fn delete_cf(&self, column_family: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.inner.delete_cf(column_family, key)
+    }
+
+    pub fn delete_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner
+            .delete_cf_with_options(column_family, key, options)
+    }
+
+    pub fn scan_cf(
+        &self,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_cf(column_family, lower, upper)
+    }
+
+    pub fn scan_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.inner
+            .scan_cf_with_options(column_family, lower, upper, options)
     }
 
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
@@ -257,18 +603,73 @@ impl MiniLsm {
         self.inner.get(key)
     }
 
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        self.inner.get_with_options(key, options)
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
 
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_with_options(batch, options)
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
 
+    pub fn put_with_options(&self, key: &[u8], value: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.put_with_options(key, value, options)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
 
+    pub fn delete_with_options(&self, key: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.delete_with_options(key, options)
+    }
+
+    /// Delete all keys in `[lower, upper)` of the default column family.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.delete_range(lower, upper)
+    }
+
+    pub fn delete_range_with_options(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.delete_range_with_options(lower, upper, options)
+    }
+
+    /// Delete all keys in `[lower, upper)` of a column family.
+    pub fn delete_range_cf(
+        &self,
+        column_family: &ColumnFamily,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<()> {
+        self.inner.delete_range_cf(column_family, lower, upper)
+    }
+
+    pub fn delete_range_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        lower: &[u8],
+        upper: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner
+            .delete_range_cf_with_options(column_family, lower, upper, options)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -277,10 +678,55 @@ impl MiniLsm {
         self.inner.new_txn()
     }
 
+    /// Pin the latest commit timestamp for consistent reads without starting a transaction.
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.snapshot()
+    }
+
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_with_options(lower, upper, options)
+    }
+
+    /// Create an iterator positioned at the last key in the range, which moves backward with
+    /// `prev`.
+    pub fn scan_rev(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.inner.scan_rev(lower, upper)
+    }
+
+    pub fn scan_rev_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_rev_with_options(lower, upper, options)
+    }
+
+    /// Get a key as of the commit timestamp `ts`, which must be within the retained history.
+    pub fn get_at(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, ts)
+    }
+
+    /// Scan a range of keys as of the commit timestamp `ts`, which must be within the retained
+    /// history.
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_at(lower, upper, ts)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -296,6 +742,47 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    /// Rewrite value logs in which at least `discard_ratio` of the bytes are garbage.
+    pub fn force_value_log_gc(&self, discard_ratio: f64) -> Result<()> {
+        self.inner.force_value_log_gc(discard_ratio)
+    }
+
+    /// Create a consistent copy of the database in `dir`, which can be opened as an independent
+    /// database with all writes committed before the call.
+    pub fn create_checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        self.inner.create_checkpoint(dir)
+    }
+
+    /// How many writes were delayed or blocked because flushes or compactions fell behind.
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        self.inner.write_stall_stats()
+    }
+
+    /// The error of a failed background flush or compaction. Once set, all writes fail until
+    /// `resume` succeeds.
+    pub fn background_error(&self) -> Option<String> {
+        self.inner.background_error()
+    }
+
+    /// Files left behind by a crash that were deleted, or moved to the `lost` directory with
+    /// `quarantine_orphan_files`, when the database was opened.
+    pub fn orphan_files(&self) -> &[PathBuf] {
+        &self.inner.orphan_files
+    }
+
+    /// The damage found so far in a database opened with `open_read_only_salvage`, including data
+    /// blocks and value log records that failed to be read. `None` if not opened for salvage.
+    pub fn salvage_report(&self) -> Option<SalvageReport> {
+        let report = self.inner.salvage_report.as_ref()?;
+        Some(report.lock().clone())
+    }
+
+    /// Retry the failed background work after its cause has been fixed, and accept writes again if
+    /// it succeeds.
+    pub fn resume(&self) -> Result<()> {
+        self.inner.resume()
+    }
 }
 
 impl LsmStorageInner {
@@ -312,16 +799,26 @@ impl LsmStorageInner {
         self.manifest.as_ref().unwrap()
     }
 
-    /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
-    /// not exist.
-    pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
-        let path = path.as_ref();
-        let mut next_sst_id = 1;
-        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
-        let manifest;
+    /// Whether the storage is opened with `open_read_only_salvage`.
+    pub(crate) fn read_only(&self) -> bool {
+        self.salvage_report.is_some()
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only() {
+            bail!("storage is opened read-only");
+        }
+        if let Some(error) = self.background_error() {
+            bail!(
+                "writes are stopped by a background error until resumed: {}",
+                error
+            );
+        }
+        Ok(())
+    }
 
-        let compaction_controller = match &options.compaction_options {
+    fn new_compaction_controller(compaction_options: &CompactionOptions) -> CompactionController {
+        match compaction_options {
             CompactionOptions::Leveled(options) => {
                 CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
             }
@@ -332,136 +829,682 @@ impl LsmStorageInner {
                 SimpleLeveledCompactionController::new(options.clone()),
             ),
             CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
+    /// Open the value logs `sst` refers to that are not in `value_logs` yet, and attach them to it.
+    /// If `salvage`, a value log that cannot be opened is reported instead of failing.
+    fn attach_value_logs(
+        path: &Path,
+        sst: &mut SsTable,
+        value_logs: &mut HashMap<usize, Arc<ValueLogFile>>,
+        salvage: Option<&Arc<Mutex<SalvageReport>>>,
+    ) -> Result<()> {
+        for file_id in sst.value_log_refs().keys() {
+            if !value_logs.contains_key(file_id) {
+                let value_log =
+                    ValueLogFile::open(*file_id, Self::path_of_vlog_static(path, *file_id))
+                        .context("failed to open value log");
+                match (value_log, salvage) {
+                    (Ok(value_log), _) => {
+                        value_logs.insert(*file_id, Arc::new(value_log));
+                    }
+                    (Err(e), Some(report)) => report.lock().add_corrupted_value_log(*file_id, &e),
+                    (Err(e), None) => return Err(e),
+                }
+            }
+        }
+        sst.attach_value_logs(value_logs)
+    }
+
+    /// Open an SST and the value logs it refers to. If `salvage`, damage in the SST and its value
+    /// logs that still leaves it readable is added to the report, now or when it is read.
+    pub(crate) fn open_sst(
+        path: &Path,
+        table_id: usize,
+        block_cache: &Arc<BlockCache>,
+        value_logs: &mut HashMap<usize, Arc<ValueLogFile>>,
+        salvage: Option<&Arc<Mutex<SalvageReport>>>,
+    ) -> Result<SsTable> {
+        let file = FileObject::open(&Self::path_of_sst_static(path, table_id))
+            .context("failed to open SST")?;
+        let mut sst = match salvage {
+            Some(report) => {
+                SsTable::open_salvage(table_id, Some(block_cache.clone()), file, report.clone())?
+            }
+            None => SsTable::open(table_id, Some(block_cache.clone()), file)?,
         };
+        Self::attach_value_logs(path, &mut sst, value_logs, salvage)?;
+        Ok(sst)
+    }
+
+    /// Replay the manifest records of one column family and open its SSTs. Returns the ids of the
+    /// memtables that have not been flushed.
+    #[allow(clippy::too_many_arguments)]
+    fn recover_state(
+        path: &Path,
+        state: &mut LsmStorageState,
+        compaction_controller: &CompactionController,
+        records: Vec<ManifestRecord>,
+        block_cache: &Arc<BlockCache>,
+        table_cache: Option<&Arc<TableCache>>,
+        value_logs: &mut HashMap<usize, Arc<ValueLogFile>>,
+        next_sst_id: &mut usize,
+        last_commit_ts: &mut u64,
+        salvage: Option<&Arc<Mutex<SalvageReport>>>,
+    ) -> Result<BTreeSet<usize>> {
+        let mut memtables = BTreeSet::new();
+        let mut sst_metas = HashMap::new();
+        for record in records {
+            match record {
+                ManifestRecord::NewSsts(metas) => {
+                    sst_metas.extend(metas.into_iter().map(|meta| (meta.id, meta)));
+                }
+                ManifestRecord::Flush(sst_id) => {
+                    let res = memtables.remove(&sst_id);
+                    assert!(res, "memtable not exist?");
+                    if compaction_controller.flush_to_l0() {
+                        state.l0_sstables.insert(0, sst_id);
+                    } else {
+                        state.levels.insert(0, (sst_id, vec![sst_id]));
+                    }
+                    *next_sst_id = (*next_sst_id).max(sst_id);
+                }
+                ManifestRecord::NewMemtable(x) => {
+                    *next_sst_id = (*next_sst_id).max(x);
+                    memtables.insert(x);
+                }
+                ManifestRecord::Compaction(task, output) => {
+                    if let CompactionTask::ForceFullCompaction {
+                        l0_sstables,
+                        l1_sstables,
+                    } = &task
+                    {
+                        apply_full_compaction_result(state, l0_sstables, l1_sstables, &output);
+                    } else {
+                        let (new_state, _) = compaction_controller
+                            .apply_compaction_result(state, &task, &output, true);
+                        // TODO: apply remove again
+                        *state = new_state;
+                    }
+                    *next_sst_id =
+                        (*next_sst_id).max(output.iter().max().copied().unwrap_or_default());
+                }
+                ManifestRecord::TrivialMove(task) => {
+                    let moved = task.trivially_moved_ssts().unwrap().to_vec();
+                    let (new_state, _) =
+                        compaction_controller.apply_compaction_result(state, &task, &moved, true);
+                    *state = new_state;
+                }
+                ManifestRecord::ValueLogGc {
+                    input_ssts,
+                    output_ssts,
+                } => {
+                    state.replace_sstables(&input_ssts, &output_ssts);
+                    *next_sst_id =
+                        (*next_sst_id).max(output_ssts.iter().max().copied().unwrap_or_default());
+                }
+                ManifestRecord::Snapshot {
+                    l0_sstables,
+                    levels,
+                    last_commit_ts: commit_ts,
+                    ssts,
+                } => {
+                    sst_metas.extend(ssts.into_iter().map(|meta| (meta.id, meta)));
+                    let max_sst_id = l0_sstables
+                        .iter()
+                        .chain(levels.iter().flat_map(|(_, ssts)| ssts))
+                        .max()
+                        .copied()
+                        .unwrap_or_default();
+                    *next_sst_id = (*next_sst_id).max(max_sst_id);
+                    *last_commit_ts = (*last_commit_ts).max(commit_ts);
+                    state.l0_sstables = l0_sstables;
+                    state.levels = levels;
+                }
+                ManifestRecord::CreateColumnFamily { .. } | ManifestRecord::ColumnFamily(..) => {
+                    unreachable!("column family records are dispatched by the caller")
+                }
+            }
+        }
+
+        let mut sst_cnt = 0;
+        // recover SSTs
+        let table_ids = state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut skipped = HashSet::new();
+        for table_id in table_ids {
+            // With a table cache, only a lightweight handle of the SST is kept, from the metadata
+            // in the manifest if it was recorded. Damaged SSTs are kept open when salvaging.
+            if let (Some(table_cache), Some(meta), None) =
+                (table_cache, sst_metas.get(&table_id), &salvage)
+            {
+                let mut sst = SsTable::open_lazy(
+                    meta,
+                    Self::path_of_sst_static(path, table_id),
+                    Some(block_cache.clone()),
+                    table_cache.clone(),
+                );
+                Self::attach_value_logs(path, &mut sst, value_logs, None)?;
+                *last_commit_ts = (*last_commit_ts).max(sst.max_ts());
+                state.sstables.insert(table_id, Arc::new(sst));
+                continue;
+            }
+            let sst = match Self::open_sst(path, table_id, block_cache, value_logs, salvage) {
+                Ok(sst) => sst,
+                Err(e) => match salvage {
+                    Some(report) => {
+                        report
+                            .lock()
+                            .skipped_ssts
+                            .push((table_id, format!("{:#}", e)));
+                        skipped.insert(table_id);
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+            };
+            *last_commit_ts = (*last_commit_ts).max(sst.max_ts());
+            let sst = match (table_cache, &salvage) {
+                (Some(table_cache), None) => {
+                    let mut handle = SsTable::open_lazy(
+                        &sst.meta(),
+                        Self::path_of_sst_static(path, table_id),
+                        Some(block_cache.clone()),
+                        table_cache.clone(),
+                    );
+                    handle.attach_value_logs(sst.value_logs())?;
+                    handle
+                }
+                _ => sst,
+            };
+            state.sstables.insert(table_id, Arc::new(sst));
+            sst_cnt += 1;
+        }
+        if !skipped.is_empty() {
+            state.l0_sstables.retain(|id| !skipped.contains(id));
+            for (_, ssts) in &mut state.levels {
+                ssts.retain(|id| !skipped.contains(id));
+            }
+        }
+        println!("{} SSTs opened", sst_cnt);
+
+        // Sort SSTs on each level (only for leveled compaction)
+        if let CompactionController::Leveled(_) = compaction_controller {
+            for (_id, ssts) in &mut state.levels {
+                ssts.sort_by(|x, y| {
+                    state
+                        .sstables
+                        .get(x)
+                        .unwrap()
+                        .first_key()
+                        .cmp(state.sstables.get(y).unwrap().first_key())
+                })
+            }
+        }
+        Ok(memtables)
+    }
+
+    /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
+    /// not exist.
+    pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        Self::open_inner(path.as_ref(), options, None)
+    }
+
+    /// Open a possibly damaged database without modifying any file. Corrupted SSTs, bloom filters
+    /// and WAL batches are skipped and listed in the returned report, and all writes fail. Blocks
+    /// and value logs are checked when they are read.
+    pub(crate) fn open_read_only_salvage(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<(Self, SalvageReport)> {
+        let report = Arc::new(Mutex::new(SalvageReport::default()));
+        let storage = Self::open_inner(path.as_ref(), options, Some(report.clone()))?;
+        let report = report.lock().clone();
+        Ok((storage, report))
+    }
+
+    fn open_inner(
+        path: &Path,
+        options: LsmStorageOptions,
+        salvage_report: Option<Arc<Mutex<SalvageReport>>>,
+    ) -> Result<Self> {
+        let salvage = salvage_report.as_ref();
+        options
+            .write_stall
+            .validate(&options.compaction_options, options.num_memtable_limit)?;
+        let read_only = salvage.is_some();
+        let mut state = LsmStorageState::create(&options);
+        let mut next_sst_id = 1;
+        let block_cache = options.new_block_cache();
+        let table_cache = options
+            .max_open_files
+            .map(|max_open_files| Arc::new(TableCache::new(max_open_files)));
+        let manifest;
+        let compaction_controller = Self::new_compaction_controller(&options.compaction_options);
+        // Column families other than the default one: (id, name, options, state)
+        let mut column_families = Vec::new();
 
+        if read_only && !Manifest::exists(path) {
+            bail!("no database found in {}", path.display());
+        }
         if !path.exists() {
             std::fs::create_dir_all(path).context("failed to create DB dir")?;
         }
-        let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
-        if !manifest_path.exists() {
+        if !Manifest::exists(path) {
             if options.enable_wal {
                 state.memtable = Arc::new(MemTable::create_with_wal(
                     state.memtable.id(),
                     Self::path_of_wal_static(path, state.memtable.id()),
                 )?);
             }
-            manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
-            manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            let m = Manifest::create(path).context("failed to create manifest")?;
+            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            manifest = Some(m);
         } else {
-            let (m, records) = Manifest::recover(&manifest_path)?;
-            let mut memtables = BTreeSet::new();
+            let (m, records) = match salvage {
+                Some(report) => {
+                    let (records, truncated) = Manifest::salvage(path)?;
+                    report.lock().manifest_truncated = truncated;
+                    (None, records)
+                }
+                None => {
+                    let (m, records) = Manifest::recover(path)?;
+                    (Some(m), records)
+                }
+            };
+            let mut default_records = Vec::new();
+            let mut column_family_records = BTreeMap::new();
             for record in records {
                 match record {
-                    ManifestRecord::Flush(sst_id) => {
-                        let res = memtables.remove(&sst_id);
-                        assert!(res, "memtable not exist?");
-                        if compaction_controller.flush_to_l0() {
-                            state.l0_sstables.insert(0, sst_id);
-                        } else {
-                            state.levels.insert(0, (sst_id, vec![sst_id]));
-                        }
-                        next_sst_id = next_sst_id.max(sst_id);
-                    }
-                    ManifestRecord::NewMemtable(x) => {
-                        next_sst_id = next_sst_id.max(x);
-                        memtables.insert(x);
+                    ManifestRecord::CreateColumnFamily {
+                        id,
+                        name,
+                        compaction_options,
+                    } => {
+                        column_family_records.insert(id, (name, compaction_options, Vec::new()));
                     }
-                    ManifestRecord::Compaction(task, output) => {
-                        let (new_state, _) = compaction_controller
-                            .apply_compaction_result(&state, &task, &output, true);
-                        // TODO: apply remove again
-                        state = new_state;
-                        next_sst_id =
-                            next_sst_id.max(output.iter().max().copied().unwrap_or_default());
+                    ManifestRecord::ColumnFamily(id, record) => {
+                        let (_, _, records) = column_family_records
+                            .get_mut(&id)
+                            .expect("column family not exist?");
+                        records.push(*record);
                     }
+                    record => default_records.push(record),
                 }
             }
 
-            let mut sst_cnt = 0;
-            // recover SSTs
-            for table_id in state
-                .l0_sstables
-                .iter()
-                .chain(state.levels.iter().flat_map(|(_, files)| files))
-            {
-                let table_id = *table_id;
-                let sst = SsTable::open(
-                    table_id,
-                    Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
-                        .context("failed to open SST")?,
+            let mut value_logs = HashMap::new();
+            let memtables = Self::recover_state(
+                path,
+                &mut state,
+                &compaction_controller,
+                default_records,
+                &block_cache,
+                table_cache.as_ref(),
+                &mut value_logs,
+                &mut next_sst_id,
+                &mut last_commit_ts,
+                salvage,
+            )?;
+            let mut column_family_memtables = Vec::new();
+            for (id, (name, compaction_options, records)) in column_family_records {
+                let cf_options = options.for_column_family(compaction_options);
+                let mut cf_state = LsmStorageState::create(&cf_options);
+                let memtables = Self::recover_state(
+                    path,
+                    &mut cf_state,
+                    &Self::new_compaction_controller(&cf_options.compaction_options),
+                    records,
+                    &block_cache,
+                    table_cache.as_ref(),
+                    &mut value_logs,
+                    &mut next_sst_id,
+                    &mut last_commit_ts,
+                    salvage,
                 )?;
-                last_commit_ts = last_commit_ts.max(sst.max_ts());
-                state.sstables.insert(table_id, Arc::new(sst));
-                sst_cnt += 1;
+                column_family_memtables.push(memtables);
+                column_families.push((id, name, cf_options, cf_state));
             }
-            println!("{} SSTs opened", sst_cnt);
+            println!("{} value logs opened", value_logs.len());
 
             next_sst_id += 1;
 
-            // Sort SSTs on each level (only for leveled compaction)
-            if let CompactionController::Leveled(_) = &compaction_controller {
-                for (_id, ssts) in &mut state.levels {
-                    ssts.sort_by(|x, y| {
-                        state
-                            .sstables
-                            .get(x)
-                            .unwrap()
-                            .first_key()
-                            .cmp(state.sstables.get(y).unwrap().first_key())
-                    })
-                }
-            }
-
             // recover memtables
             if options.enable_wal {
+                // Memtables of the other column families are recovered from the WALs of the default
+                // column family, which they share.
+                let column_family_memtables = column_family_memtables
+                    .iter()
+                    .map(|ids| {
+                        ids.iter()
+                            .map(|id| Arc::new(MemTable::create(*id)))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                let shared_memtables = column_family_memtables
+                    .iter()
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>();
                 let mut wal_cnt = 0;
+                // Whether records were dropped from a WAL in point-in-time recovery, so that the
+                // later WALs are dropped as well.
+                let mut wal_cut = false;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
-                    if !memtable.is_empty() {
+                    // An empty memtable frozen only to rotate the shared WAL is dropped together
+                    // with its WAL without writing a flush record.
+                    if !Self::path_of_wal_static(path, *id).exists() {
+                        continue;
+                    }
+                    if wal_cut {
+                        Wal::discard(Self::path_of_wal_static(path, *id))?;
+                    }
+                    let (memtable, shared) = match salvage {
+                        Some(report) => {
+                            let (memtable, corrupted) = MemTable::salvage_from_shared_wal(
+                                *id,
+                                Self::path_of_wal_static(path, *id),
+                                &shared_memtables,
+                            )?;
+                            if corrupted {
+                                report.lock().corrupted_wals.push(*id);
+                            }
+                            // Nothing is written in read-only mode, so the WAL needs no owner.
+                            (memtable, false)
+                        }
+                        None => {
+                            let (memtable, dropped, shared) = MemTable::recover_from_shared_wal(
+                                *id,
+                                Self::path_of_wal_static(path, *id),
+                                &shared_memtables,
+                                options.wal_recovery_mode,
+                            )?;
+                            wal_cut |= dropped
+                                && options.wal_recovery_mode == WalRecoveryMode::PointInTime;
+                            (memtable, shared)
+                        }
+                    };
+                    last_commit_ts = last_commit_ts.max(memtable.max_ts());
+                    // An empty memtable still owns the WAL if other column families logged to it.
+                    if !memtable.is_empty() || shared {
                         state.imm_memtables.insert(0, Arc::new(memtable));
                         wal_cnt += 1;
+                    } else if m.is_some() {
+                        // Delete the WAL of a dropped memtable here rather than reporting it as an
+                        // orphan file.
+                        std::fs::remove_file(Self::path_of_wal_static(path, *id))?;
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
-            } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                for ((_, _, _, cf_state), memtables) in
+                    column_families.iter_mut().zip(column_family_memtables)
+                {
+                    for memtable in memtables {
+                        last_commit_ts = last_commit_ts.max(memtable.max_ts());
+                        if !memtable.is_empty() {
+                            cf_state.imm_memtables.insert(0, memtable);
+                        }
+                    }
+                }
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
-            next_sst_id += 1;
-            manifest = m;
+            // Nothing is written in read-only mode, so the new memtables are neither logged nor
+            // recorded in the manifest.
+            state.memtable = match &m {
+                Some(m) => {
+                    m.add_record_when_init(ManifestRecord::NewMemtable(next_sst_id))?;
+                    if options.enable_wal {
+                        Arc::new(MemTable::create_with_wal(
+                            next_sst_id,
+                            Self::path_of_wal_static(path, next_sst_id),
+                        )?)
+                    } else {
+                        Arc::new(MemTable::create(next_sst_id))
+                    }
+                }
+                None => Arc::new(MemTable::create(next_sst_id)),
+            };
+            next_sst_id += 1;
+            for (id, _, _, cf_state) in &mut column_families {
+                cf_state.memtable = Arc::new(MemTable::create(next_sst_id));
+                if let Some(m) = &m {
+                    m.for_column_family(*id)
+                        .add_record_when_init(ManifestRecord::NewMemtable(next_sst_id))?;
+                }
+                next_sst_id += 1;
+            }
+            manifest = m;
         };
 
-        let storage = Self {
+        let subcompaction_pool = if options.max_subcompactions > 1 {
+            Some(Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(options.max_subcompactions)
+                    .thread_name(|idx| format!("subcompaction-{}", idx))
+                    .build()
+                    .context("failed to create subcompaction thread pool")?,
+            ))
+        } else {
+            None
+        };
+        let mut storage = Self {
             state: Arc::new(RwLock::new(Arc::new(state))),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
             block_cache,
-            next_sst_id: AtomicUsize::new(next_sst_id),
+            table_cache,
+            next_sst_id: Arc::new(AtomicUsize::new(next_sst_id)),
             compaction_controller,
-            manifest: Some(manifest),
+            manifest,
+            mvcc: Some(Arc::new(LsmMvccInner::new(
+                last_commit_ts,
+                options.history_retention,
+            ))),
             options: options.into(),
-            mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            compaction_lock: Mutex::new(()),
+            subcompaction_pool,
+            column_family_id: DEFAULT_COLUMN_FAMILY_ID,
+            column_families: RwLock::new(BTreeMap::new()),
+            obsolete_files: Arc::new(ObsoleteFiles::new(path)),
+            salvage_report,
+            background_error: Arc::new(Mutex::new(None)),
+            write_stall_counters: Arc::new(WriteStallCounters::default()),
+            write_stall_signal: Arc::new(WriteStallSignal::default()),
+            wal_sync: Mutex::new(WalSyncState::new()),
+            write_queue: WriteQueue::default(),
+            orphan_files: Vec::new(),
         };
+        for (id, name, cf_options, cf_state) in column_families {
+            storage.add_column_family(id, name, cf_options, cf_state);
+        }
+        if read_only {
+            return Ok(storage);
+        }
+        storage.orphan_files = storage.remove_orphan_files()?;
+        storage.maybe_rotate_manifest()?;
         storage.sync_dir()?;
 
         Ok(storage)
     }
 
+    /// A manifest record describing the SSTs of this column family in `state`.
+    pub(crate) fn snapshot_record(&self, state: &LsmStorageState) -> ManifestRecord {
+        ManifestRecord::Snapshot {
+            l0_sstables: state.l0_sstables.clone(),
+            levels: state.levels.clone(),
+            last_commit_ts: self.mvcc().latest_commit_ts(),
+            ssts: state.sstables.values().map(|sst| sst.meta()).collect(),
+        }
+    }
+
+    /// The records that restore the current LSM structure and memtables of this column family.
+    fn manifest_snapshot(&self) -> Vec<ManifestRecord> {
+        let state = self.state.read();
+        let mut records = vec![self.snapshot_record(&state)];
+        for memtable in state.imm_memtables.iter().rev() {
+            records.push(ManifestRecord::NewMemtable(memtable.id()));
+        }
+        records.push(ManifestRecord::NewMemtable(state.memtable.id()));
+        records
+    }
+
+    /// Replace the manifest with snapshots of all column families if it has grown larger than
+    /// `max_manifest_size`, so that it does not have to replay the whole history on open, see
+    /// `Manifest::needs_rotation`.
+    pub(crate) fn maybe_rotate_manifest(&self) -> Result<()> {
+        // The manifest is shared by all column families and rotated by the default one.
+        if self.read_only()
+            || self.column_family_id != DEFAULT_COLUMN_FAMILY_ID
+            || !self
+                .manifest()
+                .needs_rotation(self.options.max_manifest_size)
+        {
+            return Ok(());
+        }
+        let state_lock = self.state_lock.lock();
+        let column_families = self.column_families.read();
+        // No records can be added while holding the state locks of all column families.
+        let _column_family_locks = column_families
+            .values()
+            .map(|column_family| column_family.inner.state_lock.lock())
+            .collect::<Vec<_>>();
+        let mut records = self.manifest_snapshot();
+        for column_family in column_families.values() {
+            let inner = &column_family.inner;
+            records.push(ManifestRecord::CreateColumnFamily {
+                id: column_family.id,
+                name: column_family.name.clone(),
+                compaction_options: inner.options.compaction_options.clone(),
+            });
+            records.extend(
+                inner
+                    .manifest_snapshot()
+                    .into_iter()
+                    .map(|record| ManifestRecord::ColumnFamily(column_family.id, Box::new(record))),
+            );
+        }
+        self.manifest().rotate(&state_lock, &records)?;
+        println!("manifest rotated with {} records", records.len());
+        Ok(())
+    }
+
+    /// Register a column family sharing the manifest, WAL, timestamps and caches of this storage.
+    fn add_column_family(
+        &self,
+        id: usize,
+        name: String,
+        options: LsmStorageOptions,
+        state: LsmStorageState,
+    ) -> ColumnFamily {
+        let inner = Self {
+            state: Arc::new(RwLock::new(Arc::new(state))),
+            state_lock: Mutex::new(()),
+            path: self.path.clone(),
+            block_cache: self.block_cache.clone(),
+            table_cache: self.table_cache.clone(),
+            next_sst_id: self.next_sst_id.clone(),
+            compaction_controller: Self::new_compaction_controller(&options.compaction_options),
+            manifest: self
+                .manifest
+                .as_ref()
+                .map(|manifest| manifest.for_column_family(id)),
+            options: options.into(),
+            mvcc: self.mvcc.clone(),
+            compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            compaction_lock: Mutex::new(()),
+            subcompaction_pool: self.subcompaction_pool.clone(),
+            column_family_id: id,
+            column_families: RwLock::new(BTreeMap::new()),
+            obsolete_files: self.obsolete_files.clone(),
+            salvage_report: self.salvage_report.clone(),
+            background_error: self.background_error.clone(),
+            write_stall_counters: self.write_stall_counters.clone(),
+            write_stall_signal: self.write_stall_signal.clone(),
+            // Column families share the WAL of the default one, which syncs it.
+            wal_sync: Mutex::new(WalSyncState::new()),
+            write_queue: WriteQueue::default(),
+            orphan_files: Vec::new(),
+        };
+        let column_family = ColumnFamily {
+            id,
+            name,
+            inner: Arc::new(inner),
+        };
+        self.column_families
+            .write()
+            .insert(id, column_family.clone());
+        column_family
+    }
+
+    /// Create a column family with its own compaction strategy.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        compaction_options: CompactionOptions,
+    ) -> Result<ColumnFamily> {
+        assert_eq!(self.column_family_id, DEFAULT_COLUMN_FAMILY_ID);
+        self.check_writable()?;
+        let state_lock = self.state_lock.lock();
+        if name == DEFAULT_COLUMN_FAMILY_NAME || self.column_family(name).is_some() {
+            bail!("column family {} already exists", name);
+        }
+        let id = self
+            .column_families
+            .read()
+            .keys()
+            .max()
+            .map_or(DEFAULT_COLUMN_FAMILY_ID, |id| *id)
+            + 1;
+        let options = self.options.for_column_family(compaction_options.clone());
+        options
+            .write_stall
+            .validate(&options.compaction_options, options.num_memtable_limit)?;
+        let mut state = LsmStorageState::create(&options);
+        state.memtable = Arc::new(MemTable::create(self.next_sst_id()));
+        self.manifest().add_record(
+            &state_lock,
+            ManifestRecord::CreateColumnFamily {
+                id,
+                name: name.to_string(),
+                compaction_options,
+            },
+        )?;
+        self.manifest().for_column_family(id).add_record(
+            &state_lock,
+            ManifestRecord::NewMemtable(state.memtable.id()),
+        )?;
+        Ok(self.add_column_family(id, name.to_string(), options, state))
+    }
+
+    /// Get a column family other than the default one by name.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamily> {
+        self.column_families
+            .read()
+            .values()
+            .find(|cf| cf.name == name)
+            .cloned()
+    }
+
+    pub fn default_column_family(self: &Arc<Self>) -> ColumnFamily {
+        ColumnFamily {
+            id: self.column_family_id,
+            name: DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+            inner: self.clone(),
+        }
+    }
+
+    /// Whether memtables of this storage write to their own WAL. Memtables of column families
+    /// other than the default one share the WAL of the default column family instead.
+    pub(crate) fn owns_wal(&self) -> bool {
+        self.options.enable_wal && self.column_family_id == DEFAULT_COLUMN_FAMILY_ID
+    }
+
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
@@ -477,7 +1520,35 @@ impl LsmStorageInner {
         txn.get(key)
     }
 
-    pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+    pub fn get_with_options(
+        self: &Arc<Self>,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        let txn = self.new_txn_for_read(options)?;
+        txn.get_with_options(key, options)
+    }
+
+    /// Create the transaction a read with `options` is served by.
+    fn new_txn_for_read(self: &Arc<Self>, options: &ReadOptions) -> Result<Arc<Transaction>> {
+        match options.read_ts {
+            Some(ts) => self.mvcc().new_txn_at(self.clone(), ts, false),
+            None => Ok(self.mvcc().new_txn(self.clone(), self.options.serializable)),
+        }
+    }
+
+    /// Get a key as of an older commit timestamp.
+    pub fn get_at(self: &Arc<Self>, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        let txn = self.mvcc().new_txn_at(self.clone(), ts, false)?;
+        txn.get(key)
+    }
+
+    pub(crate) fn get_with_ts(
+        &self,
+        key: &[u8],
+        read_ts: u64,
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
@@ -498,29 +1569,24 @@ impl LsmStorageInner {
 
         let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
 
-        let keep_table = |key: &[u8], table: &SsTable| {
+        let keep_table = |key: &[u8], table: &Arc<SsTable>| -> Result<bool> {
             if key_within(
                 key,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
             ) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        return true;
-                    }
-                } else {
-                    return true;
-                }
+                return table.open_table()?.may_contain(key);
             }
-            false
+            Ok(false)
         };
 
         for table in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table].clone();
-            if keep_table(key, &table) {
-                l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
+            if keep_table(key, &table)? {
+                l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key_for_get(
                     table,
                     KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    options.clone(),
                 )?));
             }
         }
@@ -530,13 +1596,14 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if keep_table(key, &table) {
+                if keep_table(key, &table)? {
                     level_ssts.push(table);
                 }
             }
-            let level_iter = SstConcatIterator::create_and_seek_to_key(
+            let level_iter = SstConcatIterator::create_and_seek_to_key_for_get(
                 level_ssts,
                 KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                options.clone(),
             )?;
             level_iters.push(Box::new(level_iter));
         }
@@ -547,7 +1614,9 @@ impl LsmStorageInner {
                 MergeIterator::create(level_iters),
             )?,
             Bound::Unbounded,
+            Bound::Unbounded,
             read_ts,
+            snapshot.range_tombstones(read_ts, Bound::Included(key), Bound::Included(key))?,
         )?;
 
         if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
@@ -556,47 +1625,52 @@ impl LsmStorageInner {
         Ok(None)
     }
 
-    pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
-        let ts = self.mvcc().latest_commit_ts() + 1;
+    pub fn write_batch_inner<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<u64> {
+        self.check_writable()?;
+        self.stall_write(options.no_slowdown)?;
+        let mut entries = Vec::with_capacity(batch.len());
         for record in batch {
             match record {
                 WriteBatchRecord::Del(key) => {
                     let key = key.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
-                        size = guard.memtable.approximate_size();
-                    }
-                    self.try_freeze(size)?;
+                    entries.push((Bytes::copy_from_slice(key), Bytes::new()));
                 }
                 WriteBatchRecord::Put(key, value) => {
                     let key = key.as_ref();
                     let value = value.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
                     assert!(!value.is_empty(), "value cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
-                        size = guard.memtable.approximate_size();
-                    }
-                    self.try_freeze(size)?;
+                    entries.push((Bytes::copy_from_slice(key), Bytes::copy_from_slice(value)));
                 }
             }
         }
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        // The whole batch is appended to the WAL as one record, so that it is recovered atomically.
+        self.commit_write(PendingWrite {
+            entries,
+            sync: options.sync,
+            disable_wal: options.disable_wal,
+        })
     }
 
     pub fn write_batch<T: AsRef<[u8]>>(
         self: &Arc<Self>,
         batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.write_batch_with_options(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
     ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(batch)?;
+            self.write_batch_inner(batch, options)?;
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
             for record in batch {
@@ -609,36 +1683,332 @@ impl LsmStorageInner {
                     }
                 }
             }
-            txn.commit()?;
+            txn.commit_with_options(options)?;
         }
         Ok(())
     }
 
+    /// Atomically write to the memtables of several column families. With the WAL enabled, the
+    /// whole batch is logged as one record in the WAL of the default column family.
+    pub(crate) fn write_batch_cf_inner<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+        options: &WriteOptions,
+    ) -> Result<u64> {
+        self.check_writable()?;
+        let mut stalled = BTreeSet::new();
+        for (column_family, _) in batch {
+            if stalled.insert(column_family.id) {
+                column_family.inner.stall_write(options.no_slowdown)?;
+            }
+        }
+        let lck = self.mvcc().write_lock.lock();
+        let ts = self.mvcc().latest_commit_ts() + 1;
+        let mut column_families = BTreeMap::new();
+        if self.owns_wal() {
+            column_families.insert(self.column_family_id, self);
+        }
+        let mut entries = Vec::with_capacity(batch.len());
+        for (column_family, record) in batch {
+            column_families.insert(column_family.id, column_family.inner.as_ref());
+            match record {
+                WriteBatchRecord::Del(key) => {
+                    let key = key.as_ref();
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    entries.push((column_family.id, KeySlice::from_slice(key, ts), &b""[..]));
+                }
+                WriteBatchRecord::Put(key, value) => {
+                    let key = key.as_ref();
+                    let value = value.as_ref();
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    assert!(!value.is_empty(), "value cannot be empty");
+                    entries.push((column_family.id, KeySlice::from_slice(key, ts), value));
+                }
+            }
+        }
+        let sizes = {
+            // Lock the states in the order of column family ids, so that no memtable is frozen
+            // while the batch is being written.
+            let guards = column_families
+                .iter()
+                .map(|(id, inner)| (*id, inner.state.read()))
+                .collect::<BTreeMap<_, _>>();
+            if self.owns_wal() && !options.disable_wal {
+                let data = entries
+                    .iter()
+                    .map(|(id, key, value)| (guards[id].memtable.id(), *key, *value))
+                    .collect::<Vec<_>>();
+                guards[&self.column_family_id]
+                    .memtable
+                    .wal()
+                    .expect("no WAL in the default column family")
+                    .put_batch_tagged(&data)?;
+            }
+            let mut sizes = Vec::with_capacity(guards.len());
+            for (id, guard) in &guards {
+                let data = entries
+                    .iter()
+                    .filter(|(column_family, _, _)| column_family == id)
+                    .map(|(_, key, value)| (*key, *value))
+                    .collect::<Vec<_>>();
+                if !data.is_empty() {
+                    guard.memtable.put_batch_without_wal(&data);
+                    sizes.push((*id, guard.memtable.approximate_size()));
+                }
+            }
+            sizes
+        };
+        if !options.disable_wal {
+            self.sync_wal_for_writes(1, options.sync)?;
+        }
+        for (id, size) in sizes {
+            self.try_freeze_column_family(column_families[&id], size)?;
+        }
+        self.mvcc().update_commit_ts(ts);
+        drop(lck);
+        Ok(ts)
+    }
+
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        self.write_batch_cf_with_options(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_cf_with_options<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[(&ColumnFamily, WriteBatchRecord<T>)],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        if !self.options.serializable {
+            self.write_batch_cf_inner(batch, options)?;
+        } else {
+            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            for (column_family, record) in batch {
+                match record {
+                    WriteBatchRecord::Del(key) => {
+                        txn.delete_cf(column_family, key.as_ref());
+                    }
+                    WriteBatchRecord::Put(key, value) => {
+                        txn.put_cf(column_family, key.as_ref(), value.as_ref());
+                    }
+                }
+            }
+            txn.commit_with_options(options)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_cf(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        key: &[u8],
+    ) -> Result<Option<Bytes>> {
+        self.get_cf_with_options(column_family, key, &ReadOptions::default())
+    }
+
+    pub fn get_cf_with_options(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        let txn = self.new_txn_for_read(options)?;
+        txn.get_cf_with_options(column_family, key, options)
+    }
+
+    pub fn put_cf(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        self.put_cf_with_options(column_family, key, value, &WriteOptions::default())
+    }
+
+    pub fn put_cf_with_options(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.write_batch_cf_with_options(
+            &[(column_family, WriteBatchRecord::Put(key, value))],
+            options,
+        )
+    }
+
+    pub fn delete_cf(self: &Arc<Self>, column_family: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.delete_cf_with_options(column_family, key, &WriteOptions::default())
+    }
+
+    pub fn delete_cf_with_options(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.write_batch_cf_with_options(&[(column_family, WriteBatchRecord::Del(key))], options)
+    }
+
+    pub fn scan_cf(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_cf_with_options(column_family, lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_cf_with_options(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let txn = self.new_txn_for_read(options)?;
+        txn.scan_cf_with_options(column_family, lower, upper, options)
+    }
+
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(self: &Arc<Self>, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_with_options(key, value, &WriteOptions::default())
+    }
+
+    pub fn put_with_options(
+        self: &Arc<Self>,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Put(key, value)])?;
+            self.write_batch_inner(&[WriteBatchRecord::Put(key, value)], options)?;
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
             txn.put(key, value);
-            txn.commit()?;
+            txn.commit_with_options(options)?;
         }
         Ok(())
     }
 
     /// Remove a key from the storage by writing an empty value.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
+        self.delete_with_options(key, &WriteOptions::default())
+    }
+
+    pub fn delete_with_options(self: &Arc<Self>, key: &[u8], options: &WriteOptions) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Del(key)])?;
+            self.write_batch_inner(&[WriteBatchRecord::Del(key)], options)?;
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
             txn.delete(key);
-            txn.commit()?;
+            txn.commit_with_options(options)?;
+        }
+        Ok(())
+    }
+
+    /// Delete all keys in `[lower, upper)` by writing a single range tombstone. The tombstone is
+    /// not part of a transaction, so it is not checked for conflicts with serializable
+    /// transactions.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.delete_range_with_options(lower, upper, &WriteOptions::default())
+    }
+
+    pub fn delete_range_with_options(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if self.column_family_id != DEFAULT_COLUMN_FAMILY_ID {
+            bail!("range deletion is only supported in the default column family");
+        }
+        if lower >= upper {
+            bail!("lower bound of the range must be smaller than the upper bound");
         }
+        self.stall_write(options.no_slowdown)?;
+        let lck = self.mvcc().write_lock.lock();
+        let ts = self.mvcc().latest_commit_ts() + 1;
+        let size;
+        {
+            let guard = self.state.read();
+            let start = KeySlice::from_slice(lower, ts);
+            if options.disable_wal {
+                guard.memtable.delete_range_without_wal(start, upper);
+            } else {
+                guard.memtable.delete_range(start, upper)?;
+            }
+            size = guard.memtable.approximate_size();
+        }
+        if !options.disable_wal {
+            self.sync_wal_for_writes(1, options.sync)?;
+        }
+        self.try_freeze(size)?;
+        self.mvcc().update_commit_ts(ts);
+        drop(lck);
+        Ok(())
+    }
+
+    pub fn delete_range_cf(
+        &self,
+        column_family: &ColumnFamily,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<()> {
+        self.delete_range_cf_with_options(column_family, lower, upper, &WriteOptions::default())
+    }
+
+    /// Delete all keys in `[lower, upper)` of a column family, see `delete_range`. The range
+    /// tombstone is logged to the WAL of the default column family, which is shared by all of them.
+    pub fn delete_range_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        lower: &[u8],
+        upper: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        if column_family.id == self.column_family_id {
+            return self.delete_range_with_options(lower, upper, options);
+        }
+        self.check_writable()?;
+        if lower >= upper {
+            bail!("lower bound of the range must be smaller than the upper bound");
+        }
+        let inner = column_family.inner.as_ref();
+        inner.stall_write(options.no_slowdown)?;
+        let lck = self.mvcc().write_lock.lock();
+        let ts = self.mvcc().latest_commit_ts() + 1;
+        let start = KeySlice::from_slice(lower, ts);
+        let size = {
+            // Lock the states in the order of column family ids, see `write_batch_cf_inner`.
+            let guard = self.state.read();
+            let column_family_guard = inner.state.read();
+            if self.owns_wal() && !options.disable_wal {
+                guard
+                    .memtable
+                    .wal()
+                    .expect("no WAL in the default column family")
+                    .put_range_tombstone_tagged(column_family_guard.memtable.id(), start, upper)?;
+            }
+            column_family_guard
+                .memtable
+                .delete_range_without_wal(start, upper);
+            column_family_guard.memtable.approximate_size()
+        };
+        if !options.disable_wal {
+            self.sync_wal_for_writes(1, options.sync)?;
+        }
+        self.try_freeze_column_family(inner, size)?;
+        self.mvcc().update_commit_ts(ts);
+        drop(lck);
         Ok(())
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
+    pub(crate) fn try_freeze(&self, estimated_size: usize) -> Result<()> {
         if estimated_size >= self.options.target_sst_size {
             let state_lock = self.state_lock.lock();
             let guard = self.state.read();
@@ -651,6 +2021,64 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Freeze the memtable of a column family if it is full. With the WAL enabled, all column
+    /// families are frozen together by the default column family, which owns the WAL.
+    fn try_freeze_column_family(
+        &self,
+        column_family: &LsmStorageInner,
+        estimated_size: usize,
+    ) -> Result<()> {
+        if !self.owns_wal() || column_family.column_family_id == self.column_family_id {
+            return column_family.try_freeze(estimated_size);
+        }
+        if estimated_size >= column_family.options.target_sst_size {
+            let state_lock = self.state_lock.lock();
+            let guard = column_family.state.read();
+            if guard.memtable.approximate_size() >= column_family.options.target_sst_size {
+                drop(guard);
+                self.force_freeze_memtable(&state_lock)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create an SST builder that follows the table options of this storage.
+    pub(crate) fn new_sst_builder(&self) -> SsTableBuilder {
+        SsTableBuilder::new(self.options.block_size)
+            .with_compression(self.options.compression)
+            .with_block_hash_index(self.options.block_hash_index)
+            .with_partitioned_index(self.options.partitioned_index)
+    }
+
+    /// Add a newly-built SST to the table cache if there is one, and return the SST to keep in the
+    /// LSM state, which is a lightweight handle of it in that case.
+    pub(crate) fn cache_new_sst(&self, sst: SsTable) -> Result<Arc<SsTable>> {
+        let Some(table_cache) = &self.table_cache else {
+            return Ok(Arc::new(sst));
+        };
+        let mut handle = SsTable::open_lazy(
+            &sst.meta(),
+            self.path_of_sst(sst.sst_id()),
+            Some(self.block_cache.clone()),
+            table_cache.clone(),
+        );
+        handle.attach_value_logs(sst.value_logs())?;
+        table_cache.insert(Arc::new(sst));
+        Ok(Arc::new(handle))
+    }
+
+    /// Create an SST builder for `sst_id` that moves large values into a value log of the same id
+    /// if value separation is enabled.
+    pub(crate) fn new_sst_builder_with_value_log(&self, sst_id: usize) -> SsTableBuilder {
+        let builder = self.new_sst_builder();
+        match self.options.value_separation_threshold {
+            Some(threshold) => {
+                builder.with_value_separation(threshold, sst_id, self.path_of_vlog(sst_id))
+            }
+            None => builder,
+        }
+    }
+
     pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.sst", id))
     }
@@ -659,6 +2087,14 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
+    pub(crate) fn path_of_vlog_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.vlog", id))
+    }
+
+    pub(crate) fn path_of_vlog(&self, id: usize) -> PathBuf {
+        Self::path_of_vlog_static(&self.path, id)
+    }
+
     pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
@@ -690,8 +2126,9 @@ impl LsmStorageInner {
 
     /// Force freeze the current memtable to an immutable memtable
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
+        self.check_writable()?;
         let memtable_id = self.next_sst_id();
-        let memtable = if self.options.enable_wal {
+        let memtable = if self.owns_wal() {
             Arc::new(MemTable::create_with_wal(
                 memtable_id,
                 self.path_of_wal(memtable_id),
@@ -708,14 +2145,82 @@ impl LsmStorageInner {
         )?;
         self.sync_dir()?;
 
+        // The memtables of the other column families are logged in the WAL just frozen, so they
+        // are frozen as well. See `flush_column_families_before`.
+        if self.owns_wal() {
+            for column_family in self.column_families.read().values() {
+                let inner = &column_family.inner;
+                let state_lock = inner.state_lock.lock();
+                if !inner.state.read().memtable.is_empty() {
+                    inner.force_freeze_memtable(&state_lock)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush the immutable memtables of the other column families created before the memtable
+    /// `memtable_id` of the default column family. All writes logged in the WALs older than
+    /// `memtable_id` are in these memtables, as they are frozen together with the default one.
+    fn flush_column_families_before(&self, memtable_id: usize) -> Result<()> {
+        for column_family in self.column_families.read().values() {
+            let inner = &column_family.inner;
+            let state_lock = inner.state_lock.lock();
+            while inner
+                .state
+                .read()
+                .imm_memtables
+                .last()
+                .is_some_and(|memtable| memtable.id() < memtable_id)
+            {
+                inner.flush_next_imm_memtable(&state_lock)?;
+            }
+        }
         Ok(())
     }
 
     /// Force flush the earliest-created immutable memtable to disk
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
+        self.check_writable()?;
         let state_lock = self.state_lock.lock();
+        self.flush_next_imm_memtable(&state_lock)
+    }
+
+    /// Flush all memtables of this storage and its column families, so that every write is
+    /// persisted in SSTs.
+    pub(crate) fn flush_all_memtables(&self) -> Result<()> {
+        let column_families = self
+            .column_families
+            .read()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        {
+            let state_lock = self.state_lock.lock();
+            // The memtables of the other column families can only be frozen together with the
+            // default one when they share its WAL.
+            let freeze = !self.state.read().memtable.is_empty()
+                || (self.owns_wal()
+                    && column_families.iter().any(|column_family| {
+                        !column_family.inner.state.read().memtable.is_empty()
+                    }));
+            if freeze {
+                self.force_freeze_memtable(&state_lock)?;
+            }
+            while !self.state.read().imm_memtables.is_empty() {
+                self.flush_next_imm_memtable(&state_lock)?;
+            }
+        }
+        for column_family in column_families {
+            column_family.inner.flush_all_memtables()?;
+        }
+        Ok(())
+    }
 
+    fn flush_next_imm_memtable(&self, state_lock: &MutexGuard<'_, ()>) -> Result<()> {
         let flush_memtable;
+        let next_memtable_id;
 
         {
             let guard = self.state.read();
@@ -724,16 +2229,39 @@ impl LsmStorageInner {
                 .last()
                 .expect("no imm memtables!")
                 .clone();
+            next_memtable_id = match guard.imm_memtables.len() {
+                1 => guard.memtable.id(),
+                len => guard.imm_memtables[len - 2].id(),
+            };
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
-        flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
+        if self.owns_wal() {
+            self.flush_column_families_before(next_memtable_id)?;
+            // The memtable was only frozen to rotate the WAL shared with other column families.
+            if flush_memtable.is_empty() {
+                {
+                    let mut guard = self.state.write();
+                    let mut snapshot = guard.as_ref().clone();
+                    let mem = snapshot.imm_memtables.pop().unwrap();
+                    assert_eq!(mem.id(), sst_id);
+                    *guard = Arc::new(snapshot);
+                }
+                self.write_stall_signal.notify();
+                std::fs::remove_file(self.path_of_wal(sst_id))?;
+                self.sync_dir()?;
+                return Ok(());
+            }
+        }
+
+        let mut builder = self.new_sst_builder_with_value_log(sst_id);
+        flush_memtable.flush(&mut builder)?;
+        let sst = self.cache_new_sst(builder.build(
             sst_id,
             Some(self.block_cache.clone()),
             self.path_of_sst(sst_id),
-        )?);
+        )?)?;
+        let sst_meta = sst.meta();
 
         // Add the flushed L0 table to the list.
         {
@@ -755,13 +2283,19 @@ impl LsmStorageInner {
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        self.write_stall_signal.notify();
 
-        if self.options.enable_wal {
+        if self.owns_wal() {
             std::fs::remove_file(self.path_of_wal(sst_id))?;
         }
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        self.manifest().add_records(
+            state_lock,
+            vec![
+                ManifestRecord::NewSsts(vec![sst_meta]),
+                ManifestRecord::Flush(sst_id),
+            ],
+        )?;
 
         self.sync_dir()?;
 
@@ -772,13 +2306,54 @@ impl LsmStorageInner {
         Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
     }
 
+    pub fn snapshot(self: &Arc<Self>) -> Snapshot {
+        self.mvcc().new_snapshot(self.clone())
+    }
+
     /// Create an iterator over a range of keys.
-    pub fn scan<'a>(
-        self: &'a Arc<Self>,
+    pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.scan(lower, upper)
+    }
+
+    pub fn scan_with_options(
+        self: &Arc<Self>,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+        options: &ReadOptions,
     ) -> Result<TxnIterator> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        let txn = self.new_txn_for_read(options)?;
+        txn.scan_with_options(lower, upper, options)
+    }
+
+    /// Create an iterator over a range of keys, moving backward from the last key.
+    pub fn scan_rev(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_rev_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_rev_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let txn = self.new_txn_for_read(options)?;
+        txn.scan_rev_with_options(lower, upper, options)
+    }
+
+    /// Create an iterator over a range of keys as of an older commit timestamp. The timestamp stays
+    /// pinned until the iterator is dropped.
+    pub fn scan_at(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn_at(self.clone(), ts, false)?;
         txn.scan(lower, upper)
     }
 
@@ -787,6 +2362,7 @@ impl LsmStorageInner {
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
         read_ts: u64,
+        options: &ReadOptions,
     ) -> Result<FusedIterator<LsmIterator>> {
         let snapshot = {
             let guard = self.state.read();
@@ -816,21 +2392,26 @@ impl LsmStorageInner {
                 table.last_key().as_key_slice(),
             ) {
                 let iter = match lower {
-                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
+                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key_with_options(
                         table,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        options.clone(),
                     )?,
                     Bound::Excluded(key) => {
-                        let mut iter = SsTableIterator::create_and_seek_to_key(
+                        let mut iter = SsTableIterator::create_and_seek_to_key_with_options(
                             table,
                             KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                            options.clone(),
                         )?;
                         while iter.is_valid() && iter.key().key_ref() == key {
                             iter.next()?;
                         }
                         iter
                     }
-                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
+                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first_with_options(
+                        table,
+                        options.clone(),
+                    )?,
                 };
 
                 table_iters.push(Box::new(iter));
@@ -854,21 +2435,26 @@ impl LsmStorageInner {
             }
 
             let level_iter = match lower {
-                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key(
+                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key_with_options(
                     level_ssts,
                     KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    options.clone(),
                 )?,
                 Bound::Excluded(key) => {
-                    let mut iter = SstConcatIterator::create_and_seek_to_key(
+                    let mut iter = SstConcatIterator::create_and_seek_to_key_with_options(
                         level_ssts,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        options.clone(),
                     )?;
                     while iter.is_valid() && iter.key().key_ref() == key {
                         iter.next()?;
                     }
                     iter
                 }
-                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first_with_options(
+                    level_ssts,
+                    options.clone(),
+                )?,
             };
             level_iters.push(Box::new(level_iter));
         }
@@ -878,8 +2464,113 @@ impl LsmStorageInner {
 
         Ok(FusedIterator::new(LsmIterator::new(
             iter,
+            map_bound(lower),
+            map_bound(upper),
+            read_ts,
+            snapshot.range_tombstones(read_ts, lower, upper)?,
+        )?))
+    }
+
+    /// Like `scan_with_ts`, but the iterator is positioned at the last key in the range and moves
+    /// backward. Versions of a key are ordered by descending timestamps, so the last version of
+    /// the upper bound key has the smallest timestamp.
+    pub(crate) fn scan_rev_with_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+        options: &ReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
+        let memtable_lower = match lower {
+            Bound::Included(key) => Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+            Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_END)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let memtable_upper = match upper {
+            Bound::Included(key) => Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+            Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        // The key to seek for the last version within the upper bound.
+        let seek_key = match upper {
+            Bound::Included(key) => Some(KeySlice::from_slice(key, key::TS_RANGE_END)),
+            Bound::Excluded(key) => Some(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+            Bound::Unbounded => None,
+        };
+
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            memtable_iters.push(Box::new(memtable.scan_rev(memtable_lower, memtable_upper)));
+        }
+        let memtable_iter = MergeIterator::create_rev(memtable_iters);
+
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for table_id in snapshot.l0_sstables.iter() {
+            let table = snapshot.sstables[table_id].clone();
+            if range_overlap(
+                lower,
+                upper,
+                table.first_key().as_key_slice(),
+                table.last_key().as_key_slice(),
+            ) {
+                let iter = match seek_key {
+                    Some(key) => SsTableIterator::create_and_seek_for_prev_with_options(
+                        table,
+                        key,
+                        options.clone(),
+                    )?,
+                    None => SsTableIterator::create_and_seek_to_last_with_options(
+                        table,
+                        options.clone(),
+                    )?,
+                };
+                table_iters.push(Box::new(iter));
+            }
+        }
+
+        let l0_iter = MergeIterator::create_rev(table_iters);
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        for (_, level_sst_ids) in &snapshot.levels {
+            let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
+            for table in level_sst_ids {
+                let table = snapshot.sstables[table].clone();
+                if range_overlap(
+                    lower,
+                    upper,
+                    table.first_key().as_key_slice(),
+                    table.last_key().as_key_slice(),
+                ) {
+                    level_ssts.push(table);
+                }
+            }
+            let level_iter = match seek_key {
+                Some(key) => SstConcatIterator::create_and_seek_for_prev_with_options(
+                    level_ssts,
+                    key,
+                    options.clone(),
+                )?,
+                None => SstConcatIterator::create_and_seek_to_last_with_options(
+                    level_ssts,
+                    options.clone(),
+                )?,
+            };
+            level_iters.push(Box::new(level_iter));
+        }
+
+        let iter = TwoMergeIterator::create_rev(memtable_iter, l0_iter)?;
+        let iter = TwoMergeIterator::create_rev(iter, MergeIterator::create_rev(level_iters))?;
+
+        Ok(FusedIterator::new(LsmIterator::new_rev(
+            iter,
+            map_bound(lower),
             map_bound(upper),
             read_ts,
+            snapshot.range_tombstones(read_ts, lower, upper)?,
         )?))
     }
 }
@@ -1,6 +1,6 @@
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
@@ -8,10 +8,35 @@ use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
-use crate::compact::CompactionTask;
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::compact::{CompactionOptions, CompactionTask};
+use crate::table::SstMeta;
+
+/// Name of the file holding the name of the current manifest file.
+const CURRENT_FILE: &str = "CURRENT";
+/// Name of the manifest file of databases created before manifest rotation.
+const LEGACY_MANIFEST_FILE: &str = "MANIFEST";
+
+fn manifest_file_name(id: usize) -> String {
+    format!("MANIFEST-{:06}", id)
+}
+
+struct ManifestFile {
+    file: File,
+    /// Increased by one on every rotation. The legacy manifest file has id 0.
+    id: usize,
+    size: u64,
+    /// Size of the file when it was written by a rotation, which is 0 if it was not written by a
+    /// rotation of this handle.
+    rotated_size: u64,
+}
 
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    dir: PathBuf,
+    file: Arc<Mutex<ManifestFile>>,
+    /// Records added through this handle are wrapped with the column family unless it is the
+    /// default one.
+    column_family: usize,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -19,23 +44,87 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// Value log GC rewrote each SST in `input_ssts` to the SST at the same position in
+    /// `output_ssts`.
+    ValueLogGc {
+        input_ssts: Vec<usize>,
+        output_ssts: Vec<usize>,
+    },
+    /// A compaction task that moves SSTs to the lower level without rewriting them.
+    TrivialMove(CompactionTask),
+    CreateColumnFamily {
+        id: usize,
+        name: String,
+        compaction_options: CompactionOptions,
+    },
+    /// The whole LSM structure, which replaces all records before it. Written as the first
+    /// record of a column family when rotating the manifest or creating a checkpoint.
+    Snapshot {
+        l0_sstables: Vec<usize>,
+        levels: Vec<(usize, Vec<usize>)>,
+        last_commit_ts: u64,
+        /// Metadata of the SSTs, which is missing in manifests written before it was recorded.
+        #[serde(default)]
+        ssts: Vec<SstMeta>,
+    },
+    /// Metadata of new SSTs, written together with the record adding them to the LSM structure, so
+    /// that the SSTs do not have to be opened to recover it.
+    NewSsts(Vec<SstMeta>),
+    /// A record of a column family other than the default one.
+    ColumnFamily(usize, Box<ManifestRecord>),
 }
 
 impl Manifest {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    /// Whether a manifest exists in the database directory `dir`.
+    pub fn exists(dir: impl AsRef<Path>) -> bool {
+        let dir = dir.as_ref();
+        dir.join(CURRENT_FILE).exists() || dir.join(LEGACY_MANIFEST_FILE).exists()
+    }
+
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let id = 1;
+        let file = OpenOptions::new()
+            .read(true)
+            .create_new(true)
+            .write(true)
+            .open(dir.join(manifest_file_name(id)))
+            .context("failed to create manifest")?;
+        file.sync_all()?;
+        Self::set_current(dir, id)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create manifest")?,
-            )),
+            dir: dir.to_path_buf(),
+            file: Arc::new(Mutex::new(ManifestFile {
+                file,
+                id,
+                size: 0,
+                rotated_size: 0,
+            })),
+            column_family: DEFAULT_COLUMN_FAMILY_ID,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+    /// The path of the current manifest file in `dir` and its id.
+    fn current_file(dir: &Path) -> Result<(PathBuf, usize)> {
+        match std::fs::read_to_string(dir.join(CURRENT_FILE)) {
+            Ok(name) => {
+                let name = name.trim();
+                let id = name
+                    .strip_prefix("MANIFEST-")
+                    .and_then(|id| id.parse().ok())
+                    .with_context(|| format!("invalid manifest file name {}", name))?;
+                Ok((dir.join(name), id))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok((dir.join(LEGACY_MANIFEST_FILE), 0))
+            }
+            Err(e) => Err(e).context("failed to read CURRENT"),
+        }
+    }
+
+    pub fn recover(dir: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let dir = dir.as_ref();
+        let (path, id) = Self::current_file(dir)?;
         let mut file = OpenOptions::new()
             .read(true)
             .append(true)
@@ -46,24 +135,101 @@ impl Manifest {
         let mut buf_ptr = buf.as_slice();
         let mut records = Vec::new();
         while buf_ptr.has_remaining() {
-            let len = buf_ptr.get_u64();
-            let slice = &buf_ptr[..len as usize];
-            let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-            buf_ptr.advance(len as usize);
-            let checksum = buf_ptr.get_u32();
-            if checksum != crc32fast::hash(slice) {
-                bail!("checksum mismatched!");
-            }
-            records.push(json);
+            records.push(Self::decode_record(&mut buf_ptr)?);
         }
         Ok((
             Self {
-                file: Arc::new(Mutex::new(file)),
+                dir: dir.to_path_buf(),
+                file: Arc::new(Mutex::new(ManifestFile {
+                    file,
+                    id,
+                    size: buf.len() as u64,
+                    rotated_size: 0,
+                })),
+                column_family: DEFAULT_COLUMN_FAMILY_ID,
             },
             records,
         ))
     }
 
+    /// Read the records of a possibly damaged manifest without opening it for writing. Only the
+    /// records before the first corrupted one are returned, together with whether the manifest is
+    /// corrupted.
+    pub fn salvage(dir: impl AsRef<Path>) -> Result<(Vec<ManifestRecord>, bool)> {
+        let (path, _) = Self::current_file(dir.as_ref())?;
+        let buf = std::fs::read(path).context("failed to read manifest")?;
+        let mut buf_ptr = buf.as_slice();
+        let mut records = Vec::new();
+        while buf_ptr.has_remaining() {
+            match Self::decode_record(&mut buf_ptr) {
+                Ok(record) => records.push(record),
+                Err(_) => return Ok((records, true)),
+            }
+        }
+        Ok((records, false))
+    }
+
+    /// Decode the record at the start of `buf` and advance past it.
+    fn decode_record(buf: &mut &[u8]) -> Result<ManifestRecord> {
+        if buf.remaining() < 8 {
+            bail!("incomplete manifest record");
+        }
+        let len = buf.get_u64() as usize;
+        if buf.remaining() < len.saturating_add(4) {
+            bail!("incomplete manifest record");
+        }
+        let slice = &buf[..len];
+        let checksum = (&buf[len..]).get_u32();
+        if checksum != crc32fast::hash(slice) {
+            bail!("checksum mismatched!");
+        }
+        let record = serde_json::from_slice::<ManifestRecord>(slice)?;
+        buf.advance(len + 4);
+        Ok(record)
+    }
+
+    /// Get a handle that adds records of the given column family to the same manifest file.
+    pub fn for_column_family(&self, column_family: usize) -> Self {
+        Self {
+            dir: self.dir.clone(),
+            file: self.file.clone(),
+            column_family,
+        }
+    }
+
+    /// Whether `name` is a manifest file, `CURRENT` or a temporary file left by a rotation.
+    pub fn is_manifest_file(name: &str) -> bool {
+        name.starts_with(CURRENT_FILE) || name.starts_with("MANIFEST")
+    }
+
+    /// Whether `name` is `CURRENT`, which names the current manifest file.
+    pub fn is_current_file(name: &str) -> bool {
+        name == CURRENT_FILE
+    }
+
+    /// Whether `name` is a manifest file other than the current one, or a temporary file left by
+    /// a rotation.
+    pub fn is_obsolete_file(&self, name: &str) -> bool {
+        let current = match self.file.lock().id {
+            0 => LEGACY_MANIFEST_FILE.to_string(),
+            id => manifest_file_name(id),
+        };
+        name == format!("{}.tmp", CURRENT_FILE) || (name.starts_with("MANIFEST") && name != current)
+    }
+
+    /// Size of the current manifest file in bytes.
+    pub fn size(&self) -> u64 {
+        self.file.lock().size
+    }
+
+    /// Whether the manifest should be rotated. It is rotated once it exceeds `max_size` and has at
+    /// least doubled since the last rotation, so that a snapshot larger than `max_size` does not
+    /// make every later record rotate it again.
+    pub fn needs_rotation(&self, max_size: u64) -> bool {
+        let file = self.file.lock();
+        file.size >= max_size.max(2 * file.rotated_size)
+    }
+
     pub fn add_record(
         &self,
         _state_lock_observer: &MutexGuard<()>,
@@ -73,13 +239,122 @@ impl Manifest {
     }
 
     pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
+        self.add_records_inner(vec![record])
+    }
+
+    /// Add `records` with a single write and sync of the manifest file.
+    pub fn add_records(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        records: Vec<ManifestRecord>,
+    ) -> Result<()> {
+        self.add_records_inner(records)
+    }
+
+    fn add_records_inner(&self, records: Vec<ManifestRecord>) -> Result<()> {
+        let mut buf = Vec::new();
+        for record in records {
+            let record = if self.column_family == DEFAULT_COLUMN_FAMILY_ID {
+                record
+            } else {
+                ManifestRecord::ColumnFamily(self.column_family, Box::new(record))
+            };
+            buf.extend(Self::encode_record(&record)?);
+        }
         let mut file = self.file.lock();
-        let mut buf = serde_json::to_vec(&record)?;
-        let hash = crc32fast::hash(&buf);
-        file.write_all(&(buf.len() as u64).to_be_bytes())?;
-        buf.put_u32(hash);
+        file.file.write_all(&buf)?;
+        file.file.sync_all()?;
+        file.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Replace the manifest with a new file containing only `records`, which must describe the
+    /// whole current state of all column families. The new file is only used once `CURRENT` is
+    /// atomically renamed to point to it, so a crash leaves either the old or the new manifest.
+    pub fn rotate(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        records: &[ManifestRecord],
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        for record in records {
+            buf.extend(Self::encode_record(record)?);
+        }
+        let mut current = self.file.lock();
+        let id = current.id + 1;
+        // A file left by an interrupted rotation is not referenced by `CURRENT` and overwritten.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.dir.join(manifest_file_name(id)))
+            .context("failed to create manifest")?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Self::set_current(&self.dir, id)?;
+        let old_file_name = match current.id {
+            0 => LEGACY_MANIFEST_FILE.to_string(),
+            id => manifest_file_name(id),
+        };
+        *current = ManifestFile {
+            file,
+            id,
+            size: buf.len() as u64,
+            rotated_size: buf.len() as u64,
+        };
+        std::fs::remove_file(self.dir.join(old_file_name))?;
+        Ok(())
+    }
+
+    /// Write a new manifest file in `dir` containing only `records`, e.g., rebuilt by the repair,
+    /// and atomically make it the current one. The file is written and synced under a temporary
+    /// name first, so a crash leaves either the old or the new manifest. Old manifest files are left
+    /// for the caller to remove.
+    pub fn replace(dir: impl AsRef<Path>, records: &[ManifestRecord]) -> Result<()> {
+        let dir = dir.as_ref();
+        let mut id = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            if let Some(file_id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("MANIFEST-"))
+                .and_then(|id| id.parse::<usize>().ok())
+            {
+                id = id.max(file_id);
+            }
+        }
+        let id = id + 1;
+        let mut buf = Vec::new();
+        for record in records {
+            buf.extend(Self::encode_record(record)?);
+        }
+        let path = dir.join(manifest_file_name(id));
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path).context("failed to create manifest")?;
         file.write_all(&buf)?;
         file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Self::set_current(dir, id)
+    }
+
+    fn encode_record(record: &ManifestRecord) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(record)?;
+        let mut buf = Vec::with_capacity(json.len() + 12);
+        buf.put_u64(json.len() as u64);
+        buf.put_slice(&json);
+        buf.put_u32(crc32fast::hash(&json));
+        Ok(buf)
+    }
+
+    /// Atomically point `CURRENT` to the manifest file `id`.
+    fn set_current(dir: &Path, id: usize) -> Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", CURRENT_FILE));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(manifest_file_name(id).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, dir.join(CURRENT_FILE))?;
+        File::open(dir)?.sync_all()?;
         Ok(())
     }
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::ops::Bound;
 use std::path::Path;
 use std::sync::atomic::AtomicUsize;
@@ -11,8 +12,9 @@ use ouroboros::self_referencing;
 
 use crate::iterators::StorageIterator;
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{Wal, WalRecoveryMode};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
@@ -20,6 +22,8 @@ use crate::wal::Wal;
 /// chapters of week 1 and week 2.
 pub struct MemTable {
     pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    /// Range tombstones keyed by the start key and the timestamp, with the end key as the value.
+    range_tombstones: Arc<SkipMap<KeyBytes, Bytes>>,
     wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
@@ -50,7 +54,7 @@ pub(crate) fn map_key_bound(bound: Bound<KeySlice>) -> Bound<KeyBytes> {
 }
 
 /// Create a bound of `Bytes` from a bound of `KeySlice`.
-pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySlice> {
+pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySlice<'_>> {
     match bound {
         Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, ts)),
         Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, ts)),
@@ -64,6 +68,7 @@ impl MemTable {
         Self {
             id,
             map: Arc::new(SkipMap::new()),
+            range_tombstones: Arc::new(SkipMap::new()),
             wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
         }
@@ -74,6 +79,7 @@ impl MemTable {
         Ok(Self {
             id,
             map: Arc::new(SkipMap::new()),
+            range_tombstones: Arc::new(SkipMap::new()),
             wal: Some(Wal::create(path.as_ref())?),
             approximate_size: Arc::new(AtomicUsize::new(0)),
         })
@@ -82,14 +88,77 @@ impl MemTable {
     /// Create a memtable from WAL
     pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
         let map = Arc::new(SkipMap::new());
+        let range_tombstones = Arc::new(SkipMap::new());
         Ok(Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
+            wal: Some(Wal::recover(path.as_ref(), &map, &range_tombstones)?),
             map,
+            range_tombstones,
             approximate_size: Arc::new(AtomicUsize::new(0)),
         })
     }
 
+    /// Create a memtable from a WAL shared with the memtables in `others`, which are recovered as
+    /// well. Returns whether corrupted records were dropped as allowed by `mode`, and whether any of
+    /// `others` got entries from the WAL.
+    pub fn recover_from_shared_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        others: &[Arc<MemTable>],
+        mode: WalRecoveryMode,
+    ) -> Result<(Self, bool, bool)> {
+        let map = Arc::new(SkipMap::new());
+        let range_tombstones = Arc::new(SkipMap::new());
+        let mut skiplists = others
+            .iter()
+            .map(|memtable| {
+                let skiplists = (memtable.map.clone(), memtable.range_tombstones.clone());
+                (memtable.id(), skiplists)
+            })
+            .collect::<HashMap<_, _>>();
+        skiplists.insert(id, (map.clone(), range_tombstones.clone()));
+        let (wal, dropped, shared) = Wal::recover_shared(path.as_ref(), id, &skiplists, mode)?;
+        let memtable = Self {
+            id,
+            wal: Some(wal),
+            map,
+            range_tombstones,
+            approximate_size: Arc::new(AtomicUsize::new(0)),
+        };
+        Ok((memtable, dropped, shared))
+    }
+
+    /// Create a memtable without a WAL from the intact entries of a possibly damaged WAL, see
+    /// `Wal::salvage`. Returns whether the WAL is corrupted.
+    pub fn salvage_from_wal(id: usize, path: impl AsRef<Path>) -> Result<(Self, bool)> {
+        let memtable = Self::create(id);
+        let corrupted = Wal::salvage(path.as_ref(), &memtable.map, &memtable.range_tombstones)?;
+        Ok((memtable, corrupted))
+    }
+
+    /// Create a memtable without a WAL from a possibly damaged WAL shared with the memtables in
+    /// `others`, see `Wal::salvage_shared`. Returns whether the WAL is corrupted.
+    pub fn salvage_from_shared_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        others: &[Arc<MemTable>],
+    ) -> Result<(Self, bool)> {
+        let memtable = Self::create(id);
+        let mut skiplists = others
+            .iter()
+            .map(|memtable| {
+                let skiplists = (memtable.map.clone(), memtable.range_tombstones.clone());
+                (memtable.id(), skiplists)
+            })
+            .collect::<HashMap<_, _>>();
+        skiplists.insert(
+            id,
+            (memtable.map.clone(), memtable.range_tombstones.clone()),
+        );
+        let corrupted = Wal::salvage_shared(path.as_ref(), id, &skiplists)?;
+        Ok((memtable, corrupted))
+    }
+
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
         let key_bytes = KeyBytes::from_bytes_with_ts(
@@ -129,6 +198,15 @@ impl MemTable {
 
     /// Implement this in week 3, day 5.
     pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        self.put_batch_without_wal(data);
+        if let Some(ref wal) = self.wal {
+            wal.put_batch(data)?;
+        }
+        Ok(())
+    }
+
+    /// Put key-value pairs that have already been written to a WAL shared with other memtables.
+    pub(crate) fn put_batch_without_wal(&self, data: &[(KeySlice, &[u8])]) {
         let mut estimated_size = 0;
         for (key, value) in data {
             estimated_size += key.raw_len() + value.len();
@@ -139,12 +217,45 @@ impl MemTable {
         }
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Delete the keys in `[start, end)` written before the timestamp of `start`.
+    pub fn delete_range(&self, start: KeySlice, end: &[u8]) -> Result<()> {
+        self.delete_range_without_wal(start, end);
         if let Some(ref wal) = self.wal {
-            wal.put_batch(data)?;
+            wal.put_range_tombstone(start, end)?;
         }
         Ok(())
     }
 
+    /// Delete a range of keys without logging the range tombstone to the WAL.
+    pub(crate) fn delete_range_without_wal(&self, start: KeySlice, end: &[u8]) {
+        self.range_tombstones.insert(
+            start.to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(end),
+        );
+        self.approximate_size.fetch_add(
+            start.raw_len() + end.len(),
+            std::sync::atomic::Ordering::Relaxed,
+        );
+    }
+
+    /// The range tombstones in this memtable.
+    pub fn range_tombstones(&self) -> impl Iterator<Item = RangeTombstone> + '_ {
+        self.range_tombstones.iter().map(|entry| {
+            let start = entry.key();
+            RangeTombstone::new(
+                start.key_ref().to_vec().into(),
+                entry.value().clone(),
+                start.ts(),
+            )
+        })
+    }
+
+    pub(crate) fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -154,15 +265,29 @@ impl MemTable {
 
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        let mut iter = self.create_iter(lower, upper);
+        iter.next().unwrap();
+        iter
+    }
+
+    /// Get an iterator over a range of keys, positioned at the last key in the range.
+    pub fn scan_rev(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        let mut iter = self.create_iter(lower, upper);
+        iter.seek_to_last();
+        iter
+    }
+
+    fn create_iter(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
-        let mut iter = MemTableIteratorBuilder {
+        let range = (lower.clone(), upper.clone());
+        MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
+            iter_builder: |map| map.range(range),
             item: (KeyBytes::new(), Bytes::new()),
+            lower,
+            upper,
         }
-        .build();
-        iter.next().unwrap();
-        iter
+        .build()
     }
 
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
@@ -170,6 +295,9 @@ impl MemTable {
         for entry in self.map.iter() {
             builder.add(entry.key().as_key_slice(), &entry.value()[..]);
         }
+        for tombstone in self.range_tombstones() {
+            builder.add_range_tombstone(tombstone);
+        }
         Ok(())
     }
 
@@ -177,6 +305,13 @@ impl MemTable {
         self.id
     }
 
+    /// The largest timestamp of the versions and range tombstones in this memtable.
+    pub fn max_ts(&self) -> u64 {
+        let max_ts = self.map.iter().map(|x| x.key().ts()).max();
+        let max_tombstone_ts = self.range_tombstones.iter().map(|x| x.key().ts()).max();
+        max_ts.max(max_tombstone_ts).unwrap_or_default()
+    }
+
     pub fn approximate_size(&self) -> usize {
         self.approximate_size
             .load(std::sync::atomic::Ordering::Relaxed)
@@ -184,7 +319,7 @@ impl MemTable {
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.is_empty()
     }
 }
 
@@ -210,6 +345,9 @@ pub struct MemTableIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (KeyBytes, Bytes),
+    /// The range of the iterator, used to restart the skipmap iterator when moving backward.
+    lower: Bound<KeyBytes>,
+    upper: Bound<KeyBytes>,
 }
 
 impl MemTableIterator {
@@ -218,6 +356,25 @@ impl MemTableIterator {
             .map(|x| (x.key().clone(), x.value().clone()))
             .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
     }
+
+    /// Move to the last entry before `upper`, and restart the skipmap iterator after it so that
+    /// `next` continues from there.
+    fn seek_before(&mut self, upper: Bound<KeyBytes>) {
+        self.with_mut(|fields| {
+            let item = MemTableIterator::entry_to_item(
+                fields.map.range((fields.lower.clone(), upper)).next_back(),
+            );
+            *fields.iter = fields
+                .map
+                .range((Bound::Excluded(item.0.clone()), fields.upper.clone()));
+            *fields.item = item;
+        });
+    }
+
+    fn seek_to_last(&mut self) {
+        let upper = self.borrow_upper().clone();
+        self.seek_before(upper);
+    }
 }
 
 impl StorageIterator for MemTableIterator {
@@ -227,7 +384,7 @@ impl StorageIterator for MemTableIterator {
         &self.borrow_item().1[..]
     }
 
-    fn key(&self) -> KeySlice {
+    fn key(&self) -> KeySlice<'_> {
         self.borrow_item().0.as_key_slice()
     }
 
@@ -240,4 +397,34 @@ impl StorageIterator for MemTableIterator {
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        let key = self.borrow_item().0.clone();
+        self.seek_before(Bound::Excluded(key));
+        Ok(())
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        let key = key.to_key_vec().into_key_bytes();
+        let lower = match self.borrow_lower() {
+            Bound::Included(lower) if key < *lower => Bound::Included(lower.clone()),
+            Bound::Excluded(lower) if key <= *lower => Bound::Excluded(lower.clone()),
+            _ => Bound::Included(key),
+        };
+        self.with_mut(|fields| {
+            *fields.iter = fields.map.range((lower, fields.upper.clone()));
+        });
+        self.next()
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let key = key.to_key_vec().into_key_bytes();
+        let upper = match self.borrow_upper() {
+            Bound::Included(upper) if key > *upper => Bound::Included(upper.clone()),
+            Bound::Excluded(upper) if key >= *upper => Bound::Excluded(upper.clone()),
+            _ => Bound::Included(key),
+        };
+        self.seek_before(upper);
+        Ok(())
+    }
 }
@@ -1,6 +1,7 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+pub mod snapshot;
 pub mod txn;
 pub mod watermark;
 
@@ -9,12 +10,13 @@ use std::{
     sync::{atomic::AtomicBool, Arc},
 };
 
+use anyhow::{bail, Result};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use crate::lsm_storage::LsmStorageInner;
 
-use self::{txn::Transaction, watermark::Watermark};
+use self::{snapshot::Snapshot, txn::Transaction, watermark::Watermark};
 
 pub(crate) struct CommittedTxnData {
     pub(crate) key_hashes: HashSet<u32>,
@@ -29,15 +31,18 @@ pub(crate) struct LsmMvccInner {
     pub(crate) commit_lock: Mutex<()>,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    /// Number of most recent commit timestamps that stay readable with `new_txn_at`.
+    pub(crate) history_retention: u64,
 }
 
 impl LsmMvccInner {
-    pub fn new(initial_ts: u64) -> Self {
+    pub fn new(initial_ts: u64, history_retention: u64) -> Self {
         Self {
             write_lock: Mutex::new(()),
             commit_lock: Mutex::new(()),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            history_retention,
         }
     }
 
@@ -51,18 +56,61 @@ impl LsmMvccInner {
 
     /// All ts (strictly) below this ts can be garbage collected.
     pub fn watermark(&self) -> u64 {
-        let ts = self.ts.lock();
-        ts.1.watermark().unwrap_or(ts.0)
+        self.watermark_of(&self.ts.lock())
+    }
+
+    /// The watermark is held back by the oldest reader and by the history retention window.
+    fn watermark_of(&self, ts: &(u64, Watermark)) -> u64 {
+        let retained = ts.0.saturating_sub(self.history_retention);
+        ts.1.watermark().unwrap_or(ts.0).min(retained)
     }
 
     pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
         let mut ts = self.ts.lock();
         let read_ts = ts.0;
+        Self::new_txn_locked(&mut ts, inner, read_ts, serializable)
+    }
+
+    /// Create a transaction reading at the historical timestamp `read_ts`. Versions below the
+    /// watermark may have been garbage collected, so older timestamps cannot be read.
+    pub fn new_txn_at(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+        serializable: bool,
+    ) -> Result<Arc<Transaction>> {
+        let mut ts = self.ts.lock();
+        if read_ts > ts.0 {
+            bail!(
+                "timestamp {} is newer than the latest commit timestamp {}",
+                read_ts,
+                ts.0
+            );
+        }
+        let watermark = self.watermark_of(&ts);
+        if read_ts < watermark {
+            bail!(
+                "timestamp {} is older than the retained history starting at {}",
+                read_ts,
+                watermark
+            );
+        }
+        Ok(Self::new_txn_locked(&mut ts, inner, read_ts, serializable))
+    }
+
+    /// Create a transaction reading at `read_ts` and register it as a reader, with `ts` locked.
+    fn new_txn_locked(
+        ts: &mut (u64, Watermark),
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+        serializable: bool,
+    ) -> Arc<Transaction> {
         ts.1.add_reader(read_ts);
         Arc::new(Transaction {
             inner,
             read_ts,
             local_storage: Arc::new(SkipMap::new()),
+            column_family_storage: Mutex::new(BTreeMap::new()),
             committed: Arc::new(AtomicBool::new(false)),
             key_hashes: if serializable {
                 Some(Mutex::new((HashSet::new(), HashSet::new())))
@@ -71,4 +119,11 @@ impl LsmMvccInner {
             },
         })
     }
+
+    pub fn new_snapshot(&self, inner: Arc<LsmStorageInner>) -> Snapshot {
+        let mut ts = self.ts.lock();
+        let read_ts = ts.0;
+        ts.1.add_reader(read_ts);
+        Snapshot { read_ts, inner }
+    }
 }
@@ -0,0 +1,117 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    column_family::ColumnFamily,
+    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_storage::LsmStorageInner,
+    options::ReadOptions,
+};
+
+/// A read-only view of the storage at a fixed timestamp. Unlike a transaction, a snapshot has no
+/// write buffer or read set. Versions visible to it are kept by compaction until it is dropped.
+pub struct Snapshot {
+    pub(crate) read_ts: u64,
+    pub(crate) inner: Arc<LsmStorageInner>,
+}
+
+impl Snapshot {
+    /// The timestamp the snapshot reads at.
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_with_options(key, &ReadOptions::default())
+    }
+
+    /// Get a key, reading SSTs as set in `options`. The timestamp of the snapshot is used
+    /// regardless of `options.read_ts`.
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        self.inner.get_with_ts(key, self.read_ts, options)
+    }
+
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    /// Scan a range of keys, reading SSTs as set in `options` and stopping at
+    /// `options.iterate_upper_bound`. The timestamp of the snapshot is used regardless of
+    /// `options.read_ts`.
+    pub fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner
+            .scan_with_ts(lower, options.upper_bound(upper), self.read_ts, options)
+    }
+
+    /// Like `scan`, but the iterator is positioned at the last key in the range and moves backward
+    /// with `prev`.
+    pub fn scan_rev(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_rev_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_rev_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner
+            .scan_rev_with_ts(lower, options.upper_bound(upper), self.read_ts, options)
+    }
+
+    pub fn get_cf(&self, column_family: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf_with_options(column_family, key, &ReadOptions::default())
+    }
+
+    pub fn get_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        column_family.inner.get_with_ts(key, self.read_ts, options)
+    }
+
+    pub fn scan_cf(
+        &self,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_cf_with_options(column_family, lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        column_family
+            .inner
+            .scan_with_ts(lower, options.upper_bound(upper), self.read_ts, options)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts)
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     ops::Bound,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -14,31 +14,55 @@ use ouroboros::self_referencing;
 use parking_lot::Mutex;
 
 use crate::{
+    column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID},
     iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
     lsm_iterator::{FusedIterator, LsmIterator},
     lsm_storage::{LsmStorageInner, WriteBatchRecord},
     mem_table::map_bound,
     mvcc::CommittedTxnData,
+    options::{ReadOptions, WriteOptions},
 };
 
+/// The local storage of a column family in a transaction.
+type ColumnFamilyStorage = (ColumnFamily, Arc<SkipMap<Bytes, Bytes>>);
+
 pub struct Transaction {
     pub(crate) read_ts: u64,
     pub(crate) inner: Arc<LsmStorageInner>,
     pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
+    /// Local storage of the column families other than the default one.
+    pub(crate) column_family_storage: Mutex<BTreeMap<usize, ColumnFamilyStorage>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
     pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
 }
 
+/// Hash a key for conflict detection. Keys of column families other than the default one are
+/// hashed with the column family id as the seed, so that the same key in different column families
+/// does not conflict.
+fn key_hash(column_family: usize, key: &[u8]) -> u32 {
+    if column_family == DEFAULT_COLUMN_FAMILY_ID {
+        farmhash::hash32(key)
+    } else {
+        farmhash::hash32_with_seed(key, column_family as u32)
+    }
+}
+
 impl Transaction {
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_with_options(key, &ReadOptions::default())
+    }
+
+    /// Get a key, reading SSTs as set in `options`. The read timestamp of the transaction is used
+    /// regardless of `options.read_ts`.
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
         if let Some(guard) = &self.key_hashes {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            read_set.insert(key_hash(DEFAULT_COLUMN_FAMILY_ID, key));
         }
         if let Some(entry) = self.local_storage.get(key) {
             if entry.value().is_empty() {
@@ -47,27 +71,63 @@ impl Transaction {
                 return Ok(Some(entry.value().clone()));
             }
         }
-        self.inner.get_with_ts(key, self.read_ts)
+        self.inner.get_with_ts(key, self.read_ts, options)
     }
 
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.scan_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    /// Scan a range of keys, reading SSTs as set in `options` and stopping at
+    /// `options.iterate_upper_bound`. The read timestamp of the transaction is used regardless of
+    /// `options.read_ts`.
+    pub fn scan_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        let mut local_iter = TxnLocalIteratorBuilder {
-            map: self.local_storage.clone(),
-            iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
-            item: (Bytes::new(), Bytes::new()),
-        }
-        .build();
-        let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
-        local_iter.with_mut(|x| *x.item = entry);
-
+        let upper = options.upper_bound(upper);
         TxnIterator::create(
             self.clone(),
             TwoMergeIterator::create(
-                local_iter,
-                self.inner.scan_with_ts(lower, upper, self.read_ts)?,
+                TxnLocalIterator::create(self.local_storage.clone(), lower, upper),
+                self.inner
+                    .scan_with_ts(lower, upper, self.read_ts, options)?,
+            )?,
+        )
+    }
+
+    /// Like `scan`, but the iterator is positioned at the last key in the range and moves backward
+    /// with `prev`.
+    pub fn scan_rev(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_rev_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    /// Like `scan_with_options`, but the iterator moves backward from the last key in the range.
+    pub fn scan_rev_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let upper = options.upper_bound(upper);
+        TxnIterator::create_rev(
+            self.clone(),
+            TwoMergeIterator::create_rev(
+                TxnLocalIterator::create_rev(self.local_storage.clone(), lower, upper),
+                self.inner
+                    .scan_rev_with_ts(lower, upper, self.read_ts, options)?,
             )?,
         )
     }
@@ -81,7 +141,7 @@ impl Transaction {
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+            write_hashes.insert(key_hash(DEFAULT_COLUMN_FAMILY_ID, key));
         }
     }
 
@@ -94,11 +154,127 @@ impl Transaction {
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+            write_hashes.insert(key_hash(DEFAULT_COLUMN_FAMILY_ID, key));
+        }
+    }
+
+    /// Get the local storage of a column family other than the default one.
+    fn column_family_storage(&self, column_family: &ColumnFamily) -> Arc<SkipMap<Bytes, Bytes>> {
+        self.column_family_storage
+            .lock()
+            .entry(column_family.id)
+            .or_insert_with(|| (column_family.clone(), Arc::new(SkipMap::new())))
+            .1
+            .clone()
+    }
+
+    fn is_default_column_family(&self, column_family: &ColumnFamily) -> bool {
+        column_family.id == self.inner.column_family_id
+    }
+
+    pub fn get_cf(&self, column_family: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf_with_options(column_family, key, &ReadOptions::default())
+    }
+
+    pub fn get_cf_with_options(
+        &self,
+        column_family: &ColumnFamily,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        if self.is_default_column_family(column_family) {
+            return self.get_with_options(key, options);
+        }
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        if let Some(guard) = &self.key_hashes {
+            let mut guard = guard.lock();
+            let (_, read_set) = &mut *guard;
+            read_set.insert(key_hash(column_family.id, key));
+        }
+        if let Some(entry) = self.column_family_storage(column_family).get(key) {
+            if entry.value().is_empty() {
+                return Ok(None);
+            } else {
+                return Ok(Some(entry.value().clone()));
+            }
+        }
+        column_family.inner.get_with_ts(key, self.read_ts, options)
+    }
+
+    pub fn scan_cf(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_cf_with_options(column_family, lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_cf_with_options(
+        self: &Arc<Self>,
+        column_family: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        if self.is_default_column_family(column_family) {
+            return self.scan_with_options(lower, upper, options);
+        }
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let upper = options.upper_bound(upper);
+        TxnIterator::create_for_column_family(
+            self.clone(),
+            column_family.id,
+            TwoMergeIterator::create(
+                TxnLocalIterator::create(self.column_family_storage(column_family), lower, upper),
+                column_family
+                    .inner
+                    .scan_with_ts(lower, upper, self.read_ts, options)?,
+            )?,
+        )
+    }
+
+    pub fn put_cf(&self, column_family: &ColumnFamily, key: &[u8], value: &[u8]) {
+        if self.is_default_column_family(column_family) {
+            return self.put(key, value);
+        }
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.column_family_storage(column_family)
+            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        if let Some(key_hashes) = &self.key_hashes {
+            let mut key_hashes = key_hashes.lock();
+            let (write_hashes, _) = &mut *key_hashes;
+            write_hashes.insert(key_hash(column_family.id, key));
+        }
+    }
+
+    pub fn delete_cf(&self, column_family: &ColumnFamily, key: &[u8]) {
+        if self.is_default_column_family(column_family) {
+            return self.delete(key);
+        }
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.column_family_storage(column_family)
+            .insert(Bytes::copy_from_slice(key), Bytes::new());
+        if let Some(key_hashes) = &self.key_hashes {
+            let mut key_hashes = key_hashes.lock();
+            let (write_hashes, _) = &mut *key_hashes;
+            write_hashes.insert(key_hash(column_family.id, key));
         }
     }
 
     pub fn commit(&self) -> Result<()> {
+        self.commit_with_options(&WriteOptions::default())
+    }
+
+    pub fn commit_with_options(&self, options: &WriteOptions) -> Result<()> {
         self.committed
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .expect("cannot operate on committed txn!");
@@ -125,18 +301,32 @@ impl Transaction {
         } else {
             serializability_check = false;
         }
-        let batch = self
-            .local_storage
-            .iter()
-            .map(|entry| {
-                if entry.value().is_empty() {
-                    WriteBatchRecord::Del(entry.key().clone())
-                } else {
-                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
-                }
-            })
-            .collect::<Vec<_>>();
-        let ts = self.inner.write_batch_inner(&batch)?;
+        let to_record = |entry: Entry<'_, Bytes, Bytes>| {
+            if entry.value().is_empty() {
+                WriteBatchRecord::Del(entry.key().clone())
+            } else {
+                WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+            }
+        };
+        let batch = self.local_storage.iter().map(to_record).collect::<Vec<_>>();
+        let column_family_storage = self.column_family_storage.lock();
+        let ts = if column_family_storage.is_empty() {
+            self.inner.write_batch_inner(&batch, options)?
+        } else {
+            let default_column_family = self.inner.default_column_family();
+            let mut cf_batch = batch
+                .into_iter()
+                .map(|record| (&default_column_family, record))
+                .collect::<Vec<_>>();
+            for (column_family, storage) in column_family_storage.values() {
+                cf_batch.extend(
+                    storage
+                        .iter()
+                        .map(|entry| (column_family, to_record(entry))),
+                );
+            }
+            self.inner.write_batch_cf_inner(&cf_batch, options)?
+        };
         if serializability_check {
             let mut committed_txns = self.inner.mvcc().committed_txns.lock();
             let mut key_hashes = self.key_hashes.as_ref().unwrap().lock();
@@ -185,6 +375,9 @@ pub struct TxnLocalIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (Bytes, Bytes),
+    /// The range of the iterator, used to restart the skipmap iterator when moving backward.
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
 }
 
 impl TxnLocalIterator {
@@ -193,6 +386,57 @@ impl TxnLocalIterator {
             .map(|x| (x.key().clone(), x.value().clone()))
             .unwrap_or_else(|| (Bytes::new(), Bytes::new()))
     }
+
+    fn create_iter(
+        map: Arc<SkipMap<Bytes, Bytes>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Self {
+        let (lower, upper) = (map_bound(lower), map_bound(upper));
+        let range = (lower.clone(), upper.clone());
+        TxnLocalIteratorBuilder {
+            map,
+            iter_builder: |map| map.range(range),
+            item: (Bytes::new(), Bytes::new()),
+            lower,
+            upper,
+        }
+        .build()
+    }
+
+    /// Create an iterator over a range of the local storage, positioned at the first key.
+    fn create(map: Arc<SkipMap<Bytes, Bytes>>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Self {
+        let mut iter = Self::create_iter(map, lower, upper);
+        let entry = iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
+        iter.with_mut(|x| *x.item = entry);
+        iter
+    }
+
+    /// Create an iterator over a range of the local storage, positioned at the last key.
+    fn create_rev(
+        map: Arc<SkipMap<Bytes, Bytes>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Self {
+        let mut iter = Self::create_iter(map, lower, upper);
+        let upper = iter.borrow_upper().clone();
+        iter.seek_before(upper);
+        iter
+    }
+
+    /// Move to the last entry before `upper`, and restart the skipmap iterator after it so that
+    /// `next` continues from there.
+    fn seek_before(&mut self, upper: Bound<Bytes>) {
+        self.with_mut(|fields| {
+            let item = TxnLocalIterator::entry_to_item(
+                fields.map.range((fields.lower.clone(), upper)).next_back(),
+            );
+            *fields.iter = fields
+                .map
+                .range((Bound::Excluded(item.0.clone()), fields.upper.clone()));
+            *fields.item = item;
+        });
+    }
 }
 
 impl StorageIterator for TxnLocalIterator {
@@ -215,11 +459,44 @@ impl StorageIterator for TxnLocalIterator {
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        let key = self.borrow_item().0.clone();
+        self.seek_before(Bound::Excluded(key));
+        Ok(())
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        let key = Bytes::copy_from_slice(key);
+        let lower = match self.borrow_lower() {
+            Bound::Included(lower) if key < *lower => Bound::Included(lower.clone()),
+            Bound::Excluded(lower) if key <= *lower => Bound::Excluded(lower.clone()),
+            _ => Bound::Included(key),
+        };
+        self.with_mut(|fields| {
+            *fields.iter = fields.map.range((lower, fields.upper.clone()));
+        });
+        self.next()
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let key = Bytes::copy_from_slice(key);
+        let upper = match self.borrow_upper() {
+            Bound::Included(upper) if key > *upper => Bound::Included(upper.clone()),
+            Bound::Excluded(upper) if key >= *upper => Bound::Excluded(upper.clone()),
+            _ => Bound::Included(key),
+        };
+        self.seek_before(upper);
+        Ok(())
+    }
 }
 
 pub struct TxnIterator {
     txn: Arc<Transaction>,
+    column_family: usize,
     iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    /// Whether the iterator last moved backward, from larger keys to smaller keys.
+    reverse: bool,
 }
 
 impl TxnIterator {
@@ -227,7 +504,38 @@ impl TxnIterator {
         txn: Arc<Transaction>,
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
-        let mut iter = Self { txn, iter };
+        Self::create_for_column_family(txn, DEFAULT_COLUMN_FAMILY_ID, iter)
+    }
+
+    pub fn create_for_column_family(
+        txn: Arc<Transaction>,
+        column_family: usize,
+        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    ) -> Result<Self> {
+        Self::create_inner(txn, column_family, iter, false)
+    }
+
+    /// Create an iterator from iterators created for reverse iteration, which starts by moving
+    /// backward with `prev`.
+    pub fn create_rev(
+        txn: Arc<Transaction>,
+        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    ) -> Result<Self> {
+        Self::create_inner(txn, DEFAULT_COLUMN_FAMILY_ID, iter, true)
+    }
+
+    fn create_inner(
+        txn: Arc<Transaction>,
+        column_family: usize,
+        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        reverse: bool,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            txn,
+            column_family,
+            iter,
+            reverse,
+        };
         iter.skip_deletes()?;
         if iter.is_valid() {
             iter.add_to_read_set(iter.key());
@@ -237,7 +545,11 @@ impl TxnIterator {
 
     fn skip_deletes(&mut self) -> Result<()> {
         while self.iter.is_valid() && self.iter.value().is_empty() {
-            self.iter.next()?;
+            if self.reverse {
+                self.iter.prev()?;
+            } else {
+                self.iter.next()?;
+            }
         }
         Ok(())
     }
@@ -246,7 +558,7 @@ impl TxnIterator {
         if let Some(guard) = &self.txn.key_hashes {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            read_set.insert(key_hash(self.column_family, key));
         }
     }
 }
@@ -270,6 +582,7 @@ impl StorageIterator for TxnIterator {
     }
 
     fn next(&mut self) -> Result<()> {
+        self.reverse = false;
         self.iter.next()?;
         self.skip_deletes()?;
         if self.is_valid() {
@@ -278,6 +591,16 @@ impl StorageIterator for TxnIterator {
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        self.reverse = true;
+        self.iter.prev()?;
+        self.skip_deletes()?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iter.num_active_iterators()
     }
@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+use crate::value_log::ValueLogFile;
+
+/// Files removed from the LSM structure that are deleted once no reader holds them anymore.
+/// Iterators and state snapshots keep the SSTs they read alive, and SSTs keep their value logs
+/// alive, so a file can be deleted when the handle kept here is the only one left.
+pub(crate) struct ObsoleteFiles {
+    dir: PathBuf,
+    ssts: Mutex<Vec<Arc<SsTable>>>,
+    value_logs: Mutex<Vec<Arc<ValueLogFile>>>,
+}
+
+impl ObsoleteFiles {
+    pub(crate) fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            ssts: Mutex::new(Vec::new()),
+            value_logs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Defer the deletion of the SSTs removed from the LSM structure and of the value logs in
+    /// `value_logs`, which are no longer referenced by any SST in it. Fails if one of `value_logs`
+    /// is not referenced by the removed SSTs either, after deferring the deletion of the others.
+    pub(crate) fn add(&self, ssts: Vec<Arc<SsTable>>, value_logs: &[usize]) -> Result<()> {
+        let mut pending_value_logs = self.value_logs.lock();
+        let mut unknown = Vec::new();
+        for id in value_logs {
+            match ssts.iter().find_map(|sst| sst.value_logs().get(id)) {
+                Some(value_log) => pending_value_logs.push(value_log.clone()),
+                None => unknown.push(*id),
+            }
+        }
+        self.ssts.lock().extend(ssts);
+        if !unknown.is_empty() {
+            bail!(
+                "value logs {:?} are not referenced by the removed SSTs",
+                unknown
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete the files that are no longer in use. Returns the number of files deleted.
+    pub(crate) fn purge(&self) -> Result<usize> {
+        let mut deleted = 0;
+        // SSTs go first, as they hold their value logs open.
+        let mut ssts = self.ssts.lock();
+        let mut in_use = Vec::with_capacity(ssts.len());
+        for sst in ssts.drain(..) {
+            if Arc::strong_count(&sst) == 1 {
+                std::fs::remove_file(LsmStorageInner::path_of_sst_static(&self.dir, sst.sst_id()))?;
+                deleted += 1;
+            } else {
+                in_use.push(sst);
+            }
+        }
+        *ssts = in_use;
+        drop(ssts);
+        let mut value_logs = self.value_logs.lock();
+        let mut in_use = Vec::with_capacity(value_logs.len());
+        for value_log in value_logs.drain(..) {
+            if Arc::strong_count(&value_log) == 1 {
+                std::fs::remove_file(LsmStorageInner::path_of_vlog_static(
+                    &self.dir,
+                    value_log.id(),
+                ))?;
+                deleted += 1;
+            } else {
+                in_use.push(value_log);
+            }
+        }
+        *value_logs = in_use;
+        Ok(deleted)
+    }
+}
+
+impl LsmStorageInner {
+    /// Delete the files in the database directory that the recovered state does not refer to, or
+    /// move them to the `lost` directory with `quarantine_orphan_files`. They are left behind by a
+    /// crash in the middle of a flush, compaction, WAL removal or manifest rotation. Returns the
+    /// original paths of the files.
+    pub(crate) fn remove_orphan_files(&self) -> Result<Vec<PathBuf>> {
+        let mut live_ssts = BTreeSet::new();
+        let mut live_value_logs = BTreeSet::new();
+        let state = self.state.read().clone();
+        let column_families = self.column_families.read();
+        let column_family_states = column_families
+            .values()
+            .map(|column_family| column_family.inner.state.read().clone());
+        for state in std::iter::once(state.clone()).chain(column_family_states) {
+            for sst in state.sstables.values() {
+                live_ssts.insert(sst.sst_id());
+                live_value_logs.extend(sst.value_log_refs().keys().copied());
+            }
+        }
+        // Only the default column family logs to WALs, and all its memtables own one.
+        let live_wals = std::iter::once(&state.memtable)
+            .chain(state.imm_memtables.iter())
+            .map(|memtable| memtable.id())
+            .collect::<BTreeSet<_>>();
+
+        let mut removed = Vec::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let orphan = match name.split_once('.') {
+                Some((id, ext)) if id.parse::<usize>().is_ok() => {
+                    let id = id.parse::<usize>().unwrap();
+                    match ext {
+                        "sst" => !live_ssts.contains(&id),
+                        "vlog" => !live_value_logs.contains(&id),
+                        // WALs are not replayed without `enable_wal`, but keep them for when it is
+                        // enabled again.
+                        "wal" => self.options.enable_wal && !live_wals.contains(&id),
+                        _ => false,
+                    }
+                }
+                _ => self.manifest().is_obsolete_file(name),
+            };
+            if orphan {
+                if self.options.quarantine_orphan_files {
+                    let lost = self.path.join("lost");
+                    std::fs::create_dir_all(&lost)?;
+                    std::fs::rename(entry.path(), lost.join(name))?;
+                } else {
+                    std::fs::remove_file(entry.path())?;
+                }
+                removed.push(entry.path());
+            }
+        }
+        if !removed.is_empty() {
+            self.sync_dir()?;
+            if self.options.quarantine_orphan_files {
+                File::open(self.path.join("lost"))?.sync_all()?;
+            }
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,60 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+/// Options of a single write, which override the storage-wide behavior.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Sync the WAL before the write returns, regardless of `WalSyncPolicy`.
+    pub sync: bool,
+    /// Do not append the write to the WAL. The write is lost on a crash unless its memtable has been
+    /// flushed.
+    pub disable_wal: bool,
+    /// Fail the write instead of delaying or blocking it when flushes or compactions fall behind the
+    /// limits in `WriteStallOptions`.
+    pub no_slowdown: bool,
+}
+
+/// Options of a single read.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Read as of this commit timestamp, e.g. the one of a `Snapshot`, instead of the latest one.
+    /// It must be within the retained history.
+    pub read_ts: Option<u64>,
+    /// Insert the blocks read from SSTs into the block cache. Large scans can turn this off to keep
+    /// the cached blocks of other reads. Blocks read without `verify_checksums` are never cached.
+    pub fill_cache: bool,
+    /// Verify the checksums of the blocks read from SSTs.
+    pub verify_checksums: bool,
+    /// Scans stop before this key, in addition to their own upper bound.
+    pub iterate_upper_bound: Option<Bytes>,
+    /// Number of bytes of the following blocks to read together with a block when a scan moves
+    /// forward to it, so that a scan over many blocks issues fewer reads. 0 reads one block at a
+    /// time.
+    pub readahead_size: usize,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            read_ts: None,
+            fill_cache: true,
+            verify_checksums: true,
+            iterate_upper_bound: None,
+            readahead_size: 0,
+        }
+    }
+}
+
+impl ReadOptions {
+    /// The tighter of the upper bound of a scan and `iterate_upper_bound`.
+    pub(crate) fn upper_bound<'a>(&'a self, upper: Bound<&'a [u8]>) -> Bound<&'a [u8]> {
+        let Some(bound) = &self.iterate_upper_bound else {
+            return upper;
+        };
+        match upper {
+            Bound::Included(key) | Bound::Excluded(key) if key < bound.as_ref() => upper,
+            _ => Bound::Excluded(bound.as_ref()),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+/// Deletes all versions of the keys in `[start, end)` written before `ts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, ts: u64) -> Self {
+        Self { start, end, ts }
+    }
+
+    /// Whether `key` is in the range of this tombstone.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.start.as_ref() <= key && key < self.end.as_ref()
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by this tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        ts < self.ts && self.contains(key)
+    }
+
+    /// Whether the range of this tombstone overlaps with the key range `[first, last]`.
+    pub fn overlaps(&self, first: &[u8], last: &[u8]) -> bool {
+        self.start.as_ref() <= last && first < self.end.as_ref()
+    }
+}
+
+/// Range tombstones split into non-overlapping fragments, so that the tombstones of a key are found
+/// with a binary search. The fragments of an SST are computed once and cached with the SST.
+#[derive(Clone, Debug, Default)]
+pub struct FragmentedRangeTombstones {
+    /// Sorted by the start key.
+    fragments: Vec<Fragment>,
+}
+
+#[derive(Clone, Debug)]
+struct Fragment {
+    start: Bytes,
+    end: Bytes,
+    /// Timestamps of the tombstones covering the fragment, in descending order.
+    ts: Vec<u64>,
+}
+
+impl FragmentedRangeTombstones {
+    pub fn new(tombstones: impl IntoIterator<Item = RangeTombstone>) -> Self {
+        let mut tombstones = tombstones.into_iter().collect::<Vec<_>>();
+        tombstones.sort_by(|a, b| a.start.cmp(&b.start));
+        let mut bounds = tombstones
+            .iter()
+            .flat_map(|tombstone| [tombstone.start.clone(), tombstone.end.clone()])
+            .collect::<Vec<_>>();
+        bounds.sort();
+        bounds.dedup();
+        let mut fragments = Vec::new();
+        let mut active = Vec::<&RangeTombstone>::new();
+        let mut next = 0;
+        for window in bounds.windows(2) {
+            let (start, end) = (&window[0], &window[1]);
+            while next < tombstones.len() && tombstones[next].start <= start {
+                active.push(&tombstones[next]);
+                next += 1;
+            }
+            // A tombstone in the set covers the whole fragment, as no bound lies within it.
+            active.retain(|tombstone| tombstone.end > start);
+            if !active.is_empty() {
+                let mut ts = active
+                    .iter()
+                    .map(|tombstone| tombstone.ts)
+                    .collect::<Vec<_>>();
+                ts.sort_by(|a, b| b.cmp(a));
+                fragments.push(Fragment {
+                    start: start.clone(),
+                    end: end.clone(),
+                    ts,
+                });
+            }
+        }
+        Self { fragments }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by any of the tombstones written at or
+    /// before `read_ts`.
+    pub fn covers(&self, key: &[u8], ts: u64, read_ts: u64) -> bool {
+        let idx = self
+            .fragments
+            .partition_point(|fragment| fragment.start.as_ref() <= key);
+        let Some(fragment) = idx.checked_sub(1).map(|idx| &self.fragments[idx]) else {
+            return false;
+        };
+        key < fragment.end.as_ref()
+            && fragment
+                .ts
+                .iter()
+                .find(|tombstone_ts| **tombstone_ts <= read_ts)
+                .is_some_and(|tombstone_ts| ts < *tombstone_ts)
+    }
+}
+
+/// The range tombstones visible to a read, which are checked against the versions read from the
+/// LSM tree.
+#[derive(Clone, Debug, Default)]
+pub struct RangeTombstones {
+    read_ts: u64,
+    /// The tombstones of each memtable and SST the read may see.
+    sources: Vec<Arc<FragmentedRangeTombstones>>,
+}
+
+impl RangeTombstones {
+    pub fn new(read_ts: u64, sources: Vec<Arc<FragmentedRangeTombstones>>) -> Self {
+        let sources = sources
+            .into_iter()
+            .filter(|source| !source.is_empty())
+            .collect();
+        Self { read_ts, sources }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by any of the tombstones.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        self.sources
+            .iter()
+            .any(|source| source.covers(key, ts, self.read_ts))
+    }
+}
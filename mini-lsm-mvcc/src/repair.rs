@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{BlockCache, LsmStorageInner, LsmStorageOptions, LsmStorageState};
+use crate::manifest::{Manifest, ManifestRecord};
+use crate::mem_table::MemTable;
+use crate::table::{SsTable, SsTableBuilder, SstMeta};
+
+/// Capacity of the block cache used to validate SSTs, which is private to the repair so that it
+/// does not take a shared cache from open storages.
+const REPAIR_BLOCK_CACHE_SIZE: usize = 8 << 20;
+
+/// What `repair` found and did.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// SSTs in the rebuilt manifest, including those written from WALs.
+    pub ssts: Vec<usize>,
+    /// SSTs that failed validation, with the error. They are renamed to `<id>.sst.corrupt` and left
+    /// out of the manifest.
+    pub quarantined_ssts: Vec<(usize, String)>,
+    /// WALs replayed into new SSTs, with the id of the SST written, if the WAL had any data.
+    pub replayed_wals: Vec<(usize, Option<usize>)>,
+    /// WALs with corrupted batches, which were skipped while the intact batches were replayed.
+    pub corrupted_wals: Vec<usize>,
+    /// SSTs that the old manifest recorded as removed, whose files were left behind. They are left
+    /// out of the manifest and deleted.
+    pub removed_ssts: Vec<usize>,
+}
+
+/// An SST going into the rebuilt manifest.
+struct LiveSst {
+    id: usize,
+    max_ts: u64,
+    /// The range of user keys covered by the keys and the range tombstones of the SST, if any.
+    key_range: Option<(Bytes, Bytes)>,
+    /// Metadata of the SST recorded in the manifest.
+    meta: SstMeta,
+}
+
+impl LiveSst {
+    fn new(sst: &SsTable) -> Self {
+        let mut ranges = sst
+            .range_tombstones()
+            .iter()
+            .map(|tombstone| (tombstone.start.clone(), tombstone.end.clone()))
+            .collect::<Vec<_>>();
+        if sst.num_of_blocks() > 0 {
+            ranges.push((
+                Bytes::copy_from_slice(sst.first_key().key_ref()),
+                Bytes::copy_from_slice(sst.last_key().key_ref()),
+            ));
+        }
+        let key_range = ranges
+            .into_iter()
+            .reduce(|(start, end), (other_start, other_end)| {
+                (start.min(other_start), end.max(other_end))
+            });
+        Self {
+            id: sst.sst_id(),
+            max_ts: sst.max_ts(),
+            key_range,
+            meta: sst.meta(),
+        }
+    }
+
+    fn overlaps(&self, other: &LiveSst) -> bool {
+        match (&self.key_range, &other.key_range) {
+            (Some((start, end)), Some((other_start, other_end))) => {
+                start <= other_end && other_start <= end
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Assign `ssts` to L0 and the `num_levels` levels below it. The order in which the SSTs were
+/// written is approximated by their `max_ts`: from the oldest one, each SST goes right above the
+/// highest level with an SST it overlaps, or to the bottom level. So newer versions of a key stay
+/// above older ones, and SSTs in the same level do not overlap. SSTs that do not fit in the levels
+/// go to L0, newest first.
+fn assign_levels(mut ssts: Vec<LiveSst>, num_levels: usize) -> (Vec<usize>, Vec<Vec<usize>>) {
+    ssts.sort_by_key(|sst| (sst.max_ts, sst.id));
+    // Level of each SST, where 0 is L0.
+    let mut assigned: Vec<(LiveSst, usize)> = Vec::with_capacity(ssts.len());
+    for sst in ssts {
+        let level = assigned
+            .iter()
+            .filter(|(other, _)| sst.overlaps(other))
+            .map(|(_, level)| level.saturating_sub(1))
+            .min()
+            .unwrap_or(num_levels);
+        assigned.push((sst, level));
+    }
+    let l0_sstables = assigned
+        .iter()
+        .rev()
+        .filter(|(_, level)| *level == 0)
+        .map(|(sst, _)| sst.id)
+        .collect();
+    let mut levels = vec![Vec::new(); num_levels];
+    assigned.sort_by(|(x, _), (y, _)| x.key_range.cmp(&y.key_range));
+    for (sst, level) in assigned {
+        if level > 0 {
+            levels[level - 1].push(sst.id);
+        }
+    }
+    (l0_sstables, levels)
+}
+
+/// SSTs that the readable records of the old manifest remove with compactions or value log GC.
+fn removed_ssts(records: &[ManifestRecord], removed: &mut HashSet<usize>) {
+    for record in records {
+        match record {
+            ManifestRecord::Compaction(task, _) => removed.extend(task.input_sst_ids()),
+            ManifestRecord::ValueLogGc { input_ssts, .. } => removed.extend(input_ssts),
+            ManifestRecord::ColumnFamily(_, record) => {
+                removed_ssts(std::slice::from_ref(record), removed)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Open an SST with its value logs and read all of its blocks.
+fn validate_sst(path: &Path, id: usize, block_cache: &Arc<BlockCache>) -> Result<SsTable> {
+    let sst = LsmStorageInner::open_sst(path, id, block_cache, &mut HashMap::new(), None)?;
+    for block_idx in 0..sst.num_of_blocks() {
+        sst.read_block(block_idx)?;
+    }
+    Ok(sst)
+}
+
+/// Rebuild the manifest of the database in `path` from the files in it, for when the manifest is
+/// lost or corrupted. SSTs that the readable part of the old manifest records as removed are
+/// skipped, every other SST is validated, and the ones that cannot be read are quarantined.
+/// Leftover WALs are replayed into new SSTs, up to their first corrupted batch. A new manifest is
+/// then written with the SSTs assigned to levels, or in a tier each with tiered compaction, which
+/// `MiniLsm::open` accepts with the same `options`. The old manifest and the WALs are only deleted
+/// once the new manifest is in place.
+///
+/// Column families are not recorded in SSTs and WALs, so all of their data ends up in the default
+/// column family.
+pub fn repair(path: impl AsRef<Path>, options: &LsmStorageOptions) -> Result<RepairReport> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        bail!("no database found in {}", path.display());
+    }
+    let mut ssts = BTreeMap::new();
+    let mut wals = BTreeMap::new();
+    let mut manifest_files = Vec::new();
+    let mut next_id = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if Manifest::is_manifest_file(name) {
+            manifest_files.push(entry.path());
+            continue;
+        }
+        let Some((id, ext)) = name.split_once('.') else {
+            continue;
+        };
+        let Ok(id) = id.parse::<usize>() else {
+            continue;
+        };
+        next_id = next_id.max(id + 1);
+        match ext {
+            "sst" => {
+                ssts.insert(id, entry.path());
+            }
+            "wal" => {
+                wals.insert(id, entry.path());
+            }
+            _ => {}
+        }
+    }
+
+    let mut report = RepairReport::default();
+    let mut removed = HashSet::new();
+    if let Ok((records, _)) = Manifest::salvage(path) {
+        removed_ssts(&records, &mut removed);
+    }
+    let block_cache = Arc::new(BlockCache::new(REPAIR_BLOCK_CACHE_SIZE));
+    let mut live_ssts = Vec::new();
+    let mut removed_sst_paths = Vec::new();
+    for (id, sst_path) in ssts {
+        if removed.contains(&id) {
+            report.removed_ssts.push(id);
+            removed_sst_paths.push(sst_path);
+            continue;
+        }
+        match validate_sst(path, id, &block_cache) {
+            Ok(sst) => live_ssts.push(LiveSst::new(&sst)),
+            Err(e) => {
+                let quarantine_path = sst_path.with_extension("sst.corrupt");
+                std::fs::rename(&sst_path, quarantine_path)?;
+                println!("quarantined corrupted {}.sst: {:#}", id, e);
+                report.quarantined_ssts.push((id, format!("{:#}", e)));
+            }
+        }
+    }
+
+    for (&wal_id, wal_path) in &wals {
+        let (memtable, corrupted) = MemTable::salvage_from_wal(wal_id, wal_path)?;
+        if corrupted {
+            report.corrupted_wals.push(wal_id);
+        }
+        if memtable.is_empty() {
+            report.replayed_wals.push((wal_id, None));
+            continue;
+        }
+        let sst_id = next_id;
+        next_id += 1;
+        let mut builder = SsTableBuilder::new(options.block_size)
+            .with_compression(options.compression)
+            .with_block_hash_index(options.block_hash_index)
+            .with_partitioned_index(options.partitioned_index);
+        if let Some(threshold) = options.value_separation_threshold {
+            builder = builder.with_value_separation(
+                threshold,
+                sst_id,
+                LsmStorageInner::path_of_vlog_static(path, sst_id),
+            );
+        }
+        memtable.flush(&mut builder)?;
+        let sst = builder.build(
+            sst_id,
+            None,
+            LsmStorageInner::path_of_sst_static(path, sst_id),
+        )?;
+        println!("replayed {}.wal into {}.sst", wal_id, sst_id);
+        live_ssts.push(LiveSst::new(&sst));
+        report.replayed_wals.push((wal_id, Some(sst_id)));
+    }
+
+    let last_commit_ts = live_ssts
+        .iter()
+        .map(|sst| sst.max_ts)
+        .max()
+        .unwrap_or_default();
+    let sst_metas = live_ssts.iter().map(|sst| sst.meta.clone()).collect();
+    let mut state = LsmStorageState::create(options);
+    if let CompactionOptions::Tiered(_) = options.compaction_options {
+        // Tiers may overlap, so each SST is a tier, newest first.
+        live_ssts.sort_by_key(|sst| std::cmp::Reverse((sst.max_ts, sst.id)));
+        state.levels = live_ssts.iter().map(|sst| (sst.id, vec![sst.id])).collect();
+    } else {
+        let (l0_sstables, levels) = assign_levels(live_ssts, state.levels.len());
+        state.l0_sstables = l0_sstables;
+        for ((_, level), ssts) in state.levels.iter_mut().zip(levels) {
+            *level = ssts;
+        }
+    }
+    report.ssts = state
+        .l0_sstables
+        .iter()
+        .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        .copied()
+        .collect();
+    report.ssts.sort();
+
+    Manifest::replace(
+        path,
+        &[ManifestRecord::Snapshot {
+            l0_sstables: state.l0_sstables,
+            levels: state.levels,
+            last_commit_ts,
+            ssts: sst_metas,
+        }],
+    )?;
+    for manifest_file in manifest_files {
+        if !manifest_file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(Manifest::is_current_file)
+        {
+            std::fs::remove_file(manifest_file)?;
+        }
+    }
+    for file in wals.values().chain(&removed_sst_paths) {
+        std::fs::remove_file(file)?;
+    }
+    File::open(path)?.sync_all()?;
+    Ok(report)
+}
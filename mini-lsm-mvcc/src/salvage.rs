@@ -0,0 +1,50 @@
+/// What could not be read from a damaged database opened with `MiniLsm::open_read_only_salvage`.
+/// Everything not listed here is readable. Data blocks and value log records are only checked
+/// when they are read, so damage in them is added to the report of `MiniLsm::salvage_report` as
+/// reads find it.
+#[derive(Clone, Debug, Default)]
+pub struct SalvageReport {
+    /// The manifest ends with a corrupted record. Only the records before it are used, so the LSM
+    /// structure may be older than before the damage.
+    pub manifest_truncated: bool,
+    /// SSTs that cannot be opened, with the error. None of their data is readable.
+    pub skipped_ssts: Vec<(usize, String)>,
+    /// Data blocks that cannot be read, by SST id and block index. The keys in them are skipped.
+    pub corrupted_blocks: Vec<(usize, usize)>,
+    /// SSTs whose bloom filter is corrupted, which are read without it.
+    pub corrupted_blooms: Vec<usize>,
+    /// WALs with corrupted batches, which are skipped while the intact batches are replayed.
+    pub corrupted_wals: Vec<usize>,
+    /// Value logs that cannot be opened or have a corrupted record, with the first error. Reading
+    /// a value stored in a damaged part of a value log fails.
+    pub corrupted_value_logs: Vec<(usize, String)>,
+}
+
+impl SalvageReport {
+    /// Whether no damage was found.
+    pub fn is_clean(&self) -> bool {
+        !self.manifest_truncated
+            && self.skipped_ssts.is_empty()
+            && self.corrupted_blocks.is_empty()
+            && self.corrupted_blooms.is_empty()
+            && self.corrupted_wals.is_empty()
+            && self.corrupted_value_logs.is_empty()
+    }
+
+    pub(crate) fn add_corrupted_block(&mut self, sst_id: usize, block_idx: usize) {
+        if !self.corrupted_blocks.contains(&(sst_id, block_idx)) {
+            self.corrupted_blocks.push((sst_id, block_idx));
+        }
+    }
+
+    pub(crate) fn add_corrupted_value_log(&mut self, file_id: usize, error: &anyhow::Error) {
+        if !self
+            .corrupted_value_logs
+            .iter()
+            .any(|(id, _)| *id == file_id)
+        {
+            self.corrupted_value_logs
+                .push((file_id, format!("{:#}", error)));
+        }
+    }
+}
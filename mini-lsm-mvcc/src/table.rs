@@ -1,22 +1,71 @@
 pub(crate) mod bloom;
 mod builder;
+mod cache;
+mod compression;
 mod iterator;
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
+pub use cache::TableCache;
+pub use compression::CompressionType;
 pub use iterator::SsTableIterator;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 
-use crate::block::Block;
+use crate::block::{Block, BlockFormat, BlockIterator};
+use crate::block_cache::CachePriority;
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::options::ReadOptions;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::salvage::SalvageReport;
+use crate::value_log::{ValueLogFile, ValuePointer};
+use crate::varint::{get_varint, put_varint, varint_len};
 
 use self::bloom::Bloom;
 
+/// Magic number at the end of every SST written with a format footer. SSTs without it use the
+/// legacy layout that ends with the bloom filter offset.
+pub(crate) const SST_MAGIC: u64 = 0x6d69_6e69_6c73_6d21;
+
+/// The SST format without a footer.
+pub(crate) const SST_FORMAT_LEGACY: u32 = 0;
+/// Block meta records the compression type of each data block.
+pub(crate) const SST_FORMAT_COMPRESSION: u32 = 1;
+/// The SST lists the value log files it refers to. If value separation is used, values carry a tag
+/// that tells inline values apart from value log pointers.
+pub(crate) const SST_FORMAT_VALUE_LOG: u32 = 2;
+/// The SST stores range tombstones in a section after the value log refs. An SST may have range
+/// tombstones but no data blocks.
+pub(crate) const SST_FORMAT_RANGE_TOMBSTONE: u32 = 3;
+/// Key and value lengths are varints and data blocks use `u32` entry offsets, so that keys and
+/// values may be larger than 64 KiB.
+pub(crate) const SST_FORMAT_VARINT: u32 = 4;
+/// Keys in data blocks are delta-encoded against the previous key, with full keys at restart
+/// points.
+pub(crate) const SST_FORMAT_RESTART: u32 = 5;
+/// Data blocks may have a hash index for point lookups, which is flagged in their number of
+/// restart points.
+pub(crate) const SST_FORMAT_BLOCK_HASH_INDEX: u32 = 6;
+/// An index type byte before the value log refs tells whether the SST has the block meta and bloom
+/// filter sections, or a partitioned index and filter.
+pub(crate) const SST_FORMAT_PARTITIONED_INDEX: u32 = 7;
+
+/// The format version used for newly-built SSTs.
+pub(crate) const SST_FORMAT_LATEST: u32 = SST_FORMAT_PARTITIONED_INDEX;
+
+/// The SST has the meta of all data blocks and a bloom filter of all keys.
+const INDEX_TYPE_FULL: u8 = 0;
+/// The SST has index and filter partitions covering ranges of data blocks, followed by a
+/// top-level index of the partitions.
+const INDEX_TYPE_PARTITIONED: u8 = 1;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
     /// Offset of this data block.
@@ -25,6 +74,8 @@ pub struct BlockMeta {
     pub first_key: KeyBytes,
     /// The last key of the data block.
     pub last_key: KeyBytes,
+    /// The codec used to compress this data block.
+    pub compression: CompressionType,
 }
 
 impl BlockMeta {
@@ -34,12 +85,14 @@ impl BlockMeta {
         for meta in block_meta {
             // The size of offset
             estimated_size += std::mem::size_of::<u32>();
+            // The size of compression type
+            estimated_size += std::mem::size_of::<u8>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += varint_len(meta.first_key.key_len() as u64);
             // The size of actual key
             estimated_size += meta.first_key.raw_len();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += varint_len(meta.last_key.key_len() as u64);
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
@@ -53,10 +106,11 @@ impl BlockMeta {
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put_u8(meta.compression.to_u8());
+            put_varint(buf, meta.first_key.key_len() as u64);
             buf.put_slice(meta.first_key.key_ref());
             buf.put_u64(meta.first_key.ts());
-            buf.put_u16(meta.last_key.key_len() as u16);
+            put_varint(buf, meta.last_key.key_len() as u64);
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
@@ -65,23 +119,36 @@ impl BlockMeta {
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    /// Decode block meta from a buffer written in the given SST format version.
+    pub fn decode_block_meta(mut buf: &[u8], version: u32) -> Result<(Vec<BlockMeta>, u64)> {
+        if buf.len() < 16 {
+            bail!("meta too short");
+        }
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
+        // Verify the checksum before decoding, so that a corrupted length cannot overrun the buffer.
+        if (&buf[buf.remaining() - 4..]).get_u32() != checksum {
+            bail!("meta checksum mismatched");
+        }
         for _ in 0..num {
             let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let compression = if version >= SST_FORMAT_COMPRESSION {
+                CompressionType::from_u8(buf.get_u8())?
+            } else {
+                CompressionType::None
+            };
+            let first_key_len = get_key_len(&mut buf, version)?;
             let first_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
-            let last_key_len: usize = buf.get_u16() as usize;
+            let last_key_len = get_key_len(&mut buf, version)?;
             let last_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
             block_meta.push(BlockMeta {
                 offset,
                 first_key,
                 last_key,
+                compression,
             });
         }
         let max_ts = buf.get_u64();
@@ -93,17 +160,217 @@ impl BlockMeta {
     }
 }
 
+impl BlockMeta {
+    /// Encode the meta as the value of its entry in an index partition, which is keyed by the first
+    /// key of the block.
+    fn encode_index_value(&self, buf: &mut Vec<u8>) {
+        buf.put_u32(self.offset as u32);
+        buf.put_u8(self.compression.to_u8());
+        put_varint(buf, self.last_key.key_len() as u64);
+        buf.put_slice(self.last_key.key_ref());
+        buf.put_u64(self.last_key.ts());
+    }
+
+    fn decode_index_entry(first_key: KeySlice, mut value: &[u8]) -> Result<Self> {
+        let offset = value.get_u32() as usize;
+        let compression = CompressionType::from_u8(value.get_u8())?;
+        let last_key_len = get_varint(&mut value)? as usize;
+        let last_key =
+            KeyBytes::from_bytes_with_ts(value.copy_to_bytes(last_key_len), value.get_u64());
+        Ok(Self {
+            offset,
+            first_key: first_key.to_key_vec().into_key_bytes(),
+            last_key,
+            compression,
+        })
+    }
+}
+
+/// A partition of the index or of the bloom filter of an SST, which covers a range of data blocks.
+/// An index partition is a block with an entry per data block keyed by its first key, and a
+/// filter partition is a bloom filter of the keys in the data blocks.
+#[derive(Clone, Debug)]
+struct Partition {
+    /// Index of the first data block covered by the partition.
+    first_block_idx: usize,
+    /// The first key of the first data block.
+    first_key: KeyBytes,
+    /// Offset of the partition in the SST.
+    offset: usize,
+    /// Length of the partition, including its checksum.
+    len: usize,
+}
+
+/// The top-level index of an SST with a partitioned index and filter, which lists the partitions
+/// and is read when opening the SST.
+struct TopLevelIndex {
+    index_partitions: Vec<Partition>,
+    filter_partitions: Vec<Partition>,
+    num_blocks: usize,
+    last_key: KeyBytes,
+    max_ts: u64,
+}
+
+impl TopLevelIndex {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u32(self.index_partitions.len() as u32);
+        for (index, filter) in self.index_partitions.iter().zip(&self.filter_partitions) {
+            buf.put_u32(index.first_block_idx as u32);
+            put_varint(buf, index.first_key.key_len() as u64);
+            buf.put_slice(index.first_key.key_ref());
+            buf.put_u64(index.first_key.ts());
+            buf.put_u32(index.offset as u32);
+            buf.put_u32(index.len as u32);
+            buf.put_u32(filter.offset as u32);
+            buf.put_u32(filter.len as u32);
+        }
+        buf.put_u32(self.num_blocks as u32);
+        put_varint(buf, self.last_key.key_len() as u64);
+        buf.put_slice(self.last_key.key_ref());
+        buf.put_u64(self.last_key.ts());
+        buf.put_u64(self.max_ts);
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 4 {
+            bail!("top-level index too short");
+        }
+        let (mut data, mut checksum) = buf.split_at(buf.len() - 4);
+        // Verify the checksum before decoding, so that a corrupted length cannot overrun the buffer.
+        if checksum.get_u32() != crc32fast::hash(data) {
+            bail!("top-level index checksum mismatched");
+        }
+        let num = data.get_u32() as usize;
+        let mut index_partitions = Vec::with_capacity(num);
+        let mut filter_partitions = Vec::with_capacity(num);
+        for _ in 0..num {
+            let first_block_idx = data.get_u32() as usize;
+            let first_key_len = get_varint(&mut data)? as usize;
+            let first_key =
+                KeyBytes::from_bytes_with_ts(data.copy_to_bytes(first_key_len), data.get_u64());
+            for partitions in [&mut index_partitions, &mut filter_partitions] {
+                partitions.push(Partition {
+                    first_block_idx,
+                    first_key: first_key.clone(),
+                    offset: data.get_u32() as usize,
+                    len: data.get_u32() as usize,
+                });
+            }
+        }
+        let num_blocks = data.get_u32() as usize;
+        let last_key_len = get_varint(&mut data)? as usize;
+        let last_key =
+            KeyBytes::from_bytes_with_ts(data.copy_to_bytes(last_key_len), data.get_u64());
+        let max_ts = data.get_u64();
+        Ok(Self {
+            index_partitions,
+            filter_partitions,
+            num_blocks,
+            last_key,
+            max_ts,
+        })
+    }
+}
+
+/// The index partitions of an SST, which are kept in memory instead of the meta of all blocks.
+struct PartitionedIndex {
+    partitions: Vec<Partition>,
+    num_blocks: usize,
+}
+
+/// The index of the partition covering a data block.
+fn find_partition(partitions: &[Partition], block_idx: usize) -> usize {
+    partitions.partition_point(|partition| partition.first_block_idx <= block_idx) - 1
+}
+
+/// Read a key length from the SST sections, which is a varint since `SST_FORMAT_VARINT`.
+fn get_key_len(buf: &mut &[u8], version: u32) -> Result<usize> {
+    if version >= SST_FORMAT_VARINT {
+        Ok(get_varint(buf)? as usize)
+    } else {
+        Ok(buf.get_u16() as usize)
+    }
+}
+
+/// Encode the value log files referred to by an SST, with the number of bytes referred to in each.
+fn encode_value_log_refs(values_tagged: bool, refs: &BTreeMap<usize, u64>, buf: &mut Vec<u8>) {
+    let offset = buf.len();
+    buf.put_u8(values_tagged as u8);
+    buf.put_u32(refs.len() as u32);
+    for (file_id, bytes) in refs {
+        buf.put_u64(*file_id as u64);
+        buf.put_u64(*bytes);
+    }
+    let checksum = crc32fast::hash(&buf[offset..]);
+    buf.put_u32(checksum);
+}
+
+fn decode_value_log_refs(buf: &[u8]) -> Result<(bool, BTreeMap<usize, u64>)> {
+    if buf.len() < 4 {
+        bail!("value log refs too short");
+    }
+    let (mut data, mut checksum) = buf.split_at(buf.len() - 4);
+    if checksum.get_u32() != crc32fast::hash(data) {
+        bail!("value log refs checksum mismatched");
+    }
+    let values_tagged = data.get_u8() != 0;
+    let num = data.get_u32() as usize;
+    let mut refs = BTreeMap::new();
+    for _ in 0..num {
+        let file_id = data.get_u64() as usize;
+        refs.insert(file_id, data.get_u64());
+    }
+    Ok((values_tagged, refs))
+}
+
+/// Encode the range tombstones of an SST.
+fn encode_range_tombstones(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+    let offset = buf.len();
+    buf.put_u32(tombstones.len() as u32);
+    for tombstone in tombstones {
+        put_varint(buf, tombstone.start.len() as u64);
+        buf.put_slice(&tombstone.start);
+        put_varint(buf, tombstone.end.len() as u64);
+        buf.put_slice(&tombstone.end);
+        buf.put_u64(tombstone.ts);
+    }
+    let checksum = crc32fast::hash(&buf[offset..]);
+    buf.put_u32(checksum);
+}
+
+fn decode_range_tombstones(buf: &[u8], version: u32) -> Result<Vec<RangeTombstone>> {
+    if buf.len() < 4 {
+        bail!("range tombstones too short");
+    }
+    let (mut data, mut checksum) = buf.split_at(buf.len() - 4);
+    if checksum.get_u32() != crc32fast::hash(data) {
+        bail!("range tombstones checksum mismatched");
+    }
+    let num = data.get_u32() as usize;
+    let mut tombstones = Vec::with_capacity(num);
+    for _ in 0..num {
+        let start_len = get_key_len(&mut data, version)?;
+        let start = data.copy_to_bytes(start_len);
+        let end_len = get_key_len(&mut data, version)?;
+        let end = data.copy_to_bytes(end_len);
+        tombstones.push(RangeTombstone::new(start, end, data.get_u64()));
+    }
+    Ok(tombstones)
+}
+
 /// A file object.
 pub struct FileObject(Option<File>, u64);
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
         use std::os::unix::fs::FileExt;
+        let Some(file) = &self.0 else {
+            bail!("file object has no file");
+        };
         let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
+        file.read_exact_at(&mut data[..], offset)?;
         Ok(data)
     }
 
@@ -132,16 +399,84 @@ impl FileObject {
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
     pub(crate) file: FileObject,
-    /// The meta blocks that hold info for data blocks.
+    /// The meta blocks that hold info for data blocks. Empty if the index is partitioned.
     pub(crate) block_meta: Vec<BlockMeta>,
-    /// The offset that indicates the start point of meta blocks in `file`.
+    /// The offset that indicates the start point of meta blocks in `file`, which is where the data
+    /// blocks end.
     pub(crate) block_meta_offset: usize,
+    /// The index partitions, if the index is partitioned.
+    partitioned_index: Option<PartitionedIndex>,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
     first_key: KeyBytes,
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
+    /// The filter partitions, if the bloom filter is partitioned.
+    filter_partitions: Option<Vec<Partition>>,
     max_ts: u64,
+    /// Whether values are tagged as inline values or value log pointers.
+    values_tagged: bool,
+    /// Value log files referred to by this SST, and the number of bytes referred to in each.
+    value_log_refs: BTreeMap<usize, u64>,
+    /// Open handles of the value log files in `value_log_refs`.
+    value_logs: HashMap<usize, Arc<ValueLogFile>>,
+    /// Range tombstones stored in this SST, which are kept in memory while it is open.
+    range_tombstones: Vec<RangeTombstone>,
+    /// `range_tombstones` split into fragments, computed when the SST is first read with them.
+    fragmented_tombstones: OnceLock<Arc<FragmentedRangeTombstones>>,
+    /// Where damage found when reading is reported, if the SST was opened with
+    /// `SsTable::open_salvage`.
+    salvage: Option<Arc<Mutex<SalvageReport>>>,
+    /// The format version the SST is written in.
+    format_version: u32,
+    /// Set if this is a lightweight handle of an SST that is opened through the table cache, see
+    /// `SsTable::open_lazy`.
+    lazy: Option<LazyTable>,
+}
+
+/// Metadata of an SST, which is enough to place the SST in the LSM structure and to decide whether
+/// to read it without opening it. It is recorded in the manifest for every new SST, so that the
+/// SSTs are not opened when the storage is opened.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SstMeta {
+    pub id: usize,
+    pub size: u64,
+    /// The first and last keys with their timestamps, which are empty if the SST has no keys.
+    pub first_key: (Vec<u8>, u64),
+    pub last_key: (Vec<u8>, u64),
+    pub max_ts: u64,
+    pub value_log_refs: BTreeMap<usize, u64>,
+    /// The smallest start and largest end key of the range tombstones of the SST, if it has any.
+    pub tombstone_range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Where a lightweight SST handle opens the SST from.
+struct LazyTable {
+    id: usize,
+    path: PathBuf,
+    table_cache: Arc<TableCache>,
+    tombstone_range: Option<(Bytes, Bytes)>,
+}
+
+impl Drop for LazyTable {
+    /// Close the SST in the table cache, so that an SST removed from the LSM structure does not
+    /// hold its file open until it is evicted.
+    fn drop(&mut self) {
+        self.table_cache.close(self.id);
+    }
+}
+
+/// Read the offset stored in the 4 bytes before `end`, which points to the section ending there.
+fn read_section_offset(file: &FileObject, end: u64) -> Result<u64> {
+    if end < 4 {
+        bail!("SST section offset out of bounds");
+    }
+    let raw_offset = file.read(end - 4, 4)?;
+    let offset = (&raw_offset[..]).get_u32() as u64;
+    if offset > end - 4 {
+        bail!("SST section offset out of bounds");
+    }
+    Ok(offset)
 }
 impl SsTable {
     #[cfg(test)]
@@ -149,27 +484,221 @@ impl SsTable {
         Self::open(0, None, file)
     }
 
+    /// Read the format footer of an SST. Returns the format version and the length of the file
+    /// excluding the footer.
+    fn read_footer(file: &FileObject) -> Result<(u32, u64)> {
+        let len = file.size();
+        if len < 16 {
+            return Ok((SST_FORMAT_LEGACY, len));
+        }
+        let raw_footer = file.read(len - 12, 12)?;
+        let mut footer = &raw_footer[..];
+        let version = footer.get_u32();
+        if footer.get_u64() != SST_MAGIC {
+            return Ok((SST_FORMAT_LEGACY, len));
+        }
+        if version > SST_FORMAT_LATEST {
+            bail!("unsupported SST format version {}", version);
+        }
+        Ok((version, len - 12))
+    }
+
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
+        Ok(Self::open_inner(id, block_cache, file, false)?.0)
+    }
+
+    /// Open an SST that may be damaged, reporting damage to `report`. A corrupted bloom filter is
+    /// dropped, and data blocks are checked when they are read, where a block that cannot be read
+    /// is reported and read as empty, so that the rest of the SST stays readable. Fails if the meta
+    /// of the SST is corrupted.
+    pub fn open_salvage(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        report: Arc<Mutex<SalvageReport>>,
+    ) -> Result<Self> {
+        let (mut sst, mut bloom_corrupted) = Self::open_inner(id, block_cache, file, true)?;
+        if let Some(partitions) = &sst.filter_partitions {
+            if (0..partitions.len()).any(|idx| sst.read_filter_partition(idx).is_err()) {
+                sst.filter_partitions = None;
+                bloom_corrupted = true;
+            }
+        }
+        if bloom_corrupted {
+            report.lock().corrupted_blooms.push(id);
+        }
+        sst.salvage = Some(report);
+        Ok(sst)
+    }
+
+    /// Open an SST. If `salvage`, a corrupted bloom filter is dropped instead of failing, which is
+    /// reported in the returned flag. The filter partitions of a partitioned SST are only read
+    /// when used.
+    fn open_inner(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        salvage: bool,
+    ) -> Result<(Self, bool)> {
+        let (version, mut len) = Self::read_footer(&file)?;
+        let mut range_tombstones = Vec::new();
+        if version >= SST_FORMAT_RANGE_TOMBSTONE {
+            let tombstones_offset = read_section_offset(&file, len)?;
+            let raw_tombstones = file.read(tombstones_offset, len - 4 - tombstones_offset)?;
+            range_tombstones = decode_range_tombstones(&raw_tombstones, version)?;
+            len = tombstones_offset;
+        }
+        let mut values_tagged = false;
+        let mut value_log_refs = BTreeMap::new();
+        if version >= SST_FORMAT_VALUE_LOG {
+            let refs_offset = read_section_offset(&file, len)?;
+            let raw_refs = file.read(refs_offset, len - 4 - refs_offset)?;
+            (values_tagged, value_log_refs) = decode_value_log_refs(&raw_refs)?;
+            len = refs_offset;
+        }
+        if version >= SST_FORMAT_PARTITIONED_INDEX {
+            if len == 0 {
+                bail!("SST index type missing");
+            }
+            len -= 1;
+            match file.read(len, 1)?[0] {
+                INDEX_TYPE_FULL => {}
+                INDEX_TYPE_PARTITIONED => {
+                    let sst = Self::open_partitioned(id, block_cache, file, len, version)?;
+                    return Ok((
+                        Self {
+                            values_tagged,
+                            value_log_refs,
+                            range_tombstones,
+                            ..sst
+                        },
+                        false,
+                    ));
+                }
+                index_type => bail!("unknown SST index type {}", index_type),
+            }
+        }
+        let bloom_offset = read_section_offset(&file, len)?;
         let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
+        let bloom_filter = match Bloom::decode(&raw_bloom) {
+            Ok(bloom_filter) => Some(bloom_filter),
+            Err(_) if salvage => None,
+            Err(e) => return Err(e),
+        };
+        let block_meta_offset = read_section_offset(&file, bloom_offset)?;
         let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..], version)?;
+        if block_meta
+            .iter()
+            .any(|meta| meta.offset + 4 > block_meta_offset as usize)
+            || block_meta
+                .windows(2)
+                .any(|pair| pair[0].offset + 4 > pair[1].offset)
+        {
+            bail!("block offset out of bounds");
+        }
+        let bloom_corrupted = bloom_filter.is_none();
+        // An SST with only range tombstones has no data blocks and an empty key range.
+        Ok((
+            Self {
+                file,
+                first_key: block_meta
+                    .first()
+                    .map(|meta| meta.first_key.clone())
+                    .unwrap_or_default(),
+                last_key: block_meta
+                    .last()
+                    .map(|meta| meta.last_key.clone())
+                    .unwrap_or_default(),
+                block_meta,
+                block_meta_offset: block_meta_offset as usize,
+                partitioned_index: None,
+                id,
+                block_cache,
+                bloom: bloom_filter,
+                filter_partitions: None,
+                max_ts,
+                values_tagged,
+                value_log_refs,
+                value_logs: HashMap::new(),
+                range_tombstones,
+                salvage: None,
+                format_version: version,
+                lazy: None,
+                fragmented_tombstones: OnceLock::new(),
+            },
+            bloom_corrupted,
+        ))
+    }
+
+    /// Open an SST with a partitioned index and filter, whose top-level index ends at `len`.
+    fn open_partitioned(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        len: u64,
+        version: u32,
+    ) -> Result<Self> {
+        let index_offset = read_section_offset(&file, len)?;
+        let raw_index = file.read(index_offset, len - 4 - index_offset)?;
+        let index = TopLevelIndex::decode(&raw_index)?;
+        let data_end = index
+            .index_partitions
+            .first()
+            .map_or(index_offset as usize, |partition| partition.offset);
+        if index
+            .index_partitions
+            .iter()
+            .chain(&index.filter_partitions)
+            .any(|partition| {
+                partition.len < 4
+                    || partition.offset < data_end
+                    || partition.offset + partition.len > index_offset as usize
+            })
+            || index
+                .index_partitions
+                .windows(2)
+                .any(|pair| pair[0].first_block_idx >= pair[1].first_block_idx)
+            || index
+                .index_partitions
+                .first()
+                .is_some_and(|partition| partition.first_block_idx != 0)
+            || index
+                .index_partitions
+                .last()
+                .is_some_and(|partition| partition.first_block_idx >= index.num_blocks)
+            || (index.index_partitions.is_empty() && index.num_blocks > 0)
+        {
+            bail!("partition out of bounds");
+        }
         Ok(Self {
             file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
-            block_meta,
-            block_meta_offset: block_meta_offset as usize,
+            first_key: index
+                .index_partitions
+                .first()
+                .map(|partition| partition.first_key.clone())
+                .unwrap_or_default(),
+            last_key: index.last_key,
+            block_meta: Vec::new(),
+            block_meta_offset: data_end,
+            partitioned_index: Some(PartitionedIndex {
+                partitions: index.index_partitions,
+                num_blocks: index.num_blocks,
+            }),
             id,
             block_cache,
-            bloom: Some(bloom_filter),
-            max_ts,
+            bloom: None,
+            filter_partitions: Some(index.filter_partitions),
+            max_ts: index.max_ts,
+            values_tagged: false,
+            value_log_refs: BTreeMap::new(),
+            value_logs: HashMap::new(),
+            range_tombstones: Vec::new(),
+            salvage: None,
+            format_version: version,
+            lazy: None,
+            fragmented_tombstones: OnceLock::new(),
         })
     }
 
@@ -184,56 +713,302 @@ impl SsTable {
             file: FileObject(None, file_size),
             block_meta: vec![],
             block_meta_offset: 0,
+            partitioned_index: None,
             id,
             block_cache: None,
             first_key,
             last_key,
             bloom: None,
+            filter_partitions: None,
             max_ts: 0,
+            values_tagged: false,
+            value_log_refs: BTreeMap::new(),
+            value_logs: HashMap::new(),
+            range_tombstones: Vec::new(),
+            salvage: None,
+            format_version: SST_FORMAT_LATEST,
+            lazy: None,
+            fragmented_tombstones: OnceLock::new(),
+        }
+    }
+
+    /// The end offset of a data block, including its checksum.
+    fn block_end(&self, block_idx: usize) -> Result<usize> {
+        if block_idx + 1 < self.num_of_blocks() {
+            Ok(self.block_meta(block_idx + 1)?.offset)
+        } else {
+            Ok(self.block_meta_offset)
+        }
+    }
+
+    /// The meta of a data block, which is read from its index partition if the index is
+    /// partitioned.
+    pub(crate) fn block_meta(&self, block_idx: usize) -> Result<BlockMeta> {
+        let Some(index) = &self.partitioned_index else {
+            return Ok(self.block_meta[block_idx].clone());
+        };
+        let partition_idx = find_partition(&index.partitions, block_idx);
+        let first_block_idx = index.partitions[partition_idx].first_block_idx;
+        let block = self.read_index_partition(partition_idx)?;
+        let iter = BlockIterator::create_and_seek_to_restart(block, block_idx - first_block_idx);
+        if !iter.is_valid() {
+            bail!("block {} not found in index partition", block_idx);
+        }
+        let meta = BlockMeta::decode_index_entry(iter.key(), iter.value())?;
+        if meta.offset + 4 > self.block_meta_offset {
+            bail!("block offset out of bounds");
+        }
+        Ok(meta)
+    }
+
+    /// Read a partition and decode it with `decode`.
+    fn read_partition<T>(
+        &self,
+        partition: &Partition,
+        decode: impl FnOnce(&[u8]) -> Result<T>,
+    ) -> Result<Arc<T>> {
+        let data = self
+            .file
+            .read(partition.offset as u64, partition.len as u64)?;
+        Ok(Arc::new(decode(&data)?))
+    }
+
+    /// Read an index partition, which is a block with an entry per data block and a restart point
+    /// at every entry. Partitions are always added to the block cache, as they are needed by every
+    /// read of the blocks they cover.
+    fn read_index_partition(&self, partition_idx: usize) -> Result<Arc<Block>> {
+        let index = self.partitioned_index.as_ref().unwrap();
+        let read = || {
+            self.read_partition(&index.partitions[partition_idx], |data| {
+                let (block_data, mut checksum) = data.split_at(data.len() - 4);
+                if checksum.get_u32() != crc32fast::hash(block_data) {
+                    bail!("index partition checksum mismatched");
+                }
+                Block::try_decode_with_format(block_data, BlockFormat::Restart)
+            })
+        };
+        match self.block_cache {
+            Some(ref block_cache) => {
+                block_cache.try_get_index_partition_with((self.id, partition_idx), read)
+            }
+            None => read(),
         }
     }
 
+    /// Read a filter partition, which is a bloom filter cached decoded.
+    fn read_filter_partition(&self, partition_idx: usize) -> Result<Arc<Bloom>> {
+        let partitions = self.filter_partitions.as_ref().unwrap();
+        let read = || self.read_partition(&partitions[partition_idx], Bloom::decode);
+        match self.block_cache {
+            Some(ref block_cache) => {
+                block_cache.try_get_filter_partition_with((self.id, partition_idx), read)
+            }
+            None => read(),
+        }
+    }
+
+    /// Check if the SST may contain a user key with the bloom filter. Every filter partition
+    /// covering blocks that may hold versions of the key is checked.
+    pub(crate) fn may_contain(&self, key: &[u8]) -> Result<bool> {
+        let hash = farmhash::fingerprint32(key);
+        if let Some(bloom) = &self.bloom {
+            return Ok(bloom.may_contain(hash));
+        }
+        let Some(partitions) = &self.filter_partitions else {
+            return Ok(true);
+        };
+        // The versions of the key may span the partition before the first one starting with the
+        // key and all partitions starting with it.
+        let start = partitions
+            .partition_point(|partition| partition.first_key.key_ref() < key)
+            .saturating_sub(1);
+        let end = partitions.partition_point(|partition| partition.first_key.key_ref() <= key);
+        for partition_idx in start..end {
+            if self.read_filter_partition(partition_idx)?.may_contain(hash) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        let offset = self.block_meta[block_idx].offset;
-        let offset_end = self
-            .block_meta
-            .get(block_idx + 1)
-            .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
+        self.read_block_with_options(block_idx, &ReadOptions::default())
+    }
+
+    /// Read a block from the disk. When salvaging, a block that cannot be read is reported and
+    /// read as empty, which the iterators skip.
+    fn read_block_with_options(
+        &self,
+        block_idx: usize,
+        options: &ReadOptions,
+    ) -> Result<Arc<Block>> {
+        match (self.read_block_unchecked(block_idx, options), &self.salvage) {
+            (Err(_), Some(report)) => {
+                report.lock().add_corrupted_block(self.id, block_idx);
+                Ok(Arc::new(Block::empty()))
+            }
+            (block, _) => block,
+        }
+    }
+
+    fn read_block_unchecked(&self, block_idx: usize, options: &ReadOptions) -> Result<Arc<Block>> {
+        let meta = self.block_meta(block_idx)?;
+        let offset_end = self.block_end(block_idx)?;
+        if offset_end < meta.offset + 4 {
+            bail!("block offset out of bounds");
+        }
         let block_data_with_chksum: Vec<u8> = self
             .file
-            .read(offset as u64, (offset_end - offset) as u64)?;
+            .read(meta.offset as u64, (offset_end - meta.offset) as u64)?;
+        self.decode_block(meta.compression, &block_data_with_chksum, options)
+    }
+
+    /// Decode a block read from the disk, followed by its checksum.
+    fn decode_block(
+        &self,
+        compression: CompressionType,
+        block_data_with_chksum: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Arc<Block>> {
+        let block_len = block_data_with_chksum.len() - 4;
         let block_data = &block_data_with_chksum[..block_len];
         let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
+        if options.verify_checksums && checksum != crc32fast::hash(block_data) {
             bail!("block checksum mismatched");
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        let format = if self.format_version >= SST_FORMAT_RESTART {
+            BlockFormat::Restart
+        } else if self.format_version >= SST_FORMAT_VARINT {
+            BlockFormat::Varint
+        } else {
+            BlockFormat::Legacy
+        };
+        let block = if compression == CompressionType::None {
+            Block::try_decode_with_format(block_data, format)?
+        } else {
+            Block::try_decode_with_format(&compression.decompress(block_data)?, format)?
+        };
+        if block.hash_index.is_some() && self.format_version < SST_FORMAT_BLOCK_HASH_INDEX {
+            bail!(
+                "block has a hash index in an SST of format version {}",
+                self.format_version
+            );
+        }
+        Ok(Arc::new(block))
     }
 
-    /// Read a block from disk, with block cache.
-    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
-        if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
-            Ok(blk)
-        } else {
-            self.read_block(block_idx)
+    /// Read a block from disk, with block cache. Blocks not in the cache are only added to it if
+    /// `fill_cache` and `verify_checksums` are set, so that the cache only holds verified blocks.
+    pub fn read_block_cached(&self, block_idx: usize, options: &ReadOptions) -> Result<Arc<Block>> {
+        match self.block_cache {
+            Some(ref block_cache) if options.fill_cache && options.verify_checksums => block_cache
+                .try_get_with((self.id, block_idx), CachePriority::Low, || {
+                    self.read_block_with_options(block_idx, options)
+                }),
+            Some(ref block_cache) => match block_cache.get(&(self.id, block_idx)) {
+                Some(blk) => Ok(blk),
+                None => self.read_block_with_options(block_idx, options),
+            },
+            None => self.read_block_with_options(block_idx, options),
         }
     }
 
+    /// Read a block followed by the blocks within `readahead_size` bytes after it in one read from
+    /// the disk, for a scan moving forward. Only the block itself is returned if it is cached.
+    pub(crate) fn read_blocks_ahead(
+        &self,
+        block_idx: usize,
+        options: &ReadOptions,
+    ) -> Result<Vec<Arc<Block>>> {
+        let cached = self
+            .block_cache
+            .as_ref()
+            .is_some_and(|block_cache| block_cache.contains_key(&(self.id, block_idx)));
+        // When salvaging, blocks are read one at a time so that a damaged one is found by itself.
+        if options.readahead_size == 0 || cached || self.salvage.is_some() {
+            return Ok(vec![self.read_block_cached(block_idx, options)?]);
+        }
+        let mut block_meta = vec![self.block_meta(block_idx)?];
+        let offset = block_meta[0].offset;
+        let mut block_ends = vec![self.block_end(block_idx)?];
+        while block_idx + block_meta.len() < self.num_of_blocks() {
+            let idx = block_idx + block_meta.len();
+            let end = self.block_end(idx)?;
+            if end - offset > options.readahead_size {
+                break;
+            }
+            block_meta.push(self.block_meta(idx)?);
+            block_ends.push(end);
+        }
+        let data = self.file.read(
+            offset as u64,
+            (block_ends[block_ends.len() - 1] - offset) as u64,
+        )?;
+        let mut blocks = Vec::with_capacity(block_meta.len());
+        for (i, (meta, end)) in block_meta.iter().zip(block_ends).enumerate() {
+            let idx = block_idx + i;
+            let block = self.decode_block(
+                meta.compression,
+                &data[meta.offset - offset..end - offset],
+                options,
+            )?;
+            if options.fill_cache && options.verify_checksums {
+                if let Some(ref block_cache) = self.block_cache {
+                    block_cache.insert((self.id, idx), block.clone());
+                }
+            }
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
     /// Find the block that may contain `key`.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
-        self.block_meta
-            .partition_point(|meta| meta.first_key.as_key_slice() <= key)
-            .saturating_sub(1)
+    pub fn find_block_idx(&self, key: KeySlice) -> Result<usize> {
+        let Some(index) = &self.partitioned_index else {
+            return Ok(self
+                .block_meta
+                .partition_point(|meta| meta.first_key.as_key_slice() <= key)
+                .saturating_sub(1));
+        };
+        if index.partitions.is_empty() {
+            return Ok(0);
+        }
+        let partition_idx = index
+            .partitions
+            .partition_point(|partition| partition.first_key.as_key_slice() <= key)
+            .saturating_sub(1);
+        let block = self.read_index_partition(partition_idx)?;
+        let iter = BlockIterator::create_and_seek_for_prev(block, key);
+        // Keys before the first block of the SST map to the first block.
+        let idx = if iter.is_valid() {
+            iter.restart_idx()
+        } else {
+            0
+        };
+        Ok(index.partitions[partition_idx].first_block_idx + idx)
+    }
+
+    /// Keys splitting the key range of the SST, e.g., into subcompactions, which are known
+    /// without reading the SST: the first keys of the index partitions from the top-level index,
+    /// or the first keys of all data blocks.
+    pub(crate) fn split_keys(&self) -> Vec<&KeyBytes> {
+        match &self.partitioned_index {
+            Some(index) => index
+                .partitions
+                .iter()
+                .map(|partition| &partition.first_key)
+                .collect(),
+            None => self.block_meta.iter().map(|meta| &meta.first_key).collect(),
+        }
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_meta.len()
+        match &self.partitioned_index {
+            Some(index) => index.num_blocks,
+            None => self.block_meta.len(),
+        }
     }
 
     pub fn first_key(&self) -> &KeyBytes {
@@ -255,4 +1030,156 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    /// Whether the SST has any keys. An SST with only range tombstones has none.
+    pub(crate) fn has_keys(&self) -> bool {
+        !self.first_key.key_ref().is_empty()
+    }
+
+    /// The smallest start and largest end key of the range tombstones of the SST, if it has any.
+    pub(crate) fn tombstone_range(&self) -> Option<(Bytes, Bytes)> {
+        if let Some(lazy) = &self.lazy {
+            return lazy.tombstone_range.clone();
+        }
+        let start = self.range_tombstones.iter().map(|t| &t.start).min()?;
+        let end = self.range_tombstones.iter().map(|t| &t.end).max()?;
+        Some((start.clone(), end.clone()))
+    }
+
+    /// Read the range tombstones of the SST, opening it if it is a lightweight handle that has any.
+    pub(crate) fn read_range_tombstones(self: &Arc<Self>) -> Result<Vec<RangeTombstone>> {
+        if self.lazy.is_none() || self.tombstone_range().is_none() {
+            return Ok(self.range_tombstones.clone());
+        }
+        Ok(self.open_table()?.range_tombstones.clone())
+    }
+
+    /// The range tombstones of the SST split into fragments, which are cached with the SST.
+    pub(crate) fn fragmented_range_tombstones(
+        self: &Arc<Self>,
+    ) -> Result<Arc<FragmentedRangeTombstones>> {
+        if let Some(fragments) = self.fragmented_tombstones.get() {
+            return Ok(fragments.clone());
+        }
+        let fragments = Arc::new(FragmentedRangeTombstones::new(
+            self.read_range_tombstones()?,
+        ));
+        Ok(self.fragmented_tombstones.get_or_init(|| fragments).clone())
+    }
+
+    /// Metadata of the SST to record in the manifest.
+    pub fn meta(&self) -> SstMeta {
+        SstMeta {
+            id: self.id,
+            size: self.table_size(),
+            first_key: (self.first_key.key_ref().to_vec(), self.first_key.ts()),
+            last_key: (self.last_key.key_ref().to_vec(), self.last_key.ts()),
+            max_ts: self.max_ts,
+            value_log_refs: self.value_log_refs.clone(),
+            tombstone_range: self
+                .tombstone_range()
+                .map(|(start, end)| (start.to_vec(), end.to_vec())),
+        }
+    }
+
+    /// Create a lightweight handle of the SST at `path` described by `meta`, which only keeps the
+    /// metadata and opens the SST through the table cache on first access, see `open_table`.
+    pub(crate) fn open_lazy(
+        meta: &SstMeta,
+        path: PathBuf,
+        block_cache: Option<Arc<BlockCache>>,
+        table_cache: Arc<TableCache>,
+    ) -> Self {
+        let key_of = |(key, ts): &(Vec<u8>, u64)| {
+            KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key), *ts)
+        };
+        Self {
+            block_cache,
+            max_ts: meta.max_ts,
+            value_log_refs: meta.value_log_refs.clone(),
+            lazy: Some(LazyTable {
+                id: meta.id,
+                path,
+                table_cache,
+                tombstone_range: meta.tombstone_range.as_ref().map(|(start, end)| {
+                    (Bytes::copy_from_slice(start), Bytes::copy_from_slice(end))
+                }),
+            }),
+            ..Self::create_meta_only(
+                meta.id,
+                meta.size,
+                key_of(&meta.first_key),
+                key_of(&meta.last_key),
+            )
+        }
+    }
+
+    /// Get the open SST. A lightweight handle opens the SST through the table cache, with the
+    /// value logs of the handle attached, while other SSTs are already open.
+    pub(crate) fn open_table(self: &Arc<Self>) -> Result<Arc<SsTable>> {
+        let Some(lazy) = &self.lazy else {
+            return Ok(self.clone());
+        };
+        lazy.table_cache.get_or_open(self.id, || {
+            let file = FileObject::open(&lazy.path)?;
+            let mut sst = Self::open(self.id, self.block_cache.clone(), file)?;
+            sst.attach_value_logs(&self.value_logs)?;
+            Ok(Arc::new(sst))
+        })
+    }
+
+    /// Whether the values in this SST are tagged as inline values or value log pointers.
+    pub(crate) fn values_tagged(&self) -> bool {
+        self.values_tagged
+    }
+
+    /// Value log files referred to by this SST, and the number of bytes referred to in each.
+    pub fn value_log_refs(&self) -> &BTreeMap<usize, u64> {
+        &self.value_log_refs
+    }
+
+    /// Range tombstones stored in this SST. A lightweight handle has none before it is opened, see
+    /// `tombstone_range`.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Keep the value log files this SST refers to open, so that they stay readable as long as the
+    /// SST is alive.
+    pub(crate) fn attach_value_logs(
+        &mut self,
+        value_logs: &HashMap<usize, Arc<ValueLogFile>>,
+    ) -> Result<()> {
+        for file_id in self.value_log_refs.keys() {
+            if self.value_logs.contains_key(file_id) {
+                continue;
+            }
+            let Some(value_log) = value_logs.get(file_id) else {
+                // A value log that cannot be opened is reported, and reading from it fails.
+                if self.salvage.is_some() {
+                    continue;
+                }
+                bail!("value log {} of {}.sst not found", file_id, self.id);
+            };
+            self.value_logs.insert(*file_id, value_log.clone());
+        }
+        Ok(())
+    }
+
+    /// Open handles of the value log files this SST refers to.
+    pub(crate) fn value_logs(&self) -> &HashMap<usize, Arc<ValueLogFile>> {
+        &self.value_logs
+    }
+
+    /// Read a value stored in the value log.
+    pub(crate) fn read_value(&self, ptr: &ValuePointer) -> Result<Bytes> {
+        let Some(value_log) = self.value_logs.get(&ptr.file_id) else {
+            bail!("value log {} not available", ptr.file_id);
+        };
+        let value = value_log.read(ptr);
+        if let (Err(e), Some(report)) = (&value, &self.salvage) {
+            report.lock().add_corrupted_value_log(ptr.file_id, e);
+        }
+        value
+    }
 }
@@ -1,14 +1,28 @@
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 
 use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
+use super::{
+    encode_range_tombstones, encode_value_log_refs, BlockMeta, CompressionType, FileObject,
+    Partition, PartitionedIndex, SsTable, TopLevelIndex, INDEX_TYPE_FULL, INDEX_TYPE_PARTITIONED,
+    SST_FORMAT_LATEST, SST_MAGIC,
+};
 use crate::block::BlockBuilder;
 use crate::key::{KeySlice, KeyVec};
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::RangeTombstone;
+use crate::value_log::{ValueLogBuilder, ValuePointer, VALUE_TAG_INLINE, VALUE_TAG_POINTER};
+
+/// Moves large values of an SST into a value log file.
+struct ValueSeparation {
+    threshold: usize,
+    builder: ValueLogBuilder,
+    path: PathBuf,
+}
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -19,7 +33,21 @@ pub struct SsTableBuilder {
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
     key_hashes: Vec<u32>,
+    /// Number of key hashes at the end of each finished block.
+    block_hash_ends: Vec<usize>,
     max_ts: u64,
+    compression: CompressionType,
+    /// Whether data blocks have a hash index for point lookups.
+    block_hash_index: bool,
+    /// Whether the index and the bloom filter are split into partitions.
+    partitioned_index: bool,
+    value_separation: Option<ValueSeparation>,
+    /// Whether values are stored with a tag, which is required to store value log pointers.
+    values_tagged: bool,
+    value_log_refs: BTreeMap<usize, u64>,
+    /// Reusable buffer for encoding tagged values.
+    value_buf: Vec<u8>,
+    range_tombstones: Vec<RangeTombstone>,
 }
 
 impl SsTableBuilder {
@@ -33,12 +61,100 @@ impl SsTableBuilder {
             block_size,
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
+            block_hash_ends: Vec::new(),
             max_ts: 0,
+            compression: CompressionType::None,
+            block_hash_index: false,
+            partitioned_index: false,
+            value_separation: None,
+            values_tagged: false,
+            value_log_refs: BTreeMap::new(),
+            value_buf: Vec::new(),
+            range_tombstones: Vec::new(),
         }
     }
 
+    /// Compress each data block with the given codec.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Append a hash index of the user keys to each data block, see `BlockBuilder::with_hash_index`.
+    pub fn with_block_hash_index(mut self, block_hash_index: bool) -> Self {
+        self.block_hash_index = block_hash_index;
+        self.builder = BlockBuilder::new(self.block_size).with_hash_index(block_hash_index);
+        self
+    }
+
+    /// Split the index and the bloom filter into partitions stored like data blocks, each covering
+    /// the data blocks indexed in a block of `block_size`. Only a top-level index of the partitions
+    /// is kept in memory, and the partitions are read through the block cache.
+    pub fn with_partitioned_index(mut self, partitioned_index: bool) -> Self {
+        self.partitioned_index = partitioned_index;
+        self
+    }
+
+    /// Write values no smaller than `threshold` to a value log file at `path`, and only store
+    /// pointers to them in the SST.
+    pub fn with_value_separation(
+        mut self,
+        threshold: usize,
+        value_log_id: usize,
+        path: impl AsRef<Path>,
+    ) -> Self {
+        self.value_separation = Some(ValueSeparation {
+            threshold,
+            builder: ValueLogBuilder::new(value_log_id),
+            path: path.as_ref().to_path_buf(),
+        });
+        self.values_tagged = true;
+        self
+    }
+
+    /// Store values with a tag, so that values copied from SSTs with value log pointers can be
+    /// added with `add_raw`.
+    pub(crate) fn with_tagged_values(mut self) -> Self {
+        self.values_tagged = true;
+        self
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        if !self.values_tagged {
+            self.add_inner(key, value);
+            return;
+        }
+        let mut raw_value = std::mem::take(&mut self.value_buf);
+        raw_value.clear();
+        // An empty value is a delete tombstone and is stored without a tag.
+        if !value.is_empty() {
+            match &mut self.value_separation {
+                Some(separation) if value.len() >= separation.threshold => {
+                    separation.builder.add(key, value).encode(&mut raw_value);
+                }
+                _ => {
+                    raw_value.put_u8(VALUE_TAG_INLINE);
+                    raw_value.put_slice(value);
+                }
+            }
+        }
+        self.add_raw(key, &raw_value);
+        self.value_buf = raw_value;
+    }
+
+    /// Adds a key with a value that is already tagged, e.g., a value copied by compaction. Only
+    /// available if values are tagged.
+    pub(crate) fn add_raw(&mut self, key: KeySlice, value: &[u8]) {
+        assert!(self.values_tagged, "values of this SST are not tagged");
+        if value.first() == Some(&VALUE_TAG_POINTER) {
+            let ptr = ValuePointer::decode(value).expect("invalid value pointer");
+            *self.value_log_refs.entry(ptr.file_id).or_default() += ptr.len as u64;
+        }
+        self.add_inner(key, value);
+    }
+
+    fn add_inner(&mut self, key: KeySlice, value: &[u8]) {
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -46,20 +162,30 @@ impl SsTableBuilder {
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
-        self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
 
-        if self.builder.add(key, value) {
-            self.last_key.set_from_slice(key);
-            return;
+        if !self.builder.add(key, value) {
+            // create a new block builder and append block data
+            self.finish_block();
+
+            // add the key-value pair to the next block
+            assert!(self.builder.add(key, value));
+            self.first_key.set_from_slice(key);
         }
+        self.last_key.set_from_slice(key);
+        // The hash is added after the block is finished, so that it counts towards the block of
+        // the key.
+        self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+    }
 
-        // create a new block builder and append block data
-        self.finish_block();
+    /// Adds a range tombstone to the SSTable.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_ts = self.max_ts.max(tombstone.ts);
+        self.range_tombstones.push(tombstone);
+    }
 
-        // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
-        self.first_key.set_from_slice(key);
-        self.last_key.set_from_slice(key);
+    /// Whether no key-value pair or range tombstone has been added.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
     }
 
     /// Get the estimated size of the SSTable.
@@ -68,16 +194,101 @@ impl SsTableBuilder {
     }
 
     fn finish_block(&mut self) {
-        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
+        // An SST with only range tombstones has no data blocks.
+        if self.builder.is_empty() {
+            return;
+        }
+        let builder = std::mem::replace(
+            &mut self.builder,
+            BlockBuilder::new(self.block_size).with_hash_index(self.block_hash_index),
+        );
         let encoded_block = builder.build().encode();
+        // Fall back to storing the block as-is if the codec does not make it smaller.
+        let compressed = match self.compression {
+            CompressionType::None => None,
+            codec => codec
+                .compress(&encoded_block)
+                .ok()
+                .filter(|compressed| compressed.len() < encoded_block.len()),
+        };
+        let (compression, stored_block) = match compressed {
+            Some(compressed) => (self.compression, compressed.into()),
+            None => (CompressionType::None, encoded_block),
+        };
         self.meta.push(BlockMeta {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
+            compression,
         });
-        let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
+        let checksum = crc32fast::hash(&stored_block);
+        self.data.extend(stored_block);
         self.data.put_u32(checksum);
+        self.block_hash_ends.push(self.key_hashes.len());
+    }
+
+    /// Write the index and filter partitions after the data blocks, followed by the top-level
+    /// index. A partition is cut when the index entries no longer fit in a block.
+    fn build_partitions(&self, buf: &mut Vec<u8>) -> TopLevelIndex {
+        let mut index_partitions = Vec::new();
+        let mut filter_partitions = Vec::new();
+        let new_builder = || BlockBuilder::new(self.block_size).with_restart_interval(1);
+        let mut builder = new_builder();
+        let mut first_block_idx = 0;
+        let mut value = Vec::new();
+        let mut finish_partition = |builder: BlockBuilder, first_block_idx: usize, end: usize| {
+            let first_key = self.meta[first_block_idx].first_key.clone();
+            let index_offset = buf.len();
+            let block = builder.build().encode();
+            buf.extend(&block);
+            buf.put_u32(crc32fast::hash(&block));
+            index_partitions.push(Partition {
+                first_block_idx,
+                first_key: first_key.clone(),
+                offset: index_offset,
+                len: buf.len() - index_offset,
+            });
+            let hash_start = first_block_idx
+                .checked_sub(1)
+                .map_or(0, |idx| self.block_hash_ends[idx]);
+            let key_hashes = &self.key_hashes[hash_start..self.block_hash_ends[end - 1]];
+            let bloom = Bloom::build_from_key_hashes(
+                key_hashes,
+                Bloom::bloom_bits_per_key(key_hashes.len(), 0.01),
+            );
+            let filter_offset = buf.len();
+            bloom.encode(buf);
+            filter_partitions.push(Partition {
+                first_block_idx,
+                first_key,
+                offset: filter_offset,
+                len: buf.len() - filter_offset,
+            });
+        };
+        for (idx, meta) in self.meta.iter().enumerate() {
+            value.clear();
+            meta.encode_index_value(&mut value);
+            if !builder.add(meta.first_key.as_key_slice(), &value) {
+                let full = std::mem::replace(&mut builder, new_builder());
+                finish_partition(full, first_block_idx, idx);
+                first_block_idx = idx;
+                assert!(builder.add(meta.first_key.as_key_slice(), &value));
+            }
+        }
+        if !builder.is_empty() {
+            finish_partition(builder, first_block_idx, self.meta.len());
+        }
+        TopLevelIndex {
+            index_partitions,
+            filter_partitions,
+            num_blocks: self.meta.len(),
+            last_key: self
+                .meta
+                .last()
+                .map(|meta| meta.last_key.clone())
+                .unwrap_or_default(),
+            max_ts: self.max_ts,
+        }
     }
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
@@ -88,28 +299,93 @@ impl SsTableBuilder {
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
         self.finish_block();
-        let mut buf = self.data;
+        let mut buf = std::mem::take(&mut self.data);
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
-        buf.put_u32(meta_offset as u32);
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
-        let bloom_offset = buf.len();
-        bloom.encode(&mut buf);
-        buf.put_u32(bloom_offset as u32);
+        let mut partitions = None;
+        let mut bloom = None;
+        if self.partitioned_index {
+            let index = self.build_partitions(&mut buf);
+            let index_offset = buf.len();
+            index.encode(&mut buf);
+            buf.put_u32(index_offset as u32);
+            buf.put_u8(INDEX_TYPE_PARTITIONED);
+            partitions = Some(index);
+        } else {
+            BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+            buf.put_u32(meta_offset as u32);
+            let full_bloom = Bloom::build_from_key_hashes(
+                &self.key_hashes,
+                Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
+            );
+            let bloom_offset = buf.len();
+            full_bloom.encode(&mut buf);
+            buf.put_u32(bloom_offset as u32);
+            buf.put_u8(INDEX_TYPE_FULL);
+            bloom = Some(full_bloom);
+        }
+        let refs_offset = buf.len();
+        encode_value_log_refs(self.values_tagged, &self.value_log_refs, &mut buf);
+        buf.put_u32(refs_offset as u32);
+        let tombstones_offset = buf.len();
+        encode_range_tombstones(&self.range_tombstones, &mut buf);
+        buf.put_u32(tombstones_offset as u32);
+        buf.put_u32(SST_FORMAT_LATEST);
+        buf.put_u64(SST_MAGIC);
+        // The value log must be persisted before the SST pointing to it.
+        let mut value_logs = HashMap::new();
+        if let Some(separation) = self.value_separation {
+            if !separation.builder.is_empty() {
+                let value_log = separation.builder.build(&separation.path)?;
+                value_logs.insert(value_log.id(), Arc::new(value_log));
+            }
+        }
         let file = FileObject::create(path.as_ref(), buf)?;
+        let (partitioned_index, filter_partitions) = match partitions {
+            Some(index) => (
+                Some(PartitionedIndex {
+                    partitions: index.index_partitions,
+                    num_blocks: index.num_blocks,
+                }),
+                Some(index.filter_partitions),
+            ),
+            None => (None, None),
+        };
+        let first_key = self
+            .meta
+            .first()
+            .map(|meta| meta.first_key.clone())
+            .unwrap_or_default();
+        let last_key = self
+            .meta
+            .last()
+            .map(|meta| meta.last_key.clone())
+            .unwrap_or_default();
+        // The meta of the blocks is only kept in memory if the index is not partitioned.
+        let block_meta = if partitioned_index.is_some() {
+            Vec::new()
+        } else {
+            self.meta
+        };
         Ok(SsTable {
             id,
             file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
-            block_meta: self.meta,
+            first_key,
+            last_key,
+            block_meta,
             block_meta_offset: meta_offset,
+            partitioned_index,
             block_cache,
-            bloom: Some(bloom),
+            bloom,
+            filter_partitions,
             max_ts: self.max_ts,
+            values_tagged: self.values_tagged,
+            value_log_refs: self.value_log_refs,
+            value_logs,
+            range_tombstones: self.range_tombstones,
+            salvage: None,
+            format_version: SST_FORMAT_LATEST,
+            lazy: None,
+            fragmented_tombstones: OnceLock::new(),
         })
     }
 
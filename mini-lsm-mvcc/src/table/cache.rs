@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use moka::sync::{Cache, ConcurrentCacheExt};
+
+use super::SsTable;
+
+/// A bounded cache of open SSTs, keyed by SST id. The LSM state only holds lightweight handles of
+/// the SSTs, which open the SST through the cache on first access. An SST holds its file open
+/// until it is evicted from the cache, so that the number of open files does not grow with the
+/// number of SSTs.
+pub struct TableCache {
+    tables: Cache<usize, Arc<SsTable>>,
+}
+
+impl TableCache {
+    /// Create a table cache that keeps at most `max_open_files` SSTs open.
+    pub fn new(max_open_files: usize) -> Self {
+        Self {
+            tables: Cache::new(max_open_files as u64),
+        }
+    }
+
+    /// Get the open SST `id`, opening it with `open` if it is not in the cache.
+    pub(crate) fn get_or_open(
+        &self,
+        id: usize,
+        open: impl FnOnce() -> Result<Arc<SsTable>>,
+    ) -> Result<Arc<SsTable>> {
+        self.tables
+            .try_get_with(id, open)
+            .map_err(|e| anyhow!("failed to open {}.sst: {:#}", id, e))
+    }
+
+    /// Add an SST that was just written, so that it is not read again on its first access.
+    pub(crate) fn insert(&self, sst: Arc<SsTable>) {
+        self.tables.insert(sst.sst_id(), sst);
+    }
+
+    /// Close an SST, e.g., when it is removed. Reads in progress keep it open until they finish.
+    pub(crate) fn close(&self, id: usize) {
+        self.tables.invalidate(&id);
+    }
+
+    /// Number of files held open by the cache.
+    pub fn num_open_files(&self) -> usize {
+        self.tables.sync();
+        self.tables.entry_count() as usize
+    }
+}
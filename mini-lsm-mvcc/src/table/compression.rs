@@ -0,0 +1,52 @@
+use anyhow::{bail, Result};
+
+/// The codec used to compress a data block in an SST.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+impl CompressionType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Zstd => 2,
+            CompressionType::Snappy => 3,
+        }
+    }
+
+    pub fn from_u8(x: u8) -> Result<Self> {
+        Ok(match x {
+            0 => CompressionType::None,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Zstd,
+            3 => CompressionType::Snappy,
+            _ => bail!("unknown compression type {}", x),
+        })
+    }
+
+    /// Compress a block with this codec.
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::compress_prepend_size(data),
+            CompressionType::Zstd => zstd::stream::encode_all(data, 0)?,
+            CompressionType::Snappy => snap::raw::Encoder::new().compress_vec(data)?,
+        })
+    }
+
+    /// Decompress a block that was compressed with this codec.
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::decompress_size_prepended(data)?,
+            CompressionType::Zstd => zstd::stream::decode_all(data)?,
+            CompressionType::Snappy => snap::raw::Decoder::new().decompress_vec(data)?,
+        })
+    }
+}
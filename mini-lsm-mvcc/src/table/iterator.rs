@@ -1,76 +1,357 @@
+use std::cell::OnceCell;
+use std::collections::VecDeque;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use bytes::{BufMut, Bytes};
 
 use super::SsTable;
-use crate::block::BlockIterator;
+use crate::block::{Block, BlockIterator};
 use crate::iterators::StorageIterator;
 use crate::key::KeySlice;
+use crate::options::ReadOptions;
+use crate::value_log::{ValuePointer, VALUE_TAG_INLINE, VALUE_TAG_POINTER};
 
-/// An iterator over the contents of an SSTable.
+/// An iterator over the contents of an SSTable. A lightweight SST handle is opened through the
+/// table cache when the iterator is created.
 pub struct SsTableIterator {
+    /// The SST the iterator was created with. A lightweight handle is kept along with the open
+    /// SST, as the deletion of removed SSTs waits until no one else holds the handle.
+    _handle: Arc<SsTable>,
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// Whether to yield values in the tagged on-disk format instead of the user values.
+    tagged: bool,
+    /// The current value if it cannot be borrowed from the block.
+    value: Option<Bytes>,
+    /// The pointer of the current value if it is stored in a value log.
+    pointer: Option<ValuePointer>,
+    /// The value read from the value log on the first call to `value`, so that versions which
+    /// are skipped by the readers are never read.
+    resolved: OnceCell<Result<Bytes>>,
+    /// Length of the tag to skip in the value borrowed from the block.
+    value_offset: usize,
+    options: ReadOptions,
+    /// Blocks after the current one that were read ahead.
+    readahead: VecDeque<Arc<Block>>,
 }
 
 impl SsTableIterator {
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
-        Ok((
-            0,
-            BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
-        ))
+    fn new(
+        handle: Arc<SsTable>,
+        table: Arc<SsTable>,
+        blk_idx: usize,
+        blk_iter: BlockIterator,
+        tagged: bool,
+        options: ReadOptions,
+    ) -> Self {
+        Self {
+            _handle: handle,
+            table,
+            blk_iter,
+            blk_idx,
+            tagged,
+            value: None,
+            pointer: None,
+            resolved: OnceCell::new(),
+            value_offset: 0,
+            options,
+            readahead: VecDeque::new(),
+        }
+    }
+
+    fn seek_to_first_inner(
+        table: &Arc<SsTable>,
+        options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, BlockIterator::empty()));
+        }
+        let blk_iter =
+            BlockIterator::create_and_seek_to_first(table.read_block_cached(0, options)?);
+        Self::skip_empty_blocks(table, 0, blk_iter, options)
+    }
+
+    /// Move forward from an invalid block iterator to the first entry of the next block with any.
+    /// Only a seek past the last entry of a block or a corrupted block read as empty when
+    /// salvaging leave the iterator invalid.
+    fn skip_empty_blocks(
+        table: &Arc<SsTable>,
+        mut blk_idx: usize,
+        mut blk_iter: BlockIterator,
+        options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        while !blk_iter.is_valid() && blk_idx + 1 < table.num_of_blocks() {
+            blk_idx += 1;
+            blk_iter =
+                BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx, options)?);
+        }
+        Ok((blk_idx, blk_iter))
+    }
+
+    /// Like `skip_empty_blocks`, but moves backward to the last entry of the previous block.
+    fn skip_empty_blocks_back(
+        table: &Arc<SsTable>,
+        mut blk_idx: usize,
+        mut blk_iter: BlockIterator,
+        options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        while !blk_iter.is_valid() && blk_idx > 0 {
+            blk_idx -= 1;
+            blk_iter =
+                BlockIterator::create_and_seek_to_last(table.read_block_cached(blk_idx, options)?);
+        }
+        Ok((blk_idx, blk_iter))
     }
 
     /// Create a new iterator and seek to the first key-value pair.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
+        Self::create_and_seek_to_first_with_options(table, ReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks as set in `options`, and seek to the first key-value
+    /// pair.
+    pub(crate) fn create_and_seek_to_first_with_options(
+        table: Arc<SsTable>,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&opened, &options)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, false, options);
+        iter.load_value()?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator for compaction and seek to the first key-value pair. If `tagged`,
+    /// values are yielded in the tagged format, so that values in value logs are copied without
+    /// reading them.
+    pub(crate) fn create_and_seek_to_first_for_compaction(
+        table: Arc<SsTable>,
+        tagged: bool,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let options = ReadOptions::default();
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&opened, &options)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, tagged, options);
+        iter.load_value()?;
         Ok(iter)
     }
 
     /// Seek to the first key-value pair.
     pub fn seek_to_first(&mut self) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table, &self.options)?;
+        self.readahead.clear();
         self.blk_idx = blk_idx;
         self.blk_iter = blk_iter;
-        Ok(())
+        self.load_value()
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
-        let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
-        if !blk_iter.is_valid() {
-            blk_idx += 1;
-            if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
-            }
+    /// Seek to the first key-value pair which >= `key`. If `for_get`, the hash index of the data
+    /// block is used, and the iterator is invalid if it tells that the SST has no entry of the
+    /// user key of `key`.
+    fn seek_to_key_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        options: &ReadOptions,
+        for_get: bool,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, BlockIterator::empty()));
         }
-        Ok((blk_idx, blk_iter))
+        let blk_idx = table.find_block_idx(key)?;
+        let block = table.read_block_cached(blk_idx, options)?;
+        let blk_iter = if for_get {
+            match BlockIterator::create_and_seek_to_key_for_get(block, key) {
+                Some(blk_iter) => blk_iter,
+                // The entries of the user key may start in the next block.
+                None if blk_idx + 1 < table.num_of_blocks()
+                    && table.block_meta(blk_idx + 1)?.first_key.key_ref() == key.key_ref() =>
+                {
+                    BlockIterator::empty()
+                }
+                None => return Ok((table.num_of_blocks(), BlockIterator::empty())),
+            }
+        } else {
+            BlockIterator::create_and_seek_to_key(block, key)
+        };
+        Self::skip_empty_blocks(table, blk_idx, blk_iter, options)
     }
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
+        Self::create_and_seek_to_key_with_options(table, key, ReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks as set in `options`, and seek to the first key-value
+    /// pair which >= `key`.
+    pub(crate) fn create_and_seek_to_key_with_options(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&opened, key, &options, false)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, false, options);
+        iter.load_value()?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator for a point lookup of the user key of `key`, and seek to the first
+    /// key-value pair which >= `key`. The iterator is invalid if the hash index of the data block
+    /// tells that the SST has no entry of the user key, so later keys are not always yielded.
+    pub(crate) fn create_and_seek_to_key_for_get(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&opened, key, &options, true)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, false, options);
+        iter.load_value()?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator for compaction and seek to the first key-value pair which >= `key`,
+    /// see `create_and_seek_to_first_for_compaction`.
+    pub(crate) fn create_and_seek_to_key_for_compaction(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        tagged: bool,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let options = ReadOptions::default();
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&opened, key, &options, false)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, tagged, options);
+        iter.load_value()?;
         Ok(iter)
     }
 
     /// Seek to the first key-value pair which >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key, &self.options, false)?;
+        self.readahead.clear();
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
+        self.load_value()
+    }
+
+    fn seek_to_last_inner(
+        table: &Arc<SsTable>,
+        options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, BlockIterator::empty()));
+        }
+        let blk_idx = table.num_of_blocks() - 1;
+        let blk_iter =
+            BlockIterator::create_and_seek_to_last(table.read_block_cached(blk_idx, options)?);
+        Self::skip_empty_blocks_back(table, blk_idx, blk_iter, options)
+    }
+
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        Self::create_and_seek_to_last_with_options(table, ReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks as set in `options`, and seek to the last key-value
+    /// pair.
+    pub(crate) fn create_and_seek_to_last_with_options(
+        table: Arc<SsTable>,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&opened, &options)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, false, options);
+        iter.load_value()?;
+        Ok(iter)
+    }
+
+    /// Seek to the last key-value pair.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&self.table, &self.options)?;
+        self.readahead.clear();
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        self.load_value()
+    }
+
+    fn seek_for_prev_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        options: &ReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, BlockIterator::empty()));
+        }
+        let blk_idx = table.find_block_idx(key)?;
+        let blk_iter = BlockIterator::create_and_seek_for_prev(
+            table.read_block_cached(blk_idx, options)?,
+            key,
+        );
+        Self::skip_empty_blocks_back(table, blk_idx, blk_iter, options)
+    }
+
+    /// Create a new iterator and seek to the last key-value pair which <= `key`.
+    pub fn create_and_seek_for_prev(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_for_prev_with_options(table, key, ReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks as set in `options`, and seek to the last key-value
+    /// pair which <= `key`.
+    pub(crate) fn create_and_seek_for_prev_with_options(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        options: ReadOptions,
+    ) -> Result<Self> {
+        let opened = table.open_table()?;
+        let (blk_idx, blk_iter) = Self::seek_for_prev_inner(&opened, key, &options)?;
+        let mut iter = Self::new(table, opened, blk_idx, blk_iter, false, options);
+        iter.load_value()?;
+        Ok(iter)
+    }
+
+    /// Seek to the last key-value pair which <= `key`.
+    pub fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_for_prev_inner(&self.table, key, &self.options)?;
+        self.readahead.clear();
+        self.blk_iter = blk_iter;
+        self.blk_idx = blk_idx;
+        self.load_value()
+    }
+
+    /// Decode the value at the current position according to the format of the SST. Values in
+    /// value logs are read by `value`.
+    fn load_value(&mut self) -> Result<()> {
+        self.value = None;
+        self.pointer = None;
+        self.resolved = OnceCell::new();
+        self.value_offset = 0;
+        if !self.blk_iter.is_valid() {
+            return Ok(());
+        }
+        let value = self.blk_iter.value();
+        // Delete tombstones are stored as empty values in all formats.
+        if value.is_empty() {
+            return Ok(());
+        }
+        if !self.table.values_tagged() {
+            if self.tagged {
+                let mut tagged = Vec::with_capacity(value.len() + 1);
+                tagged.put_u8(VALUE_TAG_INLINE);
+                tagged.put_slice(value);
+                self.value = Some(tagged.into());
+            }
+            return Ok(());
+        }
+        if self.tagged {
+            return Ok(());
+        }
+        match value[0] {
+            VALUE_TAG_INLINE => self.value_offset = 1,
+            VALUE_TAG_POINTER => self.pointer = Some(ValuePointer::decode(value)?),
+            tag => bail!("unknown value tag {}", tag),
+        }
         Ok(())
     }
 }
@@ -78,11 +359,29 @@ impl SsTableIterator {
 impl StorageIterator for SsTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
+    /// A value that fails to be read from the value log is empty, and the error is returned by
+    /// `status`, which callers must check before treating the empty value as a delete.
     fn value(&self) -> &[u8] {
-        self.blk_iter.value()
+        if let Some(ptr) = &self.pointer {
+            return match self.resolved.get_or_init(|| self.table.read_value(ptr)) {
+                Ok(value) => value,
+                Err(_) => &[],
+            };
+        }
+        match &self.value {
+            Some(value) => value,
+            None => &self.blk_iter.value()[self.value_offset..],
+        }
+    }
+
+    fn status(&self) -> Result<()> {
+        match self.resolved.get() {
+            Some(Err(e)) => Err(anyhow!("failed to read value: {:#}", e)),
+            _ => Ok(()),
+        }
     }
 
-    fn key(&self) -> KeySlice {
+    fn key(&self) -> KeySlice<'_> {
         self.blk_iter.key()
     }
 
@@ -92,14 +391,42 @@ impl StorageIterator for SsTableIterator {
 
     fn next(&mut self) -> Result<()> {
         self.blk_iter.next();
-        if !self.blk_iter.is_valid() {
+        // Past the last block, the block iterator stays after its last entry so that `prev`
+        // moves back to it.
+        while !self.blk_iter.is_valid() && self.blk_idx + 1 < self.table.num_of_blocks() {
             self.blk_idx += 1;
-            if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
-            }
+            let block = match self.readahead.pop_front() {
+                Some(block) => block,
+                None => {
+                    self.readahead = self
+                        .table
+                        .read_blocks_ahead(self.blk_idx, &self.options)?
+                        .into();
+                    self.readahead.pop_front().unwrap()
+                }
+            };
+            self.blk_iter = BlockIterator::create_and_seek_to_first(block);
         }
-        Ok(())
+        self.load_value()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.blk_iter.prev();
+        while !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.readahead.clear();
+            self.blk_iter = BlockIterator::create_and_seek_to_last(
+                self.table.read_block_cached(self.blk_idx, &self.options)?,
+            );
+        }
+        self.load_value()
+    }
+
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        SsTableIterator::seek_to_key(self, key)
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        SsTableIterator::seek_for_prev(self, key)
     }
 }
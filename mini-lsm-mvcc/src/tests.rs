@@ -1,3 +1,4 @@
+mod common;
 mod harness;
 mod week1_day1;
 mod week1_day2;
@@ -19,3 +20,29 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+
+mod background_error;
+mod block_cache;
+mod block_compression;
+mod block_hash_index;
+mod block_restart;
+mod checkpoint;
+mod column_family;
+mod group_commit;
+mod large_entries;
+mod manifest_rotation;
+mod obsolete_files;
+mod options;
+mod partitioned_index;
+mod range_tombstone;
+mod repair;
+mod reverse_iteration;
+mod salvage;
+mod snapshot;
+mod subcompaction;
+mod table_cache;
+mod time_travel;
+mod trivial_move;
+mod value_log;
+mod wal_recovery;
+mod write_stall;
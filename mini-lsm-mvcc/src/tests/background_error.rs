@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+use super::common::{key_of, value_of, NUM_KEYS};
+
+fn options(enable_wal: bool) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = enable_wal;
+    options
+}
+
+/// Fill enough memtables to trigger a flush, and move the database directory away before the
+/// flush thread gets to it so that the flush fails.
+fn fail_next_flush(storage: &MiniLsm, path: &Path, moved_path: &Path) {
+    let state_lock = storage.inner.state_lock.lock();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % 50 == 49 {
+            storage.inner.force_freeze_memtable(&state_lock).unwrap();
+        }
+    }
+    std::fs::rename(path, moved_path).unwrap();
+    drop(state_lock);
+    for _ in 0..100 {
+        if storage.background_error().is_some() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("flush did not fail");
+}
+
+fn check_keys(storage: &Arc<MiniLsm>) {
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+}
+
+#[test]
+fn test_background_error_and_resume() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    let moved_path = dir.path().join("moved");
+    let storage = MiniLsm::open(&path, options(false)).unwrap();
+    fail_next_flush(&storage, &path, &moved_path);
+    assert!(storage
+        .background_error()
+        .unwrap()
+        .starts_with("flush failed"));
+
+    // Writes fail, but reads are served from the memtables.
+    assert!(storage.put(b"key", b"value").is_err());
+    assert!(storage
+        .write_batch(&[WriteBatchRecord::Put(&b"key"[..], &b"value"[..])])
+        .is_err());
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"key", b"value");
+    assert!(txn.commit().is_err());
+    assert!(storage.force_flush().is_err());
+    check_keys(&storage);
+
+    // Resuming fails until the cause is fixed.
+    assert!(storage.resume().is_err());
+    assert!(storage.background_error().is_some());
+    std::fs::rename(&moved_path, &path).unwrap();
+    storage.resume().unwrap();
+    assert!(storage.background_error().is_none());
+    assert!(
+        storage.inner.state.read().imm_memtables.len() < storage.inner.options.num_memtable_limit
+    );
+    storage.put(b"key", b"value").unwrap();
+    assert_eq!(
+        storage.get(b"key").unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    check_keys(&storage);
+}
+
+#[test]
+fn test_background_error_keeps_committed_writes() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    let moved_path = dir.path().join("moved");
+    let storage = MiniLsm::open(&path, options(true)).unwrap();
+    fail_next_flush(&storage, &path, &moved_path);
+    assert!(storage.put(b"key", b"value").is_err());
+
+    // Writes accepted before the error are recovered from the WAL without resuming.
+    std::fs::rename(&moved_path, &path).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&path, options(true)).unwrap();
+    assert!(storage.background_error().is_none());
+    check_keys(&storage);
+    assert_eq!(storage.get(b"key").unwrap(), None);
+}
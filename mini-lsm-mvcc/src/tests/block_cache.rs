@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockFormat},
+    block_cache::{BlockCache, BlockCacheStats, CachePriority, ShardedBlockCache},
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::key_of;
+
+fn block_of(size: usize) -> Arc<Block> {
+    Arc::new(Block {
+        data: vec![0; size],
+        offsets: Vec::new(),
+        format: BlockFormat::Restart,
+        hash_index: None,
+    })
+}
+
+#[test]
+fn test_block_cache_lru_and_priorities() {
+    let charge = block_of(1000).charge();
+    // A single shard holding 4 blocks, half of which may be high-priority when full.
+    let cache = Arc::new(ShardedBlockCache::with_config(4 * charge, 0, 0.5));
+    let block_cache = BlockCache::shared(cache.clone());
+    let other = BlockCache::shared(cache.clone());
+    for idx in 0..4 {
+        block_cache.insert((1, idx), block_of(1000));
+    }
+    assert!(block_cache.get(&(1, 0)).is_some());
+    // The least recently used block is evicted.
+    block_cache.insert((1, 4), block_of(1000));
+    assert!(!block_cache.contains_key(&(1, 1)));
+    for idx in [0, 2, 3, 4] {
+        assert!(block_cache.contains_key(&(1, idx)));
+    }
+    // Storages sharing the cache do not see each other's blocks.
+    assert!(!other.contains_key(&(1, 0)));
+    assert!(other.get(&(1, 0)).is_none());
+
+    // High-priority blocks evict low-priority ones until they take their share of the capacity.
+    for idx in 0..3 {
+        block_cache.insert_with_priority((2, idx), block_of(1000), CachePriority::High);
+    }
+    for idx in [0, 2, 3] {
+        assert!(!block_cache.contains_key(&(1, idx)));
+    }
+    assert!(block_cache.contains_key(&(1, 4)));
+    // Beyond that share, the least recently used high-priority block goes first.
+    block_cache.insert((1, 5), block_of(1000));
+    assert!(!block_cache.contains_key(&(2, 0)));
+    for key in [(2, 1), (2, 2), (1, 4), (1, 5)] {
+        assert!(block_cache.contains_key(&key));
+    }
+    // A block larger than the capacity is not cached.
+    block_cache.insert((1, 6), block_of(5 * charge));
+    assert!(!block_cache.contains_key(&(1, 6)));
+
+    assert_eq!(
+        block_cache.stats(),
+        BlockCacheStats {
+            hits: 1,
+            misses: 1,
+            evictions: 5,
+            usage: 4 * charge,
+            capacity: 4 * charge,
+        }
+    );
+}
+
+#[test]
+fn test_block_cache_loads_missing_block_once() {
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let num_loads = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(8));
+    let threads = (0..8)
+        .map(|_| {
+            let (block_cache, num_loads, barrier) =
+                (block_cache.clone(), num_loads.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                barrier.wait();
+                block_cache
+                    .try_get_with((1, 0), CachePriority::Low, || {
+                        num_loads.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        Ok(block_of(1000))
+                    })
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap().data.len(), 1000);
+    }
+    assert_eq!(num_loads.load(Ordering::SeqCst), 1);
+
+    // A failed load is retried by the next reader.
+    assert!(block_cache
+        .try_get_with((1, 1), CachePriority::Low, || anyhow::bail!("read failed"))
+        .is_err());
+    assert!(block_cache
+        .try_get_with((1, 1), CachePriority::Low, || Ok(block_of(1000)))
+        .is_ok());
+    assert!(block_cache.contains_key(&(1, 1)));
+}
+
+fn value_of(storage: usize, idx: usize) -> Bytes {
+    Bytes::from(format!("value_{}_{:05}", storage, idx))
+}
+
+#[test]
+fn test_shared_block_cache() {
+    const NUM_KEYS: usize = 1000;
+    let cache = Arc::new(ShardedBlockCache::with_config(1 << 20, 2, 0.5));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_cache = Some(cache.clone());
+    options.partitioned_index = true;
+    let dirs = [tempdir().unwrap(), tempdir().unwrap()];
+    // Both storages have SSTs with the same ids.
+    let storages = dirs
+        .iter()
+        .enumerate()
+        .map(|(storage_idx, dir)| {
+            let storage = MiniLsm::open(dir, options.clone()).unwrap();
+            for idx in 0..NUM_KEYS {
+                storage
+                    .put(&key_of(idx), &value_of(storage_idx, idx))
+                    .unwrap();
+            }
+            storage.force_flush().unwrap();
+            storage
+        })
+        .collect::<Vec<_>>();
+    for _ in 0..2 {
+        for (storage_idx, storage) in storages.iter().enumerate() {
+            for idx in 0..NUM_KEYS {
+                assert_eq!(
+                    storage.get(&key_of(idx)).unwrap(),
+                    Some(value_of(storage_idx, idx))
+                );
+            }
+        }
+    }
+    let stats = cache.stats();
+    assert!(stats.hits > stats.misses, "{stats:?}");
+    assert!(
+        stats.usage > 0 && stats.usage <= stats.capacity,
+        "{stats:?}"
+    );
+    assert_eq!(storages[0].inner.block_cache.stats(), stats);
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockFormat},
+    compact::CompactionOptions,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{bloom::Bloom, CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::common::key_of;
+use super::harness::check_iter_result_by_key;
+
+fn value_of(idx: usize) -> Bytes {
+    Bytes::from(format!("value_{:05}_{}", idx, "x".repeat(100)))
+}
+
+const NUM_KEYS: usize = 1000;
+
+fn build_sst(compression: CompressionType, path: &std::path::Path) -> SsTable {
+    let mut builder = SsTableBuilder::new(4096).with_compression(compression);
+    for idx in 0..NUM_KEYS {
+        let key = key_of(idx);
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key, 1),
+            &value_of(idx),
+        );
+    }
+    builder.build(0, None, path).unwrap()
+}
+
+fn expected_kvs() -> Vec<(Bytes, Bytes)> {
+    (0..NUM_KEYS)
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect()
+}
+
+#[test]
+fn test_sst_compression_codecs() {
+    let dir = tempdir().unwrap();
+    let uncompressed = build_sst(CompressionType::None, &dir.path().join("none.sst"));
+    for (name, compression) in [
+        ("lz4", CompressionType::Lz4),
+        ("zstd", CompressionType::Zstd),
+        ("snappy", CompressionType::Snappy),
+    ] {
+        let path = dir.path().join(format!("{name}.sst"));
+        let sst = build_sst(compression, &path);
+        assert!(sst
+            .block_meta
+            .iter()
+            .all(|meta| meta.compression == compression));
+        assert!(
+            sst.table_size() < uncompressed.table_size(),
+            "{name}: {} >= {}",
+            sst.table_size(),
+            uncompressed.table_size()
+        );
+        let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+        assert_eq!(sst.num_of_blocks(), uncompressed.num_of_blocks());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        check_iter_result_by_key(&mut iter, expected_kvs());
+    }
+}
+
+#[test]
+fn test_sst_open_legacy_format() {
+    // Hand-craft an SST in the layout used before block meta recorded the compression type.
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("legacy.sst");
+    let mut buf = Vec::new();
+    let mut raw_meta = Vec::new();
+    let mut key_hashes = Vec::new();
+    // Blocks of this layout use `u16` lengths and offsets without key prefix compression.
+    for idxs in (0..NUM_KEYS).collect::<Vec<_>>().chunks(30) {
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for &idx in idxs {
+            key_hashes.push(farmhash::fingerprint32(&key_of(idx)));
+            offsets.push(data.len() as u32);
+            data.put_u16(0);
+            data.put_u16(key_of(idx).len() as u16);
+            data.put_slice(&key_of(idx));
+            data.put_u64(1);
+            data.put_u16(value_of(idx).len() as u16);
+            data.put_slice(&value_of(idx));
+        }
+        let block = Block {
+            data,
+            offsets,
+            format: BlockFormat::Legacy,
+            hash_index: None,
+        }
+        .encode();
+        raw_meta.push((buf.len(), key_of(idxs[0]), key_of(idxs[idxs.len() - 1])));
+        buf.extend(&block);
+        buf.put_u32(crc32fast::hash(&block));
+    }
+    let meta_offset = buf.len();
+    buf.put_u32(raw_meta.len() as u32);
+    for (offset, first_key, last_key) in &raw_meta {
+        buf.put_u32(*offset as u32);
+        buf.put_u16(first_key.len() as u16);
+        buf.put_slice(first_key);
+        buf.put_u64(1);
+        buf.put_u16(last_key.len() as u16);
+        buf.put_slice(last_key);
+        buf.put_u64(1);
+    }
+    buf.put_u64(1);
+    buf.put_u32(crc32fast::hash(&buf[meta_offset + 4..]));
+    buf.put_u32(meta_offset as u32);
+    let bloom_offset = buf.len();
+    Bloom::build_from_key_hashes(&key_hashes, 10).encode(&mut buf);
+    buf.put_u32(bloom_offset as u32);
+    std::fs::write(&path, &buf).unwrap();
+
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.num_of_blocks(), raw_meta.len());
+    assert_eq!(sst.max_ts(), 1);
+    assert!(sst
+        .block_meta
+        .iter()
+        .all(|meta| meta.compression == CompressionType::None));
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    check_iter_result_by_key(&mut iter, expected_kvs());
+}
+
+#[test]
+fn test_storage_with_compression() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.compression = CompressionType::Lz4;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    {
+        let state = storage.inner.state.read();
+        let sst = &state.sstables[&state.l0_sstables[0]];
+        assert!(sst
+            .block_meta
+            .iter()
+            .all(|meta| meta.compression == CompressionType::Lz4));
+    }
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in (0..NUM_KEYS).step_by(7) {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+}
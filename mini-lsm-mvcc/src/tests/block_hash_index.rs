@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockBuilder, BlockFormat, BlockIterator},
+    compact::CompactionOptions,
+    key::{KeySlice, TS_RANGE_BEGIN},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::versioned_value_of;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx * 2).into_bytes()
+}
+
+fn absent_key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx * 2 + 1).into_bytes()
+}
+
+fn builder_for_corruption() -> BlockBuilder {
+    let mut builder = BlockBuilder::new(4096).with_hash_index(true);
+    for idx in 0..10 {
+        assert!(builder.add(
+            KeySlice::from_slice(&key_of(idx), 1),
+            &versioned_value_of(idx, 1)
+        ));
+    }
+    builder
+}
+
+#[test]
+fn test_block_hash_index_lookup() {
+    const NUM_KEYS: usize = 200;
+    const NUM_VERSIONS: u64 = 3;
+    let mut builder = BlockBuilder::new(65536).with_hash_index(true);
+    for idx in 0..NUM_KEYS {
+        for ts in (1..=NUM_VERSIONS).rev() {
+            let key = key_of(idx);
+            assert!(builder.add(
+                KeySlice::from_slice(&key, ts),
+                &versioned_value_of(idx, ts as usize)
+            ));
+        }
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    assert!(block.hash_index.is_some());
+    let mut num_skipped = 0;
+    for idx in 0..NUM_KEYS {
+        // The versions of a key may span restart points.
+        for ts in (1..=NUM_VERSIONS + 1).rev() {
+            let key = key_of(idx);
+            let iter = BlockIterator::create_and_seek_to_key_for_get(
+                block.clone(),
+                KeySlice::from_slice(&key, ts),
+            )
+            .unwrap();
+            let ts = ts.min(NUM_VERSIONS);
+            assert_eq!(iter.key(), KeySlice::from_slice(&key, ts));
+            assert_eq!(iter.value(), versioned_value_of(idx, ts as usize));
+        }
+        let key = absent_key_of(idx);
+        let key = KeySlice::from_slice(&key, TS_RANGE_BEGIN);
+        match BlockIterator::create_and_seek_to_key_for_get(block.clone(), key) {
+            Some(iter) => assert!(!iter.is_valid() || iter.key().key_ref() != key.key_ref()),
+            None => num_skipped += 1,
+        }
+    }
+    // With the buckets at most 3/4 full, many absent keys hash to an empty bucket.
+    assert!(num_skipped > NUM_KEYS / 4, "{num_skipped}");
+
+    // A corrupt hash index fails to decode instead of panicking.
+    let encoded = builder_for_corruption().build().encode();
+    assert!(Block::decode(&encoded).hash_index.is_some());
+    let trailer = encoded.len() - 4;
+    let mut no_buckets = encoded.to_vec();
+    no_buckets[trailer - 2..trailer].copy_from_slice(&0u16.to_be_bytes());
+    assert!(Block::try_decode_with_format(&no_buckets, BlockFormat::Restart).is_err());
+    let mut too_many_buckets = encoded.to_vec();
+    too_many_buckets[trailer - 2..trailer].copy_from_slice(&u16::MAX.to_be_bytes());
+    assert!(Block::try_decode_with_format(&too_many_buckets, BlockFormat::Restart).is_err());
+}
+
+#[test]
+fn test_storage_with_block_hash_index() {
+    const NUM_KEYS: usize = 500;
+    const NUM_VERSIONS: usize = 3;
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // Small blocks, so that the versions of many keys start in the next block.
+    options.block_size = 256;
+    options.block_hash_index = true;
+    options.history_retention = u64::MAX;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut commit_ts = Vec::new();
+    for version in 0..NUM_VERSIONS {
+        for idx in 0..NUM_KEYS {
+            storage
+                .put(&key_of(idx), &versioned_value_of(idx, version))
+                .unwrap();
+        }
+        commit_ts.push(storage.inner.mvcc().latest_commit_ts());
+        storage.force_flush().unwrap();
+    }
+    let check = |storage: &MiniLsm| {
+        for idx in 0..NUM_KEYS {
+            for (version, ts) in commit_ts.iter().enumerate() {
+                assert_eq!(
+                    storage.get_at(&key_of(idx), *ts).unwrap(),
+                    Some(versioned_value_of(idx, version))
+                );
+            }
+            assert_eq!(storage.get(&absent_key_of(idx)).unwrap(), None);
+        }
+    };
+    check(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use bytes::BufMut;
+
+use crate::{
+    block::{Block, BlockBuilder, BlockFormat, BlockIterator},
+    key::{KeySlice, KeyVec},
+    varint::put_varint,
+};
+
+use super::common::NUM_KEYS;
+
+fn key_of(idx: usize) -> KeyVec {
+    KeyVec::from_vec_with_ts(format!("key_{:05}", idx * 2).into_bytes(), 1)
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{}", idx).into_bytes()
+}
+
+/// Check seeks and iteration in both directions over a block of `NUM_KEYS` keys.
+fn check_block(block: Block) {
+    let block = Arc::new(block);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for idx in 0..NUM_KEYS {
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    let mut iter = BlockIterator::create_and_seek_to_last(block.clone());
+    for idx in (0..NUM_KEYS).rev() {
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        assert_eq!(iter.value(), value_of(idx));
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+    for idx in 0..NUM_KEYS {
+        let iter = BlockIterator::create_and_seek_to_key(block.clone(), key_of(idx).as_key_slice());
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+        // A key between two keys of the block.
+        let between = format!("key_{:05}", idx * 2 + 1).into_bytes();
+        let between = KeySlice::from_slice(&between, 1);
+        let iter = BlockIterator::create_and_seek_to_key(block.clone(), between);
+        assert_eq!(iter.is_valid(), idx + 1 < NUM_KEYS);
+        if idx + 1 < NUM_KEYS {
+            assert_eq!(iter.key(), key_of(idx + 1).as_key_slice());
+        }
+        let iter = BlockIterator::create_and_seek_for_prev(block.clone(), between);
+        assert_eq!(iter.key(), key_of(idx).as_key_slice());
+    }
+}
+
+#[test]
+fn test_block_restart_points() {
+    let mut builder = BlockBuilder::new(65536);
+    for idx in 0..NUM_KEYS {
+        assert!(builder.add(key_of(idx).as_key_slice(), &value_of(idx)));
+    }
+    let block = builder.build();
+    // A restart point every 16 entries, each storing its full key.
+    assert_eq!(block.offsets.len(), NUM_KEYS.div_ceil(16));
+    for offset in &block.offsets {
+        assert_eq!(block.data[*offset as usize], 0);
+    }
+    let encoded = block.encode();
+    let decoded = Block::decode(&encoded);
+    assert_eq!(decoded.offsets, block.offsets);
+    check_block(decoded);
+}
+
+#[test]
+fn test_block_varint_format() {
+    // A block in the format used before restart points, where every key is delta-encoded against
+    // the first key and the offsets of all entries are stored. The first key is stored in full.
+    let first_key = key_of(0);
+    let mut data = Vec::new();
+    let mut offsets = Vec::new();
+    for idx in 0..NUM_KEYS {
+        let key = key_of(idx);
+        let overlap = if idx == 0 {
+            &[][..]
+        } else {
+            first_key.key_ref()
+        }
+        .iter()
+        .zip(key.key_ref())
+        .take_while(|(a, b)| a == b)
+        .count();
+        offsets.push(data.len() as u32);
+        put_varint(&mut data, overlap as u64);
+        put_varint(&mut data, (key.key_len() - overlap) as u64);
+        data.put_slice(&key.key_ref()[overlap..]);
+        data.put_u64(key.ts());
+        put_varint(&mut data, value_of(idx).len() as u64);
+        data.put_slice(&value_of(idx));
+    }
+    let encoded = Block {
+        data,
+        offsets,
+        format: BlockFormat::Varint,
+        hash_index: None,
+    }
+    .encode();
+    let block = Block::decode_with_format(&encoded, BlockFormat::Varint);
+    assert_eq!(block.offsets.len(), NUM_KEYS);
+    check_block(block);
+}
@@ -0,0 +1,106 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    iterators::StorageIterator,
+    lsm_storage::MiniLsm,
+};
+
+use super::common::{key_of, wal_options, NUM_KEYS};
+
+fn value_of(idx: usize, version: usize) -> Bytes {
+    Bytes::from(format!("value_{:05}_{}", idx, version).repeat(4))
+}
+
+#[test]
+fn test_checkpoint() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), wal_options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    // Leave some writes in the memtable, which are flushed by the checkpoint.
+    for idx in (0..NUM_KEYS).step_by(2) {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage
+        .delete_range(&key_of(90), &key_of(NUM_KEYS))
+        .unwrap();
+    let commit_ts = storage.inner.mvcc().latest_commit_ts();
+    let checkpoint_dir = dir.path().join("checkpoint");
+    storage.create_checkpoint(&checkpoint_dir).unwrap();
+    assert!(storage.create_checkpoint(&checkpoint_dir).is_err());
+
+    // Writes after the checkpoint are not in it.
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 2)).unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let checkpoint = MiniLsm::open(&checkpoint_dir, wal_options()).unwrap();
+    assert_eq!(checkpoint.inner.mvcc().latest_commit_ts(), commit_ts);
+    let mut iter = checkpoint.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..90 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, 1 - idx % 2));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // The checkpoint is an independent database.
+    checkpoint.put(&key_of(0), &value_of(0, 3)).unwrap();
+    checkpoint.close().unwrap();
+    drop(checkpoint);
+    let checkpoint = MiniLsm::open(&checkpoint_dir, wal_options()).unwrap();
+    assert_eq!(checkpoint.get(&key_of(0)).unwrap(), Some(value_of(0, 3)));
+    let storage = MiniLsm::open(dir.path().join("db"), wal_options()).unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(value_of(0, 2)));
+}
+
+#[test]
+fn test_checkpoint_column_family_value_log() {
+    let dir = tempdir().unwrap();
+    let mut options = wal_options();
+    options.value_separation_threshold = Some(32);
+    let storage = MiniLsm::open(dir.path().join("db"), options.clone()).unwrap();
+    let cf = storage
+        .create_column_family(
+            "meta",
+            CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                size_ratio_percent: 200,
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 3,
+            }),
+        )
+        .unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        storage
+            .put_cf(&cf, &key_of(idx), &value_of(idx, 1))
+            .unwrap();
+    }
+    let checkpoint_dir = dir.path().join("checkpoint");
+    storage.create_checkpoint(&checkpoint_dir).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    std::fs::remove_dir_all(dir.path().join("db")).unwrap();
+
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options).unwrap();
+    let cf = checkpoint.column_family("meta").unwrap();
+    for idx in 0..NUM_KEYS {
+        assert_eq!(
+            checkpoint.get(&key_of(idx)).unwrap(),
+            Some(value_of(idx, 0))
+        );
+        assert_eq!(
+            checkpoint.get_cf(&cf, &key_of(idx)).unwrap(),
+            Some(value_of(idx, 1))
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::common::{key_of, value_of};
+use super::harness::check_lsm_iter_result_by_key;
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+fn simple_compaction() -> CompactionOptions {
+    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    })
+}
+
+#[test]
+fn test_column_family_write_batch() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let cf = storage
+        .create_column_family("meta", simple_compaction())
+        .unwrap();
+    assert!(storage
+        .create_column_family("meta", CompactionOptions::NoCompaction)
+        .is_err());
+    assert!(storage
+        .create_column_family("default", CompactionOptions::NoCompaction)
+        .is_err());
+    let default_cf = storage.default_column_family();
+    storage
+        .write_batch_cf(&[
+            (
+                &default_cf,
+                WriteBatchRecord::Put(&b"a"[..], &b"default_a"[..]),
+            ),
+            (&cf, WriteBatchRecord::Put(&b"a"[..], &b"meta_a"[..])),
+            (&cf, WriteBatchRecord::Put(&b"b"[..], &b"meta_b"[..])),
+        ])
+        .unwrap();
+    storage.force_flush().unwrap();
+    storage.put_cf(&cf, b"c", b"meta_c").unwrap();
+    storage.delete_cf(&cf, b"b").unwrap();
+    storage.put(b"d", b"default_d").unwrap();
+
+    let check = |storage: &MiniLsm| {
+        let cf = storage.column_family("meta").unwrap();
+        assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("default_a")));
+        assert_eq!(storage.get(b"c").unwrap(), None);
+        assert_eq!(
+            storage.get_cf(&cf, b"a").unwrap(),
+            Some(Bytes::from("meta_a"))
+        );
+        assert_eq!(storage.get_cf(&cf, b"b").unwrap(), None);
+        assert_eq!(storage.get_cf(&cf, b"d").unwrap(), None);
+        check_lsm_iter_result_by_key(
+            &mut storage
+                .scan_cf(&cf, Bound::Unbounded, Bound::Unbounded)
+                .unwrap(),
+            vec![
+                (Bytes::from("a"), Bytes::from("meta_a")),
+                (Bytes::from("c"), Bytes::from("meta_c")),
+            ],
+        );
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("a"), Bytes::from("default_a")),
+                (Bytes::from("d"), Bytes::from("default_d")),
+            ],
+        );
+    };
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    // The unflushed writes to both column families are recovered from the shared WAL.
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+    let cf = storage.column_family("meta").unwrap();
+    assert_eq!(
+        format!("{:?}", cf.inner.options.compaction_options),
+        format!("{:?}", simple_compaction())
+    );
+}
+
+#[test]
+fn test_column_family_flush_with_shared_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.target_sst_size = 4096;
+    options.num_memtable_limit = 4;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let cf = storage
+        .create_column_family("meta", simple_compaction())
+        .unwrap();
+    // Only the column family is written, so the memtables of the default column family are frozen
+    // empty to rotate the WAL.
+    for idx in 0..2000 {
+        storage.put_cf(&cf, &key_of(idx), &value_of(idx)).unwrap();
+    }
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let cf = storage.column_family("meta").unwrap();
+    for idx in 0..2000 {
+        assert_eq!(
+            storage.get_cf(&cf, &key_of(idx)).unwrap(),
+            Some(value_of(idx))
+        );
+    }
+    for idx in 0..2000 {
+        let expected = if idx < 100 { Some(value_of(idx)) } else { None };
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+    // Each column family only has its own SSTs.
+    let cf_state = cf.inner.state.read();
+    assert!(!cf_state.l0_sstables.is_empty() || !cf_state.levels[0].1.is_empty());
+    let state = storage.inner.state.read();
+    for id in state.l0_sstables.iter() {
+        assert!(!cf_state.sstables.contains_key(id));
+    }
+}
+
+#[test]
+fn test_column_family_empty_shared_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .create_column_family("meta", CompactionOptions::NoCompaction)
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Nothing was logged to the WAL, so it is dropped with its memtable.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert!(storage.inner.state.read().imm_memtables.is_empty());
+    let cf = storage.column_family("meta").unwrap();
+    storage.put_cf(&cf, b"a", b"meta_a").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // The empty memtable of the default column family keeps the WAL the column family logged to.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.inner.state.read().imm_memtables.len(), 1);
+    let cf = storage.column_family("meta").unwrap();
+    assert_eq!(
+        storage.get_cf(&cf, b"a").unwrap(),
+        Some(Bytes::from_static(b"meta_a"))
+    );
+    let wal_cnt = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("wal".as_ref()))
+        .count();
+    assert_eq!(wal_cnt, 2);
+}
+
+#[test]
+fn test_column_family_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let cf = storage
+        .create_column_family("meta", CompactionOptions::NoCompaction)
+        .unwrap();
+    storage.put_cf(&cf, b"key", b"1").unwrap();
+
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    assert_eq!(txn1.get_cf(&cf, b"key").unwrap(), Some(Bytes::from("1")));
+    txn1.put(b"key", b"2");
+    txn1.put_cf(&cf, b"other", b"2");
+    // Writing the same key in the default column family does not conflict.
+    txn2.get(b"other").unwrap();
+    txn2.put_cf(&cf, b"key", b"3");
+    check_lsm_iter_result_by_key(
+        &mut txn2
+            .scan_cf(&cf, Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![(Bytes::from("key"), Bytes::from("3"))],
+    );
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+
+    assert_eq!(storage.get(b"key").unwrap(), None);
+    assert_eq!(storage.get_cf(&cf, b"key").unwrap(), Some(Bytes::from("3")));
+    assert_eq!(storage.get_cf(&cf, b"other").unwrap(), None);
+}
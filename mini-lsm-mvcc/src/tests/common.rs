@@ -0,0 +1,29 @@
+//! Helpers shared by the tests of this crate. Unlike `harness`, they are not part of `mini-lsm`.
+
+use bytes::Bytes;
+
+use crate::{compact::CompactionOptions, lsm_storage::LsmStorageOptions};
+
+/// The number of keys most storage tests write.
+pub const NUM_KEYS: usize = 100;
+
+/// A key that sorts by `idx`.
+pub fn key_of(idx: usize) -> Bytes {
+    Bytes::from(format!("key_{:05}", idx))
+}
+
+pub fn value_of(idx: usize) -> Bytes {
+    Bytes::from(format!("value_{:05}", idx))
+}
+
+/// A value that differs for each `version` written to the key of `idx`.
+pub fn versioned_value_of(idx: usize, version: usize) -> Bytes {
+    Bytes::from(format!("value_{:05}_{}", idx, version))
+}
+
+/// Options of a storage without compaction that writes a WAL.
+pub fn wal_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
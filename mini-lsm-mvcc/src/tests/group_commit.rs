@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    group_commit::WalSyncPolicy,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+fn open(path: impl AsRef<Path>, wal_sync: WalSyncPolicy) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_sync = wal_sync;
+    Arc::new(LsmStorageInner::open(path.as_ref(), options).unwrap())
+}
+
+/// Bytes of the current WAL that reached the file, rather than the write buffer.
+fn wal_len(storage: &LsmStorageInner) -> u64 {
+    let id = storage.state.read().memtable.id();
+    std::fs::metadata(storage.path_of_wal(id)).unwrap().len()
+}
+
+#[test]
+fn test_group_commit_shares_sync() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path(), WalSyncPolicy::Always);
+    storage.put(b"key", b"value").unwrap();
+    assert_eq!(storage.wal_sync.lock().num_syncs, 1);
+    let synced_len = wal_len(&storage);
+    assert!(synced_len > 0);
+
+    // Hold back the sync of the first writer, so that the others queue up behind it and are
+    // committed as one group by the next leader.
+    let sync_guard = storage.wal_sync.lock();
+    let writers = (0..8)
+        .map(|i| {
+            let storage = storage.clone();
+            std::thread::spawn(move || storage.put(format!("key{}", i).as_bytes(), b"value"))
+        })
+        .collect::<Vec<_>>();
+    while storage.write_queue.num_pending() < 7 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(writers.iter().all(|writer| !writer.is_finished()));
+    assert_eq!(wal_len(&storage), synced_len);
+    // Writes are not visible before they are durable.
+    assert_eq!(storage.mvcc().latest_commit_ts(), 1);
+    drop(sync_guard);
+    for writer in writers {
+        writer.join().unwrap().unwrap();
+    }
+    assert_eq!(storage.mvcc().latest_commit_ts(), 9);
+    assert_eq!(storage.wal_sync.lock().num_syncs, 3);
+    assert!(wal_len(&storage) >= synced_len * 9);
+    for i in 0..8 {
+        let key = format!("key{}", i);
+        assert_eq!(&storage.get(key.as_bytes()).unwrap().unwrap()[..], b"value");
+    }
+}
+
+#[test]
+fn test_wal_sync_policies() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path().join("every"), WalSyncPolicy::EveryN(3));
+    for _ in 0..2 {
+        storage.put(b"key", b"value").unwrap();
+        assert_eq!(wal_len(&storage), 0);
+    }
+    storage.put(b"key", b"value").unwrap();
+    assert!(wal_len(&storage) > 0);
+    assert_eq!(storage.wal_sync.lock().num_syncs, 1);
+
+    let storage = open(dir.path().join("never"), WalSyncPolicy::Never);
+    for _ in 0..10 {
+        storage.put(b"key", b"value").unwrap();
+    }
+    assert_eq!(wal_len(&storage), 0);
+    storage.sync().unwrap();
+    assert!(wal_len(&storage) > 0);
+
+    // Writes are synced once the interval has passed since the last sync.
+    let storage = open(dir.path().join("interval"), WalSyncPolicy::Interval(50));
+    storage.put(b"key", b"value").unwrap();
+    assert_eq!(wal_len(&storage), 0);
+    std::thread::sleep(Duration::from_millis(60));
+    storage.put(b"key", b"value").unwrap();
+    assert!(wal_len(&storage) > 0);
+}
@@ -0,0 +1,190 @@
+use bytes::{BufMut, Bytes};
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::{KeyBytes, KeySlice},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::FileObject,
+    value_log::{ValueLogBuilder, ValueLogFile, ValuePointer},
+    varint::get_varint,
+    wal::Wal,
+};
+
+fn large_key() -> Vec<u8> {
+    let mut key = b"key2_".to_vec();
+    key.resize(100_000, b'k');
+    key
+}
+
+fn large_value() -> Vec<u8> {
+    (0..200_000).map(|idx| (idx % 251) as u8).collect()
+}
+
+fn check(storage: &MiniLsm) {
+    let large_key = large_key();
+    assert_eq!(
+        storage.get(b"key1").unwrap(),
+        Some(Bytes::from(large_value()))
+    );
+    assert_eq!(
+        storage.get(&large_key).unwrap(),
+        Some(Bytes::from_static(b"value2"))
+    );
+    assert_eq!(
+        storage.get(b"key3").unwrap(),
+        Some(Bytes::from_static(b"value3"))
+    );
+    let mut iter = storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    assert_eq!(keys, vec![b"key1".to_vec(), large_key, b"key3".to_vec()]);
+}
+
+#[test]
+fn test_large_entries() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"key1", &large_value()).unwrap();
+    storage.put(&large_key(), b"value2").unwrap();
+    storage.put(b"key3", b"value3").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    // Recovered from the WAL.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage);
+    storage.force_flush().unwrap();
+    check(&storage);
+    storage.put(b"key3", b"value3").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    storage.close().unwrap();
+
+    // Read from the SSTs.
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_value_log_large_keys_and_legacy_records() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_separation_threshold = Some(1024);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // The value of a key larger than 64 KiB is separated into the value log.
+    storage.put(&large_key(), &large_value()).unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(
+        storage.get(&large_key()).unwrap(),
+        Some(Bytes::from(large_value()))
+    );
+    assert!(!storage
+        .inner
+        .state
+        .read()
+        .sstables
+        .values()
+        .all(|sst| sst.value_log_refs().is_empty()));
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(&large_key()).unwrap(),
+        Some(Bytes::from(large_value()))
+    );
+
+    // A value log without a header, in the layout with `u16` key and `u32` value lengths.
+    let path = dir.path().join("legacy.vlog");
+    let mut data = Vec::new();
+    data.put_u16(4);
+    data.put_slice(b"key1");
+    data.put_u64(1);
+    data.put_u32(6);
+    data.put_slice(b"value1");
+    data.put_u32(crc32fast::hash(&data));
+    let len = data.len() as u32;
+    FileObject::create(&path, data).unwrap();
+    let value_log = ValueLogFile::open(1, &path).unwrap();
+    let ptr = ValuePointer {
+        file_id: 1,
+        offset: 0,
+        len,
+    };
+    assert_eq!(value_log.read(&ptr).unwrap(), Bytes::from_static(b"value1"));
+
+    let mut builder = ValueLogBuilder::new(2);
+    let ptr = builder.add(KeySlice::from_slice(&large_key(), 1), b"value2");
+    let value_log = builder.build(dir.path().join("new.vlog")).unwrap();
+    assert_eq!(value_log.read(&ptr).unwrap(), Bytes::from_static(b"value2"));
+
+    // Truncated and overlong varints are rejected.
+    assert!(get_varint(&mut &[][..]).is_err());
+    assert!(get_varint(&mut &[0x80, 0x80][..]).is_err());
+    assert!(get_varint(&mut &[0xff; 11][..]).is_err());
+    assert_eq!(get_varint(&mut &[0x80, 0x01][..]).unwrap(), 128);
+}
+
+#[test]
+fn test_recover_legacy_wal() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("legacy.wal");
+    // A batch in the layout used before lengths were varints, with `u16` key and value lengths
+    // and no flags in the batch size header.
+    let mut batch = Vec::new();
+    for (key, value) in [(b"key1", b"value1"), (b"key2", b"value2")] {
+        batch.put_u16(key.len() as u16);
+        batch.put_slice(key);
+        batch.put_u64(1);
+        batch.put_u16(value.len() as u16);
+        batch.put_slice(value);
+    }
+    let mut data = Vec::new();
+    data.put_u32(batch.len() as u32);
+    data.put_slice(&batch);
+    data.put_u32(crc32fast::hash(&batch));
+    std::fs::write(&path, data).unwrap();
+
+    let skiplist = SkipMap::new();
+    let range_tombstones = SkipMap::new();
+    let wal = Wal::recover(&path, &skiplist, &range_tombstones).unwrap();
+    let key = |key: &'static [u8]| KeyBytes::from_bytes_with_ts(Bytes::from_static(key), 1);
+    assert_eq!(skiplist.len(), 2);
+    assert_eq!(
+        skiplist.get(&key(b"key2")).unwrap().value(),
+        &Bytes::from_static(b"value2")
+    );
+
+    // New records are appended in the varint layout and recovered together with the old ones.
+    wal.put(
+        KeyBytes::from_bytes_with_ts(Bytes::from(large_key()), 2).as_key_slice(),
+        &large_value(),
+    )
+    .unwrap();
+    wal.sync().unwrap();
+    drop(wal);
+    let skiplist = SkipMap::new();
+    Wal::recover(&path, &skiplist, &range_tombstones).unwrap();
+    assert_eq!(skiplist.len(), 3);
+    assert_eq!(
+        skiplist.get(&key(b"key1")).unwrap().value(),
+        &Bytes::from_static(b"value1")
+    );
+    assert_eq!(
+        skiplist
+            .get(&KeyBytes::from_bytes_with_ts(Bytes::from(large_key()), 2))
+            .unwrap()
+            .value(),
+        &Bytes::from(large_value())
+    );
+}
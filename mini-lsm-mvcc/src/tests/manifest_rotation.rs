@@ -0,0 +1,141 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::{key_of, value_of, NUM_KEYS};
+
+fn options(max_manifest_size: u64) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.enable_wal = true;
+    options.max_manifest_size = max_manifest_size;
+    options
+}
+
+fn manifest_files(dir: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("MANIFEST"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_manifest_rotation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(8192)).unwrap();
+    let cf = storage
+        .create_column_family("meta", CompactionOptions::NoCompaction)
+        .unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        storage.put_cf(&cf, &key_of(idx), &value_of(idx)).unwrap();
+        if idx % 5 == 4 {
+            storage.force_flush().unwrap();
+        }
+    }
+    // Keep some writes in the WAL.
+    storage.put(b"wal", b"default").unwrap();
+    storage.put_cf(&cf, b"wal", b"meta").unwrap();
+    // The flush thread may have rotated the manifest already.
+    storage.inner.maybe_rotate_manifest().unwrap();
+    assert!(storage.inner.manifest().size() < 8192);
+    let files = manifest_files(dir.path());
+    assert_eq!(files.len(), 1);
+    assert_ne!(files[0], "MANIFEST-000001");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("CURRENT")).unwrap(),
+        files[0]
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options(8192)).unwrap();
+    let cf = storage.column_family("meta").unwrap();
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+        assert_eq!(
+            storage.get_cf(&cf, &key_of(idx)).unwrap(),
+            Some(value_of(idx))
+        );
+    }
+    assert_eq!(storage.get(b"wal").unwrap(), Some(Bytes::from("default")));
+    assert_eq!(
+        storage.get_cf(&cf, b"wal").unwrap(),
+        Some(Bytes::from("meta"))
+    );
+}
+
+#[test]
+fn test_manifest_legacy_and_interrupted_rotation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(4 << 20)).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // A database created before manifest rotation has a single `MANIFEST` file.
+    std::fs::rename(
+        dir.path().join("MANIFEST-000001"),
+        dir.path().join("MANIFEST"),
+    )
+    .unwrap();
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    let storage = MiniLsm::open(&dir, options(4 << 20)).unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(value_of(0)));
+    storage.close().unwrap();
+    drop(storage);
+
+    // A rotation interrupted before switching `CURRENT` leaves an unused manifest file.
+    std::fs::write(dir.path().join("MANIFEST-000001"), b"garbage").unwrap();
+    std::fs::write(dir.path().join("CURRENT.tmp"), b"MANIFEST-000001").unwrap();
+    let storage = MiniLsm::open(&dir, options(1)).unwrap();
+    assert_eq!(manifest_files(dir.path()), vec!["MANIFEST-000001"]);
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+}
+
+#[test]
+fn test_manifest_rotation_with_large_snapshot() {
+    let dir = tempdir().unwrap();
+    // The snapshot alone is larger than the limit.
+    let storage = MiniLsm::open(&dir, options(1)).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % 20 == 19 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.inner.maybe_rotate_manifest().unwrap();
+    let rotated = manifest_files(dir.path());
+    let rotated_size = storage.inner.manifest().size();
+
+    // Not rotated again until the manifest doubles in size.
+    storage.put(b"key", b"value").unwrap();
+    storage.force_flush().unwrap();
+    storage.inner.maybe_rotate_manifest().unwrap();
+    assert_eq!(manifest_files(dir.path()), rotated);
+    while storage.inner.manifest().size() < 2 * rotated_size {
+        storage.put(b"key", b"value").unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.inner.maybe_rotate_manifest().unwrap();
+    assert_ne!(manifest_files(dir.path()), rotated);
+}
@@ -0,0 +1,132 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, MiniLsm},
+};
+
+use super::common::{key_of, value_of, wal_options, NUM_KEYS};
+
+/// Write a database with two SSTs and a WAL, and the files left by crashes before the manifest
+/// refers to them. Returns the names of the orphans.
+fn write_with_orphans(dir: &Path) -> [&'static str; 5] {
+    let storage = MiniLsm::open(dir, wal_options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % 50 == 49 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.put(b"wal", b"wal").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Also a file not owned by the storage.
+    let orphans = [
+        "99999.sst",
+        "99998.vlog",
+        "99997.wal",
+        "MANIFEST-000099",
+        "CURRENT.tmp",
+    ];
+    for name in orphans.iter().chain(&["notes.txt"]) {
+        std::fs::write(dir.join(name), b"garbage").unwrap();
+    }
+    orphans
+}
+
+fn assert_orphans_reported(storage: &MiniLsm, dir: &Path, orphans: &[&str]) {
+    let mut reported = storage.orphan_files().to_vec();
+    reported.sort();
+    let mut expected = orphans
+        .iter()
+        .map(|name| dir.join(name))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(reported, expected);
+}
+
+#[test]
+fn test_remove_orphan_files() {
+    let dir = tempdir().unwrap();
+    let orphans = write_with_orphans(dir.path());
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    assert_orphans_reported(&storage, dir.path(), &orphans);
+    for name in orphans {
+        assert!(!dir.path().join(name).exists(), "{} not removed", name);
+    }
+    assert!(!dir.path().join("lost").exists());
+    assert!(dir.path().join("notes.txt").exists());
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+    assert_eq!(storage.get(b"wal").unwrap(), Some(Bytes::from("wal")));
+}
+
+#[test]
+fn test_quarantine_orphan_files() {
+    let dir = tempdir().unwrap();
+    let orphans = write_with_orphans(dir.path());
+    let mut quarantine_options = wal_options();
+    quarantine_options.quarantine_orphan_files = true;
+    let storage = MiniLsm::open(&dir, quarantine_options).unwrap();
+    assert_orphans_reported(&storage, dir.path(), &orphans);
+    for name in orphans {
+        assert!(!dir.path().join(name).exists(), "{} not moved", name);
+        assert_eq!(
+            std::fs::read(dir.path().join("lost").join(name)).unwrap(),
+            b"garbage"
+        );
+    }
+    assert!(dir.path().join("notes.txt").exists());
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(value_of(0)));
+    drop(storage);
+
+    // Nothing is left to quarantine on the next open.
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    assert!(storage.orphan_files().is_empty());
+}
+
+#[test]
+fn test_deferred_sst_deletion() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % 50 == 49 {
+            storage.force_flush().unwrap();
+        }
+    }
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+    assert_eq!(l0_sstables.len(), 2);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+
+    // The iterator still reads the compacted SSTs.
+    for id in &l0_sstables {
+        assert!(LsmStorageInner::path_of_sst_static(dir.path(), *id).exists());
+    }
+    for idx in 0..NUM_KEYS {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    drop(iter);
+    storage.inner.obsolete_files.purge().unwrap();
+    for id in &l0_sstables {
+        assert!(!LsmStorageInner::path_of_sst_static(dir.path(), *id).exists());
+    }
+}
+
+#[test]
+fn test_unknown_obsolete_value_log() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    // The value log is not referenced by any removed SST, which fails instead of panicking.
+    assert!(storage.inner.obsolete_files.add(Vec::new(), &[1]).is_err());
+}
@@ -0,0 +1,240 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+    options::{ReadOptions, WriteOptions},
+};
+
+use super::common::{key_of, value_of};
+
+const NUM_KEYS: usize = 1000;
+
+#[test]
+fn test_write_options() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options.clone()).unwrap());
+    let wal_path = storage.path_of_wal(storage.state.read().memtable.id());
+    let disable_wal = WriteOptions {
+        disable_wal: true,
+        ..Default::default()
+    };
+    storage
+        .put_with_options(b"key1", b"value1", &disable_wal)
+        .unwrap();
+    storage.sync().unwrap();
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+    let sync = WriteOptions {
+        sync: true,
+        ..Default::default()
+    };
+    storage.put_with_options(b"key2", b"value2", &sync).unwrap();
+    assert!(std::fs::metadata(&wal_path).unwrap().len() > 0);
+    assert_eq!(storage.wal_sync.lock().num_syncs, 1);
+    assert_eq!(
+        storage.get(b"key1").unwrap(),
+        Some(Bytes::from_static(b"value1"))
+    );
+
+    // Only the write logged to the WAL is recovered.
+    drop(storage);
+    options.num_memtable_limit = 1;
+    options.write_stall.stop_imm_memtables = 1;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    assert_eq!(storage.get(b"key1").unwrap(), None);
+    assert_eq!(
+        storage.get(b"key2").unwrap(),
+        Some(Bytes::from_static(b"value2"))
+    );
+
+    // A write that would be blocked fails right away.
+    while !storage.state.read().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    let no_slowdown = WriteOptions {
+        no_slowdown: true,
+        ..Default::default()
+    };
+    assert!(storage
+        .put_with_options(b"key3", b"value3", &no_slowdown)
+        .is_err());
+    assert!(storage.delete_with_options(b"key2", &no_slowdown).is_err());
+    assert_eq!(storage.write_stall_stats().stopped_writes, 0);
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage
+        .put_with_options(b"key3", b"value3", &no_slowdown)
+        .unwrap();
+}
+
+#[test]
+fn test_read_options() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    let snapshot = storage.snapshot();
+    storage.put(&key_of(0), b"new_value").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    let sst = {
+        let state = storage.state.read();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    assert!(sst.num_of_blocks() > 2);
+
+    // Scan all blocks with readahead, without filling the block cache.
+    let no_cache = ReadOptions {
+        fill_cache: false,
+        readahead_size: 1 << 20,
+        ..Default::default()
+    };
+    let mut iter = storage
+        .scan_with_options(Bound::Unbounded, Bound::Unbounded, &no_cache)
+        .unwrap();
+    for idx in 1..NUM_KEYS {
+        iter.next().unwrap();
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+    }
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    for block_idx in 0..sst.num_of_blocks() {
+        assert!(!storage.block_cache.contains_key(&(sst.sst_id(), block_idx)));
+    }
+    storage.get(&key_of(0)).unwrap();
+    assert!(storage.block_cache.contains_key(&(sst.sst_id(), 0)));
+
+    // Read as of an older timestamp, and stop scans at the upper bound.
+    let old = ReadOptions {
+        read_ts: Some(snapshot.read_ts()),
+        iterate_upper_bound: Some(key_of(10)),
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(0), &old).unwrap(),
+        Some(value_of(0))
+    );
+    let mut iter = storage
+        .scan_with_options(Bound::Unbounded, Bound::Included(&key_of(20)), &old)
+        .unwrap();
+    for idx in 0..10 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Corrupted blocks are only read without verifying checksums.
+    let idx = NUM_KEYS - 1;
+    let path = storage.path_of_sst(sst.sst_id());
+    let mut data = std::fs::read(&path).unwrap();
+    let pos = data
+        .windows(value_of(idx).len())
+        .position(|window| window == value_of(idx))
+        .unwrap();
+    data[pos] = b'V';
+    std::fs::write(&path, data).unwrap();
+    assert!(storage.get(&key_of(idx)).is_err());
+    let no_verify = ReadOptions {
+        verify_checksums: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(idx), &no_verify).unwrap(),
+        Some(Bytes::from(format!("Value_{:05}", idx)))
+    );
+    // The unverified block was not cached for the reads that verify checksums.
+    assert!(storage.get(&key_of(idx)).is_err());
+}
+
+#[test]
+fn test_options_on_all_paths() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options.clone()).unwrap());
+    let column_family = storage
+        .create_column_family("cf", CompactionOptions::NoCompaction)
+        .unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        storage
+            .put_cf(&column_family, &key_of(idx), &value_of(idx))
+            .unwrap();
+    }
+    for inner in [&storage, &column_family.inner] {
+        inner
+            .force_freeze_memtable(&inner.state_lock.lock())
+            .unwrap();
+        inner.force_flush_next_imm_memtable().unwrap();
+    }
+
+    // Snapshots, column families and reverse scans read without filling the block cache, and
+    // reverse scans stop at the upper bound.
+    let no_cache = ReadOptions {
+        fill_cache: false,
+        iterate_upper_bound: Some(key_of(10)),
+        ..Default::default()
+    };
+    let snapshot = storage.snapshot();
+    assert_eq!(
+        snapshot.get_with_options(&key_of(0), &no_cache).unwrap(),
+        Some(value_of(0))
+    );
+    assert_eq!(
+        storage
+            .get_cf_with_options(&column_family, &key_of(0), &no_cache)
+            .unwrap(),
+        Some(value_of(0))
+    );
+    let iter = storage
+        .scan_rev_with_options(Bound::Unbounded, Bound::Unbounded, &no_cache)
+        .unwrap();
+    assert_eq!(iter.key(), key_of(9));
+    let iter = snapshot
+        .scan_cf_with_options(
+            &column_family,
+            Bound::Unbounded,
+            Bound::Unbounded,
+            &no_cache,
+        )
+        .unwrap();
+    assert_eq!(iter.key(), key_of(0));
+    assert_eq!(storage.block_cache.stats().usage, 0);
+
+    // Writes to column families and range deletions skip the WAL.
+    let disable_wal = WriteOptions {
+        disable_wal: true,
+        ..Default::default()
+    };
+    storage
+        .delete_cf_with_options(&column_family, &key_of(1), &disable_wal)
+        .unwrap();
+    storage
+        .delete_range_with_options(&key_of(2), &key_of(4), &disable_wal)
+        .unwrap();
+    assert_eq!(storage.get(&key_of(2)).unwrap(), None);
+    storage.sync().unwrap();
+    drop((snapshot, column_family, storage));
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let column_family = storage.column_family("cf").unwrap();
+    assert_eq!(storage.get(&key_of(2)).unwrap(), Some(value_of(2)));
+    assert_eq!(
+        storage.get_cf(&column_family, &key_of(1)).unwrap(),
+        Some(value_of(1))
+    );
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    block_cache::BlockKind,
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::{KeySlice, TS_RANGE_BEGIN},
+    lsm_storage::{BlockCache, LsmStorageOptions, MiniLsm},
+    table::{FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::common::versioned_value_of;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx * 2).into_bytes()
+}
+
+fn absent_key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx * 2 + 1).into_bytes()
+}
+
+const NUM_KEYS: usize = 500;
+const NUM_VERSIONS: u64 = 3;
+
+fn build_sst(partitioned_index: bool, path: &std::path::Path) {
+    let mut builder = SsTableBuilder::new(128).with_partitioned_index(partitioned_index);
+    for idx in 0..NUM_KEYS {
+        for ts in (1..=NUM_VERSIONS).rev() {
+            builder.add(
+                KeySlice::from_slice(&key_of(idx), ts),
+                &versioned_value_of(idx, ts as usize),
+            );
+        }
+    }
+    builder.build_for_test(path).unwrap();
+}
+
+#[test]
+fn test_sst_partitioned_index() {
+    let dir = tempdir().unwrap();
+    build_sst(false, &dir.path().join("full.sst"));
+    build_sst(true, &dir.path().join("partitioned.sst"));
+    let full =
+        SsTable::open_for_test(FileObject::open(&dir.path().join("full.sst")).unwrap()).unwrap();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let sst = Arc::new(
+        SsTable::open(
+            1,
+            Some(block_cache.clone()),
+            FileObject::open(&dir.path().join("partitioned.sst")).unwrap(),
+        )
+        .unwrap(),
+    );
+    // Only the top-level index is read when opening the SST.
+    assert!(sst.block_meta.is_empty());
+    assert!(sst.bloom.is_none());
+    assert!(!block_cache.contains(BlockKind::IndexPartition, &(1, 0)));
+    assert_eq!(sst.num_of_blocks(), full.num_of_blocks());
+    assert_eq!(sst.first_key(), full.first_key());
+    assert_eq!(sst.last_key(), full.last_key());
+    assert_eq!(sst.max_ts(), full.max_ts());
+    for idx in 0..sst.num_of_blocks() {
+        assert_eq!(sst.block_meta(idx).unwrap(), full.block_meta[idx]);
+    }
+    // Index and filter partitions are loaded through the block cache, and there are several.
+    assert!(block_cache.contains(BlockKind::IndexPartition, &(1, 0)));
+    assert!(block_cache.contains(BlockKind::IndexPartition, &(1, 1)));
+    // Index partitions are not mistaken for the data blocks at the same index.
+    assert!(!block_cache.contains_key(&(1, 0)));
+
+    for idx in 0..NUM_KEYS {
+        for ts in 1..=NUM_VERSIONS + 1 {
+            let key = key_of(idx);
+            let key = KeySlice::from_slice(&key, ts);
+            assert_eq!(
+                sst.find_block_idx(key).unwrap(),
+                full.find_block_idx(key).unwrap()
+            );
+        }
+        assert!(sst.may_contain(&key_of(idx)).unwrap());
+        let key = absent_key_of(idx);
+        let key = KeySlice::from_slice(&key, TS_RANGE_BEGIN);
+        assert_eq!(
+            sst.find_block_idx(key).unwrap(),
+            full.find_block_idx(key).unwrap()
+        );
+    }
+    assert!(block_cache.contains(BlockKind::FilterPartition, &(1, 0)));
+    let num_absent = (0..NUM_KEYS)
+        .filter(|idx| !sst.may_contain(&absent_key_of(*idx)).unwrap())
+        .count();
+    assert!(num_absent > NUM_KEYS * 9 / 10, "{num_absent}");
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..NUM_KEYS {
+        for ts in (1..=NUM_VERSIONS).rev() {
+            assert_eq!(iter.key(), KeySlice::from_slice(&key_of(idx), ts));
+            assert_eq!(iter.value(), versioned_value_of(idx, ts as usize));
+            iter.next().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_storage_with_partitioned_index() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.partitioned_index = true;
+    options.history_retention = u64::MAX;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut commit_ts = Vec::new();
+    for version in 0..NUM_VERSIONS as usize {
+        for idx in 0..NUM_KEYS {
+            storage
+                .put(&key_of(idx), &versioned_value_of(idx, version))
+                .unwrap();
+        }
+        commit_ts.push(storage.inner.mvcc().latest_commit_ts());
+        storage.force_flush().unwrap();
+    }
+    let check = |storage: &MiniLsm| {
+        for idx in 0..NUM_KEYS {
+            for (version, ts) in commit_ts.iter().enumerate() {
+                assert_eq!(
+                    storage.get_at(&key_of(idx), *ts).unwrap(),
+                    Some(versioned_value_of(idx, version))
+                );
+            }
+            assert_eq!(storage.get(&absent_key_of(idx)).unwrap(), None);
+        }
+        let mut iter = storage
+            .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+            .unwrap();
+        for idx in 0..NUM_KEYS {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(
+                iter.value(),
+                versioned_value_of(idx, NUM_VERSIONS as usize - 1)
+            );
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+    check(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
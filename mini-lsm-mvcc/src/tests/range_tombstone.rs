@@ -0,0 +1,190 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions, iterators::StorageIterator, lsm_storage::MiniLsm,
+    table::SsTableIterator,
+};
+
+use super::common::{key_of, value_of, wal_options, NUM_KEYS};
+
+fn put_keys(storage: &MiniLsm) {
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+}
+
+/// Check that exactly the keys for which `expected` returns true are visible.
+fn check_keys(storage: &MiniLsm, expected: impl Fn(usize) -> bool) {
+    for idx in 0..NUM_KEYS {
+        let value = expected(idx).then(|| value_of(idx));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), value, "key {}", idx);
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in (0..NUM_KEYS).filter(|idx| expected(*idx)) {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+fn num_range_tombstones(storage: &MiniLsm) -> usize {
+    let state = storage.inner.state.read();
+    state
+        .sstables
+        .values()
+        .map(|sst| sst.range_tombstones().len())
+        .sum()
+}
+
+#[test]
+fn test_delete_range() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    put_keys(&storage);
+    storage.force_flush().unwrap();
+    storage.delete_range(&key_of(10), &key_of(20)).unwrap();
+    // Keys written after the tombstone are visible.
+    storage.put(&key_of(15), &value_of(15)).unwrap();
+    let expected = |idx: usize| !(10..20).contains(&idx) || idx == 15;
+    check_keys(&storage, expected);
+    assert!(storage.delete_range(&key_of(20), &key_of(10)).is_err());
+
+    // Recover the tombstone from the WAL.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    check_keys(&storage, expected);
+
+    // Recover the tombstone from the SST.
+    storage.force_flush().unwrap();
+    assert_eq!(num_range_tombstones(&storage), 1);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    check_keys(&storage, expected);
+}
+
+#[test]
+fn test_delete_range_overlapping() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    put_keys(&storage);
+    storage.delete_range(&key_of(10), &key_of(30)).unwrap();
+    storage.force_flush().unwrap();
+    for idx in 20..40 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(25), &key_of(50)).unwrap();
+    storage.force_flush().unwrap();
+    check_keys(&storage, |idx| {
+        !(10..20).contains(&idx) && !(25..50).contains(&idx)
+    });
+    for idx in 0..NUM_KEYS {
+        let value = (!(10..20).contains(&idx)).then(|| value_of(idx));
+        assert_eq!(snapshot.get(&key_of(idx)).unwrap(), value, "key {}", idx);
+    }
+
+    // The fragments are computed once per SST.
+    let state = storage.inner.state.read();
+    for sst in state.sstables.values() {
+        assert!(Arc::ptr_eq(
+            &sst.fragmented_range_tombstones().unwrap(),
+            &sst.fragmented_range_tombstones().unwrap()
+        ));
+    }
+}
+
+#[test]
+fn test_delete_range_column_family() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    let column_family = storage
+        .create_column_family("cf", CompactionOptions::NoCompaction)
+        .unwrap();
+    for idx in 0..NUM_KEYS {
+        storage
+            .put_cf(&column_family, &key_of(idx), &value_of(idx))
+            .unwrap();
+    }
+    storage
+        .delete_range_cf(&column_family, &key_of(10), &key_of(20))
+        .unwrap();
+    let check = |storage: &MiniLsm| {
+        let column_family = storage.column_family("cf").unwrap();
+        for idx in 0..NUM_KEYS {
+            let value = (!(10..20).contains(&idx)).then(|| value_of(idx));
+            let actual = storage.get_cf(&column_family, &key_of(idx)).unwrap();
+            assert_eq!(actual, value, "key {}", idx);
+        }
+        // The default column family is not affected.
+        assert!(storage.get(&key_of(15)).unwrap().is_none());
+    };
+    check(&storage);
+
+    // Recover the tombstone from the shared WAL.
+    storage.close().unwrap();
+    drop((column_family, storage));
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_delete_range_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    put_keys(&storage);
+    let txn = storage.new_txn().unwrap();
+    storage
+        .delete_range(&key_of(0), &key_of(NUM_KEYS / 2))
+        .unwrap();
+    storage.force_flush().unwrap();
+    check_keys(&storage, |idx| idx >= NUM_KEYS / 2);
+    for idx in 0..NUM_KEYS {
+        assert_eq!(txn.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+}
+
+#[test]
+fn test_delete_range_compaction_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    put_keys(&storage);
+    storage.force_flush().unwrap();
+    let txn = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(20), &key_of(60)).unwrap();
+    storage.force_flush().unwrap();
+    let expected = |idx: usize| !(20..60).contains(&idx);
+
+    // The transaction holds the watermark below the tombstone, so nothing is collected.
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_range_tombstones(&storage), 1);
+    check_keys(&storage, expected);
+    assert_eq!(txn.get(&key_of(30)).unwrap(), Some(value_of(30)));
+    drop(txn);
+
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_range_tombstones(&storage), 0);
+    check_keys(&storage, expected);
+    let state = storage.inner.state.read();
+    let num_keys = state
+        .levels
+        .iter()
+        .flat_map(|(_, ssts)| ssts)
+        .map(|id| {
+            let sst = state.sstables[id].clone();
+            let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+            let mut num_keys = 0;
+            while iter.is_valid() {
+                num_keys += 1;
+                iter.next().unwrap();
+            }
+            num_keys
+        })
+        .sum::<usize>();
+    assert_eq!(num_keys, NUM_KEYS - 40);
+}
@@ -0,0 +1,229 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    manifest::{Manifest, ManifestRecord},
+    repair::repair,
+};
+
+use super::common::{key_of, NUM_KEYS};
+
+fn value_of(idx: usize, epoch: usize) -> Bytes {
+    Bytes::from(format!("value_{:05}@{}", idx, epoch))
+}
+
+fn options(compaction_options: CompactionOptions) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    options.enable_wal = true;
+    options
+}
+
+fn remove_manifest(dir: &Path) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        if Manifest::is_manifest_file(entry.file_name().to_str().unwrap()) {
+            std::fs::remove_file(entry.path()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_repair_lost_manifest() {
+    let dir = tempdir().unwrap();
+    let options = options(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // Overwrite and delete keys across several SSTs, and leave the last epoch in the WAL.
+    for epoch in 0..3 {
+        for idx in 0..NUM_KEYS {
+            storage.put(&key_of(idx), &value_of(idx, epoch)).unwrap();
+        }
+        for idx in (epoch..NUM_KEYS).step_by(10) {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+        if epoch < 2 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    remove_manifest(dir.path());
+    let report = repair(&dir, &options).unwrap();
+    assert_eq!(report.ssts.len(), 3);
+    assert_eq!(report.replayed_wals.len(), 1);
+    assert!(report.quarantined_ssts.is_empty());
+    assert!(report.corrupted_wals.is_empty());
+
+    let check = |storage: &MiniLsm| {
+        for idx in 0..NUM_KEYS {
+            let expected = (idx % 10 != 2).then(|| value_of(idx, 2));
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+        }
+    };
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage);
+    // The repaired database accepts new writes and can be reopened.
+    storage.put(b"new", b"value").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+    assert_eq!(
+        storage.get(b"new").unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+}
+
+#[test]
+fn test_repair_corrupted_files() {
+    let dir = tempdir().unwrap();
+    let options = options(CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width: None,
+    }));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        if idx % 50 == 49 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.put(b"wal", b"wal").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Lose the second SST and damage the manifest and the end of the WAL.
+    let mut ssts = Vec::new();
+    let mut wals = Vec::new();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("sst") => ssts.push(path),
+            Some("wal") => wals.push(path),
+            _ => {}
+        }
+    }
+    ssts.sort();
+    assert_eq!(ssts.len(), 2);
+    assert_eq!(wals.len(), 1);
+    std::fs::write(&ssts[1], b"garbage").unwrap();
+    let current = std::fs::read_to_string(dir.path().join("CURRENT")).unwrap();
+    std::fs::write(dir.path().join(current.trim()), b"garbage").unwrap();
+    let mut wal = OpenOptions::new().append(true).open(&wals[0]).unwrap();
+    wal.write_all(b"garbage").unwrap();
+    drop(wal);
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    let report = repair(&dir, &options).unwrap();
+    assert_eq!(report.ssts.len(), 2);
+    assert_eq!(report.quarantined_ssts.len(), 1);
+    assert_eq!(report.corrupted_wals.len(), 1);
+    assert!(!ssts[1].exists());
+    assert!(ssts[1].with_extension("sst.corrupt").exists());
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..NUM_KEYS {
+        let expected = (idx < 50).then(|| value_of(idx, 0));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+    assert_eq!(
+        storage.get(b"wal").unwrap(),
+        Some(Bytes::from_static(b"wal"))
+    );
+}
+
+#[test]
+fn test_repair_assigns_levels_and_skips_removed_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(CompactionOptions::NoCompaction)).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in 0..10 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    // Keep the inputs of the full compaction, as if the process stopped before deleting them.
+    let inputs = storage.inner.state.read().l0_sstables.clone();
+    let copies = inputs
+        .iter()
+        .map(|id| std::fs::read(storage.inner.path_of_sst(*id)).unwrap())
+        .collect::<Vec<_>>();
+    storage.force_full_compaction().unwrap();
+    let compacted = storage.inner.state.read().levels[0].1.clone();
+    // An SST with keys after the compacted ones, and another overlapping them.
+    for idx in NUM_KEYS..NUM_KEYS * 2 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in 50..60 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let flushed = storage.inner.state.read().l0_sstables.clone();
+    storage.close().unwrap();
+    drop(storage);
+
+    for (id, data) in inputs.iter().zip(copies) {
+        std::fs::write(dir.path().join(format!("{:05}.sst", id)), data).unwrap();
+    }
+    let current = std::fs::read_to_string(dir.path().join("CURRENT")).unwrap();
+    let mut manifest = OpenOptions::new()
+        .append(true)
+        .open(dir.path().join(current.trim()))
+        .unwrap();
+    manifest.write_all(b"garbage").unwrap();
+    drop(manifest);
+
+    let options = options(CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    }));
+    let report = repair(&dir, &options).unwrap();
+    let mut removed = inputs.clone();
+    removed.sort();
+    assert_eq!(report.removed_ssts, removed);
+    for id in &inputs {
+        assert!(!dir.path().join(format!("{:05}.sst", id)).exists());
+    }
+    // The older SSTs do not overlap and go to the bottom level, with the newer SST above them.
+    let (_, records) = Manifest::recover(&dir).unwrap();
+    let [ManifestRecord::Snapshot {
+        l0_sstables,
+        levels,
+        ..
+    }] = &records[..]
+    else {
+        panic!("the repaired manifest is not a single snapshot");
+    };
+    assert!(l0_sstables.is_empty());
+    assert!(levels[0].1.is_empty());
+    assert_eq!(levels[1].1, vec![flushed[0]]);
+    let mut bottom = compacted.clone();
+    bottom.push(flushed[1]);
+    assert_eq!(levels[2].1, bottom);
+
+    // The deleted keys in the removed SSTs are not brought back.
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..NUM_KEYS * 2 {
+        let expected = match idx {
+            0..10 => None,
+            50..60 => Some(value_of(idx, 1)),
+            _ => Some(value_of(idx, 0)),
+        };
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+}
@@ -0,0 +1,230 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{SsTableBuilder, SsTableIterator},
+};
+
+use super::common::{key_of, versioned_value_of};
+
+fn collect(mut iter: impl for<'a> StorageIterator<KeyType<'a> = &'a [u8]>) -> Vec<(Bytes, Bytes)> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn collect_rev(
+    mut iter: impl for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+) -> Vec<(Bytes, Bytes)> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.prev().unwrap();
+    }
+    result.reverse();
+    result
+}
+
+/// Walk over `expected` from `pos` moving three steps in one direction and two steps back, so
+/// that every key is reached right after a change of direction.
+fn check_alternating(
+    mut iter: impl for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+    expected: &[(Bytes, Bytes)],
+    mut pos: isize,
+    forward: bool,
+) {
+    for step in 0.. {
+        let (key, value) = &expected[pos as usize];
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value(), value);
+        if (step % 5 < 3) == forward {
+            iter.next().unwrap();
+            pos += 1;
+        } else {
+            iter.prev().unwrap();
+            pos -= 1;
+        }
+        if pos < 0 || pos as usize >= expected.len() {
+            assert!(!iter.is_valid());
+            break;
+        }
+    }
+}
+
+#[test]
+fn test_sst_iterator_prev() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx * 2)),
+            &versioned_value_of(idx * 2, 0),
+        );
+    }
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst.clone()).unwrap();
+    for idx in (0..100).rev() {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx * 2));
+        assert_eq!(iter.value(), versioned_value_of(idx * 2, 0));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Move in both directions across block boundaries.
+    let key = key_of(101);
+    let mut iter = SsTableIterator::create_and_seek_for_prev(
+        sst.clone(),
+        KeySlice::for_testing_from_slice_no_ts(&key),
+    )
+    .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(100));
+    for idx in (0..50).rev() {
+        iter.prev().unwrap();
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx * 2));
+    }
+    for idx in 1..100 {
+        iter.next().unwrap();
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx * 2));
+    }
+    let key = key_of(0);
+    let iter = SsTableIterator::create_and_seek_for_prev(
+        sst,
+        KeySlice::for_testing_from_slice_no_ts(&key[..key.len() - 1]),
+    )
+    .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_scan_rev() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    // Spread versions of the keys over the levels, L0, and the memtables.
+    for version in 0..4 {
+        for idx in (version..200).step_by(version + 1) {
+            storage
+                .put(&key_of(idx), &versioned_value_of(idx, version))
+                .unwrap();
+        }
+        match version {
+            0 => {
+                storage.force_flush().unwrap();
+                storage.force_full_compaction().unwrap();
+            }
+            1 => storage.force_flush().unwrap(),
+            2 => storage
+                .inner
+                .force_freeze_memtable(&storage.inner.state_lock.lock())
+                .unwrap(),
+            _ => {}
+        }
+    }
+    let txn = storage.new_txn().unwrap();
+    for idx in (0..200).step_by(7) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.delete_range(&key_of(50), &key_of(60)).unwrap();
+
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(key_of(20)), Bound::Included(key_of(140))),
+        (Bound::Excluded(key_of(21)), Bound::Excluded(key_of(141))),
+        (Bound::Unbounded, Bound::Excluded(key_of(55))),
+    ];
+    for (lower, upper) in &bounds {
+        let lower = lower.as_ref().map(|key| &key[..]);
+        let upper = upper.as_ref().map(|key| &key[..]);
+        let expected = collect(storage.scan(lower, upper).unwrap());
+        assert!(!expected.is_empty());
+        assert_eq!(
+            collect_rev(storage.scan_rev(lower, upper).unwrap()),
+            expected
+        );
+        // The transaction reads at a timestamp before the deletes.
+        let expected = collect(txn.scan(lower, upper).unwrap());
+        assert_eq!(collect_rev(txn.scan_rev(lower, upper).unwrap()), expected);
+    }
+
+    // Writes of the transaction are merged into the reverse scan.
+    txn.put(&key_of(300), b"local");
+    txn.delete(&key_of(199));
+    let mut iter = txn.scan_rev(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(iter.key(), key_of(300));
+    assert_eq!(iter.value(), b"local");
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(198));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(300));
+}
+
+#[test]
+fn test_scan_alternating_directions() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for version in 0..3 {
+        for idx in (version..200).step_by(version + 1) {
+            storage
+                .put(&key_of(idx), &versioned_value_of(idx, version))
+                .unwrap();
+        }
+        match version {
+            0 => {
+                storage.force_flush().unwrap();
+                storage.force_full_compaction().unwrap();
+            }
+            1 => storage.force_flush().unwrap(),
+            _ => {}
+        }
+    }
+    for idx in (0..200).step_by(7) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.delete_range(&key_of(50), &key_of(60)).unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(101), b"local");
+    txn.put(&key_of(300), b"local");
+    txn.delete(&key_of(103));
+
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Excluded(key_of(21)), Bound::Included(key_of(141))),
+    ];
+    for (lower, upper) in &bounds {
+        let lower = lower.as_ref().map(|key| &key[..]);
+        let upper = upper.as_ref().map(|key| &key[..]);
+        let expected = collect(storage.scan(lower, upper).unwrap());
+        let last = expected.len() as isize - 1;
+        check_alternating(storage.scan(lower, upper).unwrap(), &expected, 0, true);
+        check_alternating(
+            storage.scan_rev(lower, upper).unwrap(),
+            &expected,
+            last,
+            false,
+        );
+
+        let expected = collect(txn.scan(lower, upper).unwrap());
+        let last = expected.len() as isize - 1;
+        check_alternating(txn.scan(lower, upper).unwrap(), &expected, 0, true);
+        check_alternating(txn.scan_rev(lower, upper).unwrap(), &expected, last, false);
+    }
+}
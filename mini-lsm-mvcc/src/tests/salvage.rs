@@ -0,0 +1,254 @@
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::{key_of, value_of, wal_options, NUM_KEYS};
+
+fn options() -> LsmStorageOptions {
+    let mut options = wal_options();
+    options.block_size = 64;
+    options
+}
+
+/// Files in `dir` with the extension `ext`, ordered by id.
+fn files_with_ext(dir: &Path, ext: &str) -> Vec<(usize, PathBuf)> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == ext))
+        .map(|path| {
+            let id = path.file_stem().unwrap().to_str().unwrap().parse().unwrap();
+            (id, path)
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn append_garbage(path: &Path) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(b"garbage").unwrap();
+}
+
+fn flip_byte(data: &mut [u8], offset: usize) {
+    data[offset] ^= 0xff;
+}
+
+/// The offset of the bloom filter in an SST, found by walking back through the sections after it.
+fn bloom_offset(data: &[u8]) -> usize {
+    // Skip the footer, then the range tombstones and value log references, and the index type.
+    let mut end = data.len() - 12;
+    for _ in 0..2 {
+        end = (&data[end - 4..]).get_u32() as usize;
+    }
+    end -= 1;
+    (&data[end - 4..]).get_u32() as usize
+}
+
+#[test]
+fn test_salvage_corrupted_files() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % 50 == 49 {
+            storage.force_flush().unwrap();
+        }
+    }
+    for idx in NUM_KEYS..NUM_KEYS + 10 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // Damage a data block and the bloom filter of the first SST, and the whole second SST.
+    let ssts = files_with_ext(dir.path(), "sst");
+    assert_eq!(ssts.len(), 2);
+    let (damaged_sst, path) = &ssts[0];
+    let mut data = std::fs::read(path).unwrap();
+    flip_byte(&mut data, 0);
+    let bloom_offset = bloom_offset(&data);
+    flip_byte(&mut data, bloom_offset);
+    std::fs::write(path, data).unwrap();
+    let (lost_sst, path) = &ssts[1];
+    std::fs::write(path, b"garbage").unwrap();
+    // Leave incomplete records at the end of the WAL and the manifest.
+    let (wal, path) = files_with_ext(dir.path(), "wal").pop().unwrap();
+    append_garbage(&path);
+    let current = std::fs::read_to_string(dir.path().join("CURRENT")).unwrap();
+    append_garbage(&dir.path().join(current.trim()));
+
+    assert!(MiniLsm::open(&dir, options()).is_err());
+    let (storage, report) = MiniLsm::open_read_only_salvage(&dir, options()).unwrap();
+    assert!(report.manifest_truncated);
+    assert_eq!(
+        report
+            .skipped_ssts
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>(),
+        vec![*lost_sst]
+    );
+    // Data blocks are only checked when they are read.
+    assert!(report.corrupted_blocks.is_empty());
+    assert_eq!(report.corrupted_blooms, vec![*damaged_sst]);
+    assert_eq!(report.corrupted_wals, vec![wal]);
+    assert!(!report.is_clean());
+
+    // The first block of the first SST and the second SST are lost, everything else is readable.
+    let mut readable = BTreeSet::new();
+    for idx in 0..NUM_KEYS + 10 {
+        if let Some(value) = storage.get(&key_of(idx)).unwrap() {
+            assert_eq!(value, value_of(idx));
+            readable.insert(idx);
+        }
+    }
+    assert!(!readable.contains(&0));
+    assert!(readable.contains(&49));
+    assert!((50..NUM_KEYS).all(|idx| !readable.contains(&idx)));
+    assert!((NUM_KEYS..NUM_KEYS + 10).all(|idx| readable.contains(&idx)));
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut scanned = BTreeSet::new();
+    while iter.is_valid() {
+        scanned.insert(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        scanned,
+        readable.iter().map(|idx| key_of(*idx).to_vec()).collect()
+    );
+    let report = storage.salvage_report().unwrap();
+    assert_eq!(report.corrupted_blocks, vec![(*damaged_sst, 0)]);
+}
+
+#[test]
+fn test_salvage_skips_corrupted_wal_batches() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // Every put is a batch of the same size, so this damages exactly one batch in the middle.
+    let (wal, path) = files_with_ext(dir.path(), "wal").pop().unwrap();
+    let mut data = std::fs::read(&path).unwrap();
+    let len = data.len();
+    flip_byte(&mut data, len / 2);
+    std::fs::write(&path, data).unwrap();
+
+    let (storage, report) = MiniLsm::open_read_only_salvage(&dir, options()).unwrap();
+    assert_eq!(report.corrupted_wals, vec![wal]);
+    let lost = (0..NUM_KEYS)
+        .filter(|idx| storage.get(&key_of(*idx)).unwrap().is_none())
+        .collect::<Vec<_>>();
+    assert_eq!(lost.len(), 1);
+    assert!(lost[0] > 0 && lost[0] < NUM_KEYS - 1);
+}
+
+#[test]
+fn test_salvage_corrupted_value_log() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.value_separation_threshold = Some(64);
+    let large_value_of = |idx| Bytes::from(format!("value_{:05}_{}", idx, "x".repeat(200)));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &large_value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Damage one record in the middle of the value log, which is found when it is read.
+    let (vlog, path) = files_with_ext(dir.path(), "vlog").pop().unwrap();
+    let mut data = std::fs::read(&path).unwrap();
+    let len = data.len();
+    flip_byte(&mut data, len / 2);
+    std::fs::write(&path, data).unwrap();
+    let (storage, report) = MiniLsm::open_read_only_salvage(&dir, options.clone()).unwrap();
+    assert!(report.is_clean());
+    let mut failed = 0;
+    for idx in 0..NUM_KEYS {
+        match storage.get(&key_of(idx)) {
+            Ok(value) => assert_eq!(value, Some(large_value_of(idx))),
+            Err(_) => failed += 1,
+        }
+    }
+    assert_eq!(failed, 1);
+    let report = storage.salvage_report().unwrap();
+    assert_eq!(
+        report
+            .corrupted_value_logs
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>(),
+        vec![vlog]
+    );
+    drop(storage);
+
+    // A missing value log is reported on open, and only the values in it cannot be read.
+    std::fs::remove_file(&path).unwrap();
+    let (storage, report) = MiniLsm::open_read_only_salvage(&dir, options).unwrap();
+    assert!(report.skipped_ssts.is_empty());
+    assert_eq!(report.corrupted_value_logs.len(), 1);
+    assert_eq!(report.corrupted_value_logs[0].0, vlog);
+    assert!(storage.get(&key_of(0)).is_err());
+}
+
+#[test]
+fn test_salvage_read_only() {
+    let dir = tempdir().unwrap();
+    assert!(MiniLsm::open_read_only_salvage(dir.path().join("missing"), options()).is_err());
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx == NUM_KEYS / 2 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let list_files = || {
+        std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.file_name(), entry.metadata().unwrap().len())
+            })
+            .collect::<BTreeSet<_>>()
+    };
+    let files = list_files();
+    let (storage, report) = MiniLsm::open_read_only_salvage(&dir, options()).unwrap();
+    assert!(report.is_clean());
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+    assert!(storage.put(b"key", b"value").is_err());
+    assert!(storage.delete(&key_of(0)).is_err());
+    assert!(storage.delete_range(&key_of(0), &key_of(10)).is_err());
+    assert!(storage.force_full_compaction().is_err());
+    assert!(storage
+        .create_checkpoint(dir.path().join("checkpoint"))
+        .is_err());
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"key", b"value");
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(value_of(0)));
+    storage.close().unwrap();
+    drop(storage);
+    assert_eq!(list_files(), files);
+}
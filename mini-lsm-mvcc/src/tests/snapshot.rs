@@ -0,0 +1,88 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::{key_of, versioned_value_of, NUM_KEYS};
+
+#[test]
+fn test_snapshot_read() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage
+            .put(&key_of(idx), &versioned_value_of(idx, 0))
+            .unwrap();
+    }
+    let snapshot = storage.snapshot();
+    for idx in 0..NUM_KEYS {
+        if idx % 2 == 0 {
+            storage
+                .put(&key_of(idx), &versioned_value_of(idx, 1))
+                .unwrap();
+        } else {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+    }
+    storage
+        .put(&key_of(NUM_KEYS), &versioned_value_of(NUM_KEYS, 1))
+        .unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    for idx in 0..NUM_KEYS {
+        assert_eq!(
+            snapshot.get(&key_of(idx)).unwrap(),
+            Some(versioned_value_of(idx, 0))
+        );
+    }
+    assert_eq!(snapshot.get(&key_of(NUM_KEYS)).unwrap(), None);
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..NUM_KEYS {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), versioned_value_of(idx, 0));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let iter = snapshot
+        .scan_rev(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.key(), key_of(NUM_KEYS - 1));
+
+    // A new snapshot reads the latest data.
+    let latest = storage.snapshot();
+    assert!(latest.read_ts() > snapshot.read_ts());
+    assert_eq!(
+        latest.get(&key_of(0)).unwrap(),
+        Some(versioned_value_of(0, 1))
+    );
+    assert_eq!(latest.get(&key_of(1)).unwrap(), None);
+}
+
+#[test]
+fn test_snapshot_watermark() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    let second = storage.snapshot();
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.inner.mvcc().watermark(), snapshot.read_ts());
+    // Snapshots do not keep committed transaction data around.
+    assert!(storage.inner.mvcc().committed_txns.lock().is_empty());
+
+    drop(snapshot);
+    assert_eq!(storage.inner.mvcc().watermark(), second.read_ts());
+    drop(second);
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+}
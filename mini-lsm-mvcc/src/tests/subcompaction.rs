@@ -0,0 +1,132 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::key_of;
+
+fn value_of(idx: usize, version: usize) -> Bytes {
+    Bytes::from(format!(
+        "value_{:05}_{}_{}",
+        idx,
+        version,
+        "x".repeat(idx % 100)
+    ))
+}
+
+const NUM_KEYS: usize = 2000;
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 512;
+    options.target_sst_size = 8192;
+    options.max_subcompactions = 4;
+    options
+}
+
+fn check_full_compaction(options: LsmStorageOptions) {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    // Keep the first version of all keys visible to the snapshot.
+    let snapshot = storage.new_txn().unwrap();
+    for idx in (0..NUM_KEYS).step_by(3) {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    for idx in (0..NUM_KEYS).step_by(7) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        let ssts = &state.levels[0].1;
+        assert!(ssts.len() > 1);
+        // No key is split across SSTs.
+        for pair in ssts.windows(2) {
+            assert!(
+                state.sstables[&pair[0]].last_key().key_ref()
+                    < state.sstables[&pair[1]].first_key().key_ref()
+            );
+        }
+    }
+    let expected = |idx: usize| {
+        if idx.is_multiple_of(7) {
+            None
+        } else if idx.is_multiple_of(3) {
+            Some(value_of(idx, 1))
+        } else {
+            Some(value_of(idx, 0))
+        }
+    };
+    let check = |storage: &MiniLsm| {
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        for idx in 0..NUM_KEYS {
+            if let Some(value) = expected(idx) {
+                assert_eq!(iter.key(), key_of(idx));
+                assert_eq!(iter.value(), value);
+                iter.next().unwrap();
+            }
+        }
+        assert!(!iter.is_valid());
+    };
+    check(&storage);
+    for idx in 0..NUM_KEYS {
+        assert_eq!(snapshot.get(&key_of(idx)).unwrap(), Some(value_of(idx, 0)));
+    }
+    drop(snapshot);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_parallel_full_compaction() {
+    check_full_compaction(options());
+}
+
+#[test]
+fn test_parallel_full_compaction_with_value_log() {
+    let mut options = options();
+    options.value_separation_threshold = Some(64);
+    check_full_compaction(options);
+}
+
+#[test]
+fn test_parallel_full_compaction_of_range_tombstones() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    // The SSTs only hold range tombstones and have no data blocks to split the compaction at.
+    for idx in 0..NUM_KEYS {
+        storage
+            .delete_range(&key_of(idx * 2), &key_of(idx * 2 + 1))
+            .unwrap();
+    }
+    while {
+        let state = storage.inner.state.read();
+        !state.memtable.is_empty() || !state.imm_memtables.is_empty()
+    } {
+        storage.force_flush().unwrap();
+    }
+    {
+        let state = storage.inner.state.read();
+        let size = state.sst_bytes(&state.l0_sstables);
+        assert!(size > 2 * options().target_sst_size as u64);
+    }
+    storage.force_full_compaction().unwrap();
+    let iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert!(!iter.is_valid());
+}
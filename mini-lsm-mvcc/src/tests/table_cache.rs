@@ -0,0 +1,111 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+};
+
+use super::common::{key_of, value_of};
+
+const NUM_KEYS: usize = 400;
+const NUM_SSTS: usize = 8;
+
+fn options(max_open_files: usize) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.max_open_files = Some(max_open_files);
+    options
+}
+
+fn num_open_files(storage: &MiniLsm) -> usize {
+    storage.inner.table_cache.as_ref().unwrap().num_open_files()
+}
+
+fn check(storage: &MiniLsm) {
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..NUM_KEYS {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_table_cache_max_open_files() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(3)).unwrap();
+    // The keys are written in order, so each SST holds the next range of keys and a scan reads
+    // all of them.
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % NUM_SSTS == NUM_SSTS - 1 {
+            storage.force_flush().unwrap();
+        }
+    }
+    check(&storage);
+    assert!(num_open_files(&storage) <= 3);
+    storage.close().unwrap();
+    drop(storage);
+
+    // The SSTs are only opened when they are read, from the metadata in the manifest.
+    let storage = MiniLsm::open(&dir, options(3)).unwrap();
+    assert_eq!(num_open_files(&storage), 0);
+    check(&storage);
+    assert!(num_open_files(&storage) <= 3);
+}
+
+#[test]
+fn test_table_cache_closes_removed_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(100)).unwrap();
+    for sst in 0..NUM_SSTS {
+        for idx in (sst..NUM_KEYS).step_by(NUM_SSTS) {
+            storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    check(&storage);
+    assert_eq!(num_open_files(&storage), NUM_SSTS);
+    storage.force_full_compaction().unwrap();
+    let num_ssts = storage.inner.state.read().sstables.len();
+    assert!(num_ssts < NUM_SSTS);
+    // The files of the compacted SSTs are closed when the SSTs are removed.
+    check(&storage);
+    assert_eq!(num_open_files(&storage), num_ssts);
+}
+
+#[test]
+fn test_table_cache_deferred_sst_deletion() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(100)).unwrap();
+    for sst in 0..NUM_SSTS {
+        for idx in (sst..NUM_KEYS).step_by(NUM_SSTS) {
+            storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    storage.force_full_compaction().unwrap();
+
+    // The scan still reads the compacted SSTs through the table cache.
+    for id in &l0_sstables {
+        assert!(LsmStorageInner::path_of_sst_static(dir.path(), *id).exists());
+    }
+    for idx in 0..NUM_KEYS {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    drop(iter);
+    storage.inner.obsolete_files.purge().unwrap();
+    for id in &l0_sstables {
+        assert!(!LsmStorageInner::path_of_sst_static(dir.path(), *id).exists());
+    }
+}
@@ -0,0 +1,125 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::SsTableIterator,
+};
+
+use super::common::{key_of, versioned_value_of};
+
+const NUM_KEYS: usize = 10;
+const NUM_VERSIONS: usize = 5;
+
+fn options(history_retention: u64) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.history_retention = history_retention;
+    options
+}
+
+/// Write `NUM_VERSIONS` versions of all keys, one commit per key, and return the commit timestamp
+/// of each version.
+fn put_versions(storage: &MiniLsm) -> Vec<u64> {
+    let mut commit_ts = Vec::new();
+    for version in 0..NUM_VERSIONS {
+        for idx in 0..NUM_KEYS {
+            storage
+                .put(&key_of(idx), &versioned_value_of(idx, version))
+                .unwrap();
+        }
+        commit_ts.push(storage.inner.mvcc().latest_commit_ts());
+    }
+    commit_ts
+}
+
+#[test]
+fn test_time_travel_read() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(1000)).unwrap();
+    let commit_ts = put_versions(&storage);
+    storage.delete(&key_of(0)).unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    for (version, ts) in commit_ts.iter().enumerate() {
+        for idx in 0..NUM_KEYS {
+            assert_eq!(
+                storage.get_at(&key_of(idx), *ts).unwrap(),
+                Some(versioned_value_of(idx, version))
+            );
+        }
+        let mut iter = storage
+            .scan_at(Bound::Unbounded, Bound::Unbounded, *ts)
+            .unwrap();
+        for idx in 0..NUM_KEYS {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), versioned_value_of(idx, version));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+    // Timestamps between the versions see the keys committed so far.
+    let ts = commit_ts[0] + 2;
+    assert_eq!(
+        storage.get_at(&key_of(1), ts).unwrap(),
+        Some(versioned_value_of(1, 1))
+    );
+    assert_eq!(
+        storage.get_at(&key_of(2), ts).unwrap(),
+        Some(versioned_value_of(2, 0))
+    );
+
+    let latest = storage.inner.mvcc().latest_commit_ts();
+    assert_eq!(storage.get_at(&key_of(0), latest).unwrap(), None);
+    assert!(storage.get_at(&key_of(0), latest + 1).is_err());
+}
+
+#[test]
+fn test_history_retention() {
+    let dir = tempdir().unwrap();
+    let retention = NUM_KEYS as u64 * 2;
+    let storage = MiniLsm::open(&dir, options(retention)).unwrap();
+    let commit_ts = put_versions(&storage);
+    let latest = storage.inner.mvcc().latest_commit_ts();
+    assert_eq!(storage.inner.mvcc().watermark(), latest - retention);
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    // The last versions within the retention window are kept by compaction.
+    let retained = &commit_ts[NUM_VERSIONS - 3..];
+    for (version, ts) in (NUM_VERSIONS - 3..).zip(retained) {
+        for idx in 0..NUM_KEYS {
+            assert_eq!(
+                storage.get_at(&key_of(idx), *ts).unwrap(),
+                Some(versioned_value_of(idx, version))
+            );
+        }
+    }
+    assert!(storage.get_at(&key_of(0), commit_ts[0]).is_err());
+    assert!(storage
+        .scan_at(Bound::Unbounded, Bound::Unbounded, commit_ts[0])
+        .is_err());
+
+    // Without retention, compaction only keeps the latest version.
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(0)).unwrap();
+    let commit_ts = put_versions(&storage);
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(storage
+        .get_at(&key_of(0), commit_ts[NUM_VERSIONS - 2])
+        .is_err());
+    let state = storage.inner.state.read();
+    assert_eq!(state.levels[0].1.len(), 1);
+    let sst = state.sstables[&state.levels[0].1[0]].clone();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut num_versions = 0;
+    while iter.is_valid() {
+        num_versions += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(num_versions, NUM_KEYS);
+}
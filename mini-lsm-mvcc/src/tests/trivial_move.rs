@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::{key_of, value_of};
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ))
+}
+
+/// Write `keys` and flush them into one L0 SST, returning its id.
+fn flush_keys(storage: &MiniLsm, keys: impl Iterator<Item = usize>) -> usize {
+    for idx in keys {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.inner.state.read().l0_sstables[0]
+}
+
+fn wait_for_l0_compaction(storage: &MiniLsm) {
+    for _ in 0..100 {
+        if storage.inner.state.read().l0_sstables.is_empty() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("L0 SSTs are not compacted");
+}
+
+fn sst_ids_in_levels(storage: &MiniLsm) -> Vec<Vec<usize>> {
+    let state = storage.inner.state.read();
+    state.levels.iter().map(|(_, ssts)| ssts.clone()).collect()
+}
+
+#[test]
+fn test_trivial_move_sequential_inserts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let mut flushed = HashSet::new();
+    for batch in 0..6 {
+        flushed.insert(flush_keys(&storage, batch * 100..(batch + 1) * 100));
+        if batch % 2 == 1 {
+            wait_for_l0_compaction(&storage);
+        }
+    }
+    let levels = sst_ids_in_levels(&storage);
+    // No SST is rewritten, so all of them are the flushed ones.
+    let ssts = levels.iter().flatten().copied().collect::<HashSet<_>>();
+    assert_eq!(ssts, flushed);
+    for idx in 0..600 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(sst_ids_in_levels(&storage), levels);
+    for idx in 0..600 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+}
+
+#[test]
+fn test_no_trivial_move_for_overlapping_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let mut flushed = HashSet::new();
+    flushed.insert(flush_keys(&storage, 0..100));
+    flushed.insert(flush_keys(&storage, 50..150));
+    wait_for_l0_compaction(&storage);
+    let ssts = sst_ids_in_levels(&storage)
+        .into_iter()
+        .flatten()
+        .collect::<HashSet<_>>();
+    assert!(ssts.is_disjoint(&flushed));
+    for idx in 0..150 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx)));
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::common::key_of;
+
+fn value_of(idx: usize, version: usize) -> Bytes {
+    // Odd keys have values large enough to be separated.
+    let padding = if idx % 2 == 1 { 100 } else { 4 };
+    Bytes::from(format!(
+        "value_{:05}_{}_{}",
+        idx,
+        version,
+        "x".repeat(padding)
+    ))
+}
+
+fn value_log_ids(path: &Path) -> Vec<usize> {
+    let mut ids = std::fs::read_dir(path)
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            name.strip_suffix(".vlog").map(|id| id.parse().unwrap())
+        })
+        .collect::<Vec<usize>>();
+    ids.sort();
+    ids
+}
+
+const NUM_KEYS: usize = 500;
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_separation_threshold = Some(64);
+    options
+}
+
+#[test]
+fn test_value_separation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.delete(&key_of(1)).unwrap();
+    storage.force_flush().unwrap();
+    {
+        let state = storage.inner.state.read();
+        let sst = &state.sstables[&state.l0_sstables[0]];
+        assert_eq!(sst.value_log_refs().len(), 1);
+        assert_eq!(value_log_ids(dir.path()), vec![sst.sst_id()]);
+    }
+    let check = |storage: &MiniLsm| {
+        assert_eq!(storage.get(&key_of(1)).unwrap(), None);
+        for idx in 2..NUM_KEYS {
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx, 0)));
+        }
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        for idx in (0..NUM_KEYS).filter(|idx| *idx != 1) {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value_of(idx, 0));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    // Overwrite half of the keys, so that half of the first value log becomes garbage after
+    // compaction.
+    for idx in 0..NUM_KEYS / 2 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let value_logs = value_log_ids(dir.path());
+    assert_eq!(value_logs.len(), 2);
+
+    storage.force_full_compaction().unwrap();
+    assert_eq!(value_log_ids(dir.path()), value_logs);
+    // The second value log has no garbage and is kept.
+    storage.force_value_log_gc(0.3).unwrap();
+    let after_gc = value_log_ids(dir.path());
+    assert_eq!(after_gc.len(), 2);
+    assert!(!after_gc.contains(&value_logs[0]));
+    assert!(after_gc.contains(&value_logs[1]));
+    // Nothing left to collect.
+    storage.force_value_log_gc(0.3).unwrap();
+    assert_eq!(value_log_ids(dir.path()), after_gc);
+
+    let expected = |idx: usize| value_of(idx, if idx < NUM_KEYS / 2 { 1 } else { 0 });
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(expected(idx)));
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..NUM_KEYS {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(expected(idx)));
+    }
+}
+
+#[test]
+fn test_value_log_read_lazily() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let mut txns = Vec::new();
+    for version in 0..3 {
+        for idx in 0..NUM_KEYS {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        txns.push(storage.new_txn().unwrap());
+    }
+
+    // Corrupt the value log of the oldest versions.
+    let ids = value_log_ids(dir.path());
+    assert_eq!(ids.len(), 3);
+    let path = dir.path().join(format!("{:05}.vlog", ids[0]));
+    let mut data = std::fs::read(&path).unwrap();
+    data[12..].fill(0);
+    std::fs::write(&path, data).unwrap();
+
+    // Readers of newer versions skip the corrupted values without reading them.
+    for (version, txn) in txns.iter().enumerate().skip(1) {
+        for idx in 0..NUM_KEYS {
+            assert_eq!(txn.get(&key_of(idx)).unwrap(), Some(value_of(idx, version)));
+        }
+        let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        for idx in 0..NUM_KEYS {
+            assert_eq!(iter.value(), value_of(idx, version));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+
+    // Reading a corrupted value fails instead of yielding the key as deleted.
+    assert_eq!(txns[0].get(&key_of(0)).unwrap(), Some(value_of(0, 0)));
+    assert!(txns[0].get(&key_of(1)).is_err());
+    let mut iter = txns[0].scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(iter.key(), key_of(0));
+    assert!(iter.next().is_err());
+}
@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+    wal::WalRecoveryMode,
+};
+
+fn options(mode: WalRecoveryMode) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_recovery_mode = mode;
+    options
+}
+
+fn open(path: &Path, mode: WalRecoveryMode) -> Arc<LsmStorageInner> {
+    Arc::new(LsmStorageInner::open(path, options(mode)).unwrap())
+}
+
+fn get(storage: &Arc<LsmStorageInner>, key: &[u8]) -> Option<Bytes> {
+    storage.get(key).unwrap()
+}
+
+/// Write `key1`, a corrupted `key2` and `key4` to the first WAL, and `key3` to the second one.
+fn create_corrupted_wals(path: &Path) -> PathBuf {
+    let storage = open(path, WalRecoveryMode::AbsoluteConsistency);
+    let wal_path = storage.path_of_wal(storage.state.read().memtable.id());
+    storage.put(b"key1", b"value1").unwrap();
+    storage.put(b"key2", b"value2").unwrap();
+    storage.put(b"key4", b"value4").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"key3", b"value3").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let mut data = std::fs::read(&wal_path).unwrap();
+    let pos = data.windows(6).position(|x| x == b"value2").unwrap();
+    data[pos] ^= 0xff;
+    std::fs::write(&wal_path, data).unwrap();
+    wal_path
+}
+
+#[test]
+fn test_wal_recovery_torn_tail() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path(), WalRecoveryMode::AbsoluteConsistency);
+    let wal_path = storage.path_of_wal(storage.state.read().memtable.id());
+    storage.put(b"key1", b"value1").unwrap();
+    storage.put(b"key2", b"value2").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    // A record header claiming more bytes than were written before the crash.
+    let clean_len = std::fs::metadata(&wal_path).unwrap().len();
+    let mut data = std::fs::read(&wal_path).unwrap();
+    data.extend_from_slice(&100u32.to_be_bytes());
+    data.extend_from_slice(b"torn");
+    std::fs::write(&wal_path, data).unwrap();
+    assert!(
+        LsmStorageInner::open(dir.path(), options(WalRecoveryMode::AbsoluteConsistency)).is_err()
+    );
+    let storage = open(dir.path(), WalRecoveryMode::TolerateCorruptedTailRecords);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), clean_len);
+    assert_eq!(get(&storage, b"key2"), Some(Bytes::from_static(b"value2")));
+
+    // Writes after recovery are appended at the clean boundary.
+    storage.put(b"key3", b"value3").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = open(dir.path(), WalRecoveryMode::AbsoluteConsistency);
+    assert_eq!(get(&storage, b"key1"), Some(Bytes::from_static(b"value1")));
+    assert_eq!(get(&storage, b"key3"), Some(Bytes::from_static(b"value3")));
+}
+
+#[test]
+fn test_wal_recovery_modes() {
+    let dir = tempdir().unwrap();
+    for mode in [
+        WalRecoveryMode::AbsoluteConsistency,
+        WalRecoveryMode::TolerateCorruptedTailRecords,
+    ] {
+        let path = dir.path().join(format!("{:?}", mode));
+        create_corrupted_wals(&path);
+        assert!(LsmStorageInner::open(&path, options(mode)).is_err());
+    }
+
+    // Nothing after the corrupted record is recovered, including the later WAL.
+    let path = dir.path().join("point_in_time");
+    let wal_path = create_corrupted_wals(&path);
+    let storage = open(&path, WalRecoveryMode::PointInTime);
+    assert_eq!(get(&storage, b"key1"), Some(Bytes::from_static(b"value1")));
+    for key in [&b"key2"[..], b"key3", b"key4"] {
+        assert_eq!(get(&storage, key), None);
+    }
+    drop(storage);
+    let storage = open(&path, WalRecoveryMode::AbsoluteConsistency);
+    assert_eq!(get(&storage, b"key1"), Some(Bytes::from_static(b"value1")));
+    assert_eq!(get(&storage, b"key3"), None);
+    drop(storage);
+    assert!(std::fs::metadata(&wal_path).unwrap().len() > 0);
+
+    // Only the corrupted record is dropped, and the WAL is rewritten without it.
+    let path = dir.path().join("skip");
+    let wal_path = create_corrupted_wals(&path);
+    let len = std::fs::metadata(&wal_path).unwrap().len();
+    let storage = open(&path, WalRecoveryMode::SkipAnyCorruptedRecords);
+    assert_eq!(get(&storage, b"key2"), None);
+    for (key, value) in [
+        (&b"key1"[..], &b"value1"[..]),
+        (b"key3", b"value3"),
+        (b"key4", b"value4"),
+    ] {
+        assert_eq!(get(&storage, key).as_deref(), Some(value));
+    }
+    drop(storage);
+    assert!(std::fs::metadata(&wal_path).unwrap().len() < len);
+    let storage = open(&path, WalRecoveryMode::AbsoluteConsistency);
+    assert_eq!(get(&storage, b"key4"), Some(Bytes::from_static(b"value4")));
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+#[test]
+fn test_write_stall_on_imm_memtables() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_day6_test();
+    options.write_stall.slowdown_imm_memtables = 1;
+    options.write_stall.stop_imm_memtables = 2;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for _ in 0..2 {
+        storage.put(b"key", b"value").unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+    }
+
+    // Blocked until a memtable is flushed.
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"key", b"blocked"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    storage.force_flush_next_imm_memtable().unwrap();
+    writer.join().unwrap().unwrap();
+    let stats = storage.write_stall_stats();
+    assert_eq!(stats.stopped_writes, 1);
+    assert!(stats.stopped_duration >= Duration::from_millis(100));
+    // Delayed at the soft limit, as the second write above.
+    assert_eq!(stats.delayed_writes, 1);
+    storage.put(b"key", b"delayed").unwrap();
+    assert_eq!(storage.write_stall_stats().delayed_writes, 2);
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.put(b"key", b"value").unwrap();
+    let stats = storage.write_stall_stats();
+    assert_eq!((stats.delayed_writes, stats.stopped_writes), (2, 1));
+}
+
+#[test]
+fn test_write_stall_on_l0_files() {
+    let dir = tempdir().unwrap();
+    // Compactions are only run by hand, as no background thread is started.
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 1,
+            max_levels: 3,
+        },
+    ));
+    options.write_stall.stop_l0_files = 2;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for _ in 0..2 {
+        storage.put(b"key", b"value").unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+
+    // A blocked writer fails once background work stops on an error.
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"key", b"blocked"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    storage.set_background_error("compaction", anyhow!("injected"));
+    assert!(writer.join().unwrap().is_err());
+    *storage.background_error.lock() = None;
+
+    // Compacting L0 lifts the stall.
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"key", b"blocked"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    storage.trigger_compaction().unwrap();
+    writer.join().unwrap().unwrap();
+    assert_eq!(storage.write_stall_stats().stopped_writes, 2);
+    assert_eq!(
+        storage.get(b"key").unwrap().as_deref(),
+        Some(&b"blocked"[..])
+    );
+}
+
+#[test]
+fn test_write_stall_rejects_limits_never_lifted() {
+    let dir = tempdir().unwrap();
+    // Without compaction, L0 never shrinks.
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.write_stall.stop_l0_files = 8;
+    assert!(LsmStorageInner::open(&dir, options).is_err());
+    // Memtables are only flushed once there are `num_memtable_limit` of them.
+    let mut options = LsmStorageOptions::default_for_week1_day6_test();
+    options.write_stall.stop_imm_memtables = options.num_memtable_limit - 1;
+    assert!(LsmStorageInner::open(&dir, options).is_err());
+
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.write_stall.stop_l0_files = 4;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    assert!(storage
+        .create_column_family("raw", CompactionOptions::NoCompaction)
+        .is_err());
+}
@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::key::KeySlice;
+use crate::table::FileObject;
+use crate::varint::{get_varint, put_varint};
+
+/// Tag of a value stored inline in an SST.
+pub(crate) const VALUE_TAG_INLINE: u8 = 0;
+/// Tag of a value stored in a value log, followed by an encoded `ValuePointer`.
+pub(crate) const VALUE_TAG_POINTER: u8 = 1;
+
+/// Magic number at the start of every value log file with a format header. Files without it use
+/// the legacy record layout.
+const VALUE_LOG_MAGIC: u64 = 0x6d69_6e69_766c_6f67;
+/// Records store the key and value lengths as `u16` and `u32`, without a file header.
+const VALUE_LOG_FORMAT_LEGACY: u32 = 0;
+/// Records store the key and value lengths as varints, after a `magic (u64) | version (u32)`
+/// header.
+const VALUE_LOG_FORMAT_VARINT: u32 = 1;
+/// The format version used for newly-built value logs.
+const VALUE_LOG_FORMAT_LATEST: u32 = VALUE_LOG_FORMAT_VARINT;
+const VALUE_LOG_HEADER_LEN: usize = std::mem::size_of::<u64>() + std::mem::size_of::<u32>();
+
+/// Points to a record in a value log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValuePointer {
+    /// The id of the value log file.
+    pub file_id: usize,
+    /// Offset of the record in the file.
+    pub offset: u64,
+    /// Length of the record, including its checksum.
+    pub len: u32,
+}
+
+impl ValuePointer {
+    pub(crate) const ENCODED_LEN: usize = 1 + 8 + 8 + 4;
+
+    /// Encode the pointer as a tagged SST value.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(VALUE_TAG_POINTER);
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+    }
+
+    /// Decode a pointer from a tagged SST value.
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN || buf[0] != VALUE_TAG_POINTER {
+            bail!("invalid value pointer");
+        }
+        buf.advance(1);
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u32(),
+        })
+    }
+}
+
+/// Builds a value log file. It starts with a `magic (u64) | version (u32)` header, followed by
+/// records laid out as `key_len (varint) | key | ts (u64) | value_len (varint) | value |
+/// checksum (u32)`.
+pub struct ValueLogBuilder {
+    id: usize,
+    data: Vec<u8>,
+}
+
+impl ValueLogBuilder {
+    pub fn new(id: usize) -> Self {
+        let mut data = Vec::new();
+        data.put_u64(VALUE_LOG_MAGIC);
+        data.put_u32(VALUE_LOG_FORMAT_LATEST);
+        Self { id, data }
+    }
+
+    /// Append a value to the log and return the pointer to it.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> ValuePointer {
+        let offset = self.data.len();
+        put_varint(&mut self.data, key.key_len() as u64);
+        self.data.put_slice(key.key_ref());
+        self.data.put_u64(key.ts());
+        put_varint(&mut self.data, value.len() as u64);
+        self.data.put_slice(value);
+        let checksum = crc32fast::hash(&self.data[offset..]);
+        self.data.put_u32(checksum);
+        ValuePointer {
+            file_id: self.id,
+            offset: offset as u64,
+            len: (self.data.len() - offset) as u32,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.len() == VALUE_LOG_HEADER_LEN
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Write the value log to the given path.
+    pub fn build(self, path: impl AsRef<Path>) -> Result<ValueLogFile> {
+        Ok(ValueLogFile {
+            id: self.id,
+            file: FileObject::create(path.as_ref(), self.data)?,
+            format_version: VALUE_LOG_FORMAT_LATEST,
+        })
+    }
+}
+
+/// An immutable value log file.
+pub struct ValueLogFile {
+    id: usize,
+    file: FileObject,
+    format_version: u32,
+}
+
+impl ValueLogFile {
+    pub fn open(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let file = FileObject::open(path.as_ref())?;
+        let format_version = Self::read_format_version(&file)?;
+        Ok(Self {
+            id,
+            file,
+            format_version,
+        })
+    }
+
+    /// Read the format version from the header, if the file has one.
+    fn read_format_version(file: &FileObject) -> Result<u32> {
+        if file.size() < VALUE_LOG_HEADER_LEN as u64 {
+            return Ok(VALUE_LOG_FORMAT_LEGACY);
+        }
+        let mut header = &file.read(0, VALUE_LOG_HEADER_LEN as u64)?[..];
+        if header.get_u64() != VALUE_LOG_MAGIC {
+            return Ok(VALUE_LOG_FORMAT_LEGACY);
+        }
+        let version = header.get_u32();
+        if version > VALUE_LOG_FORMAT_LATEST {
+            bail!("unsupported value log format version {}", version);
+        }
+        Ok(version)
+    }
+
+    /// Read the value a pointer refers to.
+    pub fn read(&self, ptr: &ValuePointer) -> Result<Bytes> {
+        if ptr.file_id != self.id {
+            bail!(
+                "value pointer to {} read from value log {}",
+                ptr.file_id,
+                self.id
+            );
+        }
+        let data = self.file.read(ptr.offset, ptr.len as u64)?;
+        let (record, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(record) {
+            bail!("value log checksum mismatched");
+        }
+        let mut record = record;
+        let key_len = if self.format_version >= VALUE_LOG_FORMAT_VARINT {
+            get_varint(&mut record)? as usize
+        } else {
+            record.get_u16() as usize
+        };
+        if record.remaining() < key_len + std::mem::size_of::<u64>() {
+            bail!("incomplete value log record");
+        }
+        record.advance(key_len + std::mem::size_of::<u64>());
+        let value_len = if self.format_version >= VALUE_LOG_FORMAT_VARINT {
+            get_varint(&mut record)? as usize
+        } else {
+            record.get_u32() as usize
+        };
+        if record.remaining() != value_len {
+            bail!("incomplete value log record");
+        }
+        Ok(Bytes::copy_from_slice(record))
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+}
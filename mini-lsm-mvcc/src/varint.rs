@@ -0,0 +1,36 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+/// Append `value` as a LEB128 varint, 7 bits per byte with the high bit set on all but the last.
+pub(crate) fn put_varint(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Longest encoding of a `u64` varint.
+const MAX_VARINT_LEN: usize = 10;
+
+/// Read a varint written by `put_varint` and advance past it. Fails if the buffer ends before the
+/// varint does, or if the varint is longer than any `u64` encoding.
+pub(crate) fn get_varint(buf: &mut impl Buf) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..MAX_VARINT_LEN * 7).step_by(7) {
+        if !buf.has_remaining() {
+            bail!("truncated varint");
+        }
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    bail!("varint longer than {} bytes", MAX_VARINT_LEN)
+}
+
+/// Number of bytes `put_varint` writes for `value`.
+pub(crate) fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::hash::Hasher;
 use std::io::{BufWriter, Read, Write};
@@ -10,6 +11,43 @@ use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use crate::key::{KeyBytes, KeySlice};
+use crate::varint::{get_varint, put_varint};
+
+/// Set in the batch size header of a batch whose entries are tagged with the memtable they belong
+/// to, which is used when the WAL is shared by the memtables of several column families.
+const WAL_BATCH_TAGGED: u32 = 1 << 31;
+/// Set in the batch size header of a batch of range tombstones, whose entries store the start key
+/// as the key and the end key as the value.
+const WAL_BATCH_RANGE_TOMBSTONE: u32 = 1 << 30;
+/// Set in the batch size header of a batch whose key and value lengths are varints instead of
+/// `u16`, which is the case for all batches written since keys and values may exceed 64 KiB.
+const WAL_BATCH_VARINT: u32 = 1 << 29;
+/// All flags of the batch size header. The remaining bits are the size of the batch.
+const WAL_BATCH_FLAGS: u32 = WAL_BATCH_TAGGED | WAL_BATCH_RANGE_TOMBSTONE | WAL_BATCH_VARINT;
+
+/// How corrupted records are handled when a WAL is recovered on open. Records are dropped by
+/// truncating or rewriting the WAL, so that later writes are appended at a clean boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Fail to open on any corrupted record, including an incomplete last record.
+    AbsoluteConsistency,
+    /// Drop a corrupted last record, which is expected if a crash interrupted appending it, and
+    /// fail on corrupted records anywhere else.
+    TolerateCorruptedTailRecords,
+    /// Recover up to the first corrupted record, and drop everything after it including later WALs.
+    PointInTime,
+    /// Drop corrupted records and recover all intact ones around them.
+    SkipAnyCorruptedRecords,
+}
+
+/// The skiplists a memtable is recovered into: its key-value pairs and its range tombstones.
+pub(crate) type MemTableSkipLists = (Arc<SkipMap<KeyBytes, Bytes>>, Arc<SkipMap<KeyBytes, Bytes>>);
+
+/// A decoded WAL batch. Entries are `(memtable id, key, ts, value)`.
+struct WalBatch {
+    range_tombstone: bool,
+    kv_pairs: Vec<(Option<usize>, Bytes, u64, Bytes)>,
+}
 
 pub struct Wal {
     file: Arc<Mutex<BufWriter<File>>>,
@@ -29,8 +67,116 @@ impl Wal {
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
-        let path = path.as_ref();
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        range_tombstones: &SkipMap<KeyBytes, Bytes>,
+    ) -> Result<Self> {
+        let mode = WalRecoveryMode::AbsoluteConsistency;
+        let (wal, _) =
+            Self::recover_inner(path.as_ref(), mode, |_, range_tombstone, key, value| {
+                if range_tombstone {
+                    range_tombstones.insert(key, value);
+                } else {
+                    skiplist.insert(key, value);
+                }
+            })?;
+        Ok(wal)
+    }
+
+    /// Recover a WAL shared by several memtables. Entries without a memtable tag belong to the
+    /// memtable `id` that owns the WAL, and entries of memtables not in `skiplists` are skipped.
+    /// Corrupted records are handled as set by `mode`. Returns whether any record was dropped, and
+    /// whether any entry was recovered into a memtable other than `id`.
+    pub fn recover_shared(
+        path: impl AsRef<Path>,
+        id: usize,
+        skiplists: &HashMap<usize, MemTableSkipLists>,
+        mode: WalRecoveryMode,
+    ) -> Result<(Self, bool, bool)> {
+        let mut shared = false;
+        let (wal, dropped) = Self::recover_inner(
+            path.as_ref(),
+            mode,
+            |memtable_id, range_tombstone, key, value| {
+                let memtable_id = memtable_id.unwrap_or(id);
+                if let Some((skiplist, range_tombstones)) = skiplists.get(&memtable_id) {
+                    shared |= memtable_id != id;
+                    if range_tombstone {
+                        range_tombstones.insert(key, value);
+                    } else {
+                        skiplist.insert(key, value);
+                    }
+                }
+            },
+        )?;
+        Ok((wal, dropped, shared))
+    }
+
+    /// Replay all entries of a possibly damaged WAL into one memtable without opening it for
+    /// writing, including those of other memtables sharing the WAL. Corrupted batches are skipped
+    /// and the intact ones around them are replayed. Returns whether the WAL is corrupted.
+    pub fn salvage(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        range_tombstones: &SkipMap<KeyBytes, Bytes>,
+    ) -> Result<bool> {
+        Self::salvage_inner(path.as_ref(), |_, range_tombstone, key, value| {
+            if range_tombstone {
+                range_tombstones.insert(key, value);
+            } else {
+                skiplist.insert(key, value);
+            }
+        })
+    }
+
+    /// Replay a possibly damaged WAL shared by several memtables without opening it for writing,
+    /// see `recover_shared`. Corrupted batches are skipped and the intact ones around them are
+    /// replayed. Returns whether the WAL is corrupted.
+    pub fn salvage_shared(
+        path: impl AsRef<Path>,
+        id: usize,
+        skiplists: &HashMap<usize, MemTableSkipLists>,
+    ) -> Result<bool> {
+        Self::salvage_inner(path.as_ref(), |memtable_id, range_tombstone, key, value| {
+            if let Some((skiplist, range_tombstones)) = skiplists.get(&memtable_id.unwrap_or(id)) {
+                if range_tombstone {
+                    range_tombstones.insert(key, value);
+                } else {
+                    skiplist.insert(key, value);
+                }
+            }
+        })
+    }
+
+    fn salvage_inner(
+        path: &Path,
+        mut apply: impl FnMut(Option<usize>, bool, KeyBytes, Bytes),
+    ) -> Result<bool> {
+        let buf = std::fs::read(path).context("failed to read WAL")?;
+        let mut corrupted = false;
+        let mut offset = 0;
+        while offset < buf.len() {
+            let mut rbuf = &buf[offset..];
+            match Self::decode_batch(&mut rbuf) {
+                Ok(batch) => {
+                    Self::apply_batch(batch, &mut apply);
+                    offset = buf.len() - rbuf.len();
+                }
+                Err(_) => {
+                    corrupted = true;
+                    offset = Self::next_record(&buf, offset);
+                }
+            }
+        }
+        Ok(corrupted)
+    }
+
+    fn recover_inner(
+        path: &Path,
+        mode: WalRecoveryMode,
+        mut apply: impl FnMut(Option<usize>, bool, KeyBytes, Bytes),
+    ) -> Result<(Self, bool)> {
         let mut file = OpenOptions::new()
             .read(true)
             .append(true)
@@ -38,73 +184,307 @@ impl Wal {
             .context("failed to recover from WAL")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let batch_size = rbuf.get_u32() as usize;
-            if rbuf.remaining() < batch_size {
-                bail!("incomplete WAL");
-            }
-            let mut batch_buf = &rbuf[..batch_size];
-            let mut kv_pairs = Vec::new();
-            let mut hasher = crc32fast::Hasher::new();
-            // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
-            // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
-            let single_checksum = crc32fast::hash(batch_buf);
-            while batch_buf.has_remaining() {
-                let key_len = batch_buf.get_u16() as usize;
-                hasher.write(&(key_len as u16).to_be_bytes());
-                let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
-                hasher.write(&key);
-                batch_buf.advance(key_len);
-                let ts = batch_buf.get_u64();
-                hasher.write(&ts.to_be_bytes());
-                let value_len = batch_buf.get_u16() as usize;
-                hasher.write(&(value_len as u16).to_be_bytes());
-                let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
-                hasher.write(&value);
-                kv_pairs.push((key, ts, value));
-                batch_buf.advance(value_len);
-            }
-            rbuf.advance(batch_size);
-            let expected_checksum = rbuf.get_u32();
-            let component_checksum = hasher.finalize();
-            assert_eq!(component_checksum, single_checksum);
-            if single_checksum != expected_checksum {
-                bail!("checksum mismatch");
+        // Byte ranges of the intact records.
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let mut rbuf = &buf[offset..];
+            let error = match Self::decode_batch(&mut rbuf) {
+                Ok(batch) => {
+                    Self::apply_batch(batch, &mut apply);
+                    let end = buf.len() - rbuf.len();
+                    records.push(offset..end);
+                    offset = end;
+                    continue;
+                }
+                Err(e) => e,
+            };
+            let end = match mode {
+                WalRecoveryMode::AbsoluteConsistency => {
+                    return Err(error.context(format!("corrupted WAL record at offset {}", offset)));
+                }
+                WalRecoveryMode::TolerateCorruptedTailRecords => {
+                    if !Self::is_last_record(&buf[offset..]) {
+                        return Err(
+                            error.context(format!("corrupted WAL record at offset {}", offset))
+                        );
+                    }
+                    buf.len()
+                }
+                WalRecoveryMode::PointInTime => buf.len(),
+                WalRecoveryMode::SkipAnyCorruptedRecords => Self::next_record(&buf, offset),
+            };
+            println!(
+                "{}: dropped {} bytes of corrupted records at offset {}: {}",
+                path.display(),
+                end - offset,
+                offset,
+                error
+            );
+            offset = end;
+        }
+        let recovered_len = records.iter().map(|range| range.len()).sum::<usize>();
+        if recovered_len == buf.len() {
+            let wal = Self {
+                file: Arc::new(Mutex::new(BufWriter::new(file))),
+            };
+            return Ok((wal, false));
+        }
+        if records.last().map_or(0, |range| range.end) == recovered_len {
+            file.set_len(recovered_len as u64)?;
+            file.sync_all()?;
+        } else {
+            // Intact records follow the dropped ones, so they are moved together in a new file.
+            let mut data = Vec::with_capacity(recovered_len);
+            for range in records {
+                data.extend_from_slice(&buf[range]);
             }
-            for (key, ts, value) in kv_pairs {
-                skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            let tmp_path = path.with_extension("wal.tmp");
+            std::fs::write(&tmp_path, &data)?;
+            File::open(&tmp_path)?.sync_all()?;
+            std::fs::rename(&tmp_path, path)?;
+            if let Some(dir) = path.parent() {
+                File::open(dir)?.sync_all()?;
             }
+            file = OpenOptions::new()
+                .read(true)
+                .append(true)
+                .open(path)
+                .context("failed to recover from WAL")?;
         }
-        Ok(Self {
+        let wal = Self {
             file: Arc::new(Mutex::new(BufWriter::new(file))),
+        };
+        Ok((wal, true))
+    }
+
+    /// Whether the corrupted record at the start of `buf` is the last one, i.e. it reaches the end of
+    /// the WAL or runs past it.
+    fn is_last_record(buf: &[u8]) -> bool {
+        if buf.len() < 4 {
+            return true;
+        }
+        let batch_header = (&buf[..4]).get_u32();
+        let batch_size = (batch_header & !WAL_BATCH_FLAGS) as usize;
+        batch_size + 8 >= buf.len()
+    }
+
+    /// Find where the intact records after the corrupted one at `offset` resume, or the end of the
+    /// WAL if there are none. The record is skipped by its length if it is followed by an intact
+    /// one, otherwise the next intact record is searched for byte by byte.
+    fn next_record(buf: &[u8], offset: usize) -> usize {
+        let is_intact = |start: usize| Self::decode_batch(&mut &buf[start..]).is_ok();
+        if buf.len() - offset >= 4 {
+            let batch_header = (&buf[offset..offset + 4]).get_u32();
+            let batch_size = (batch_header & !WAL_BATCH_FLAGS) as usize;
+            let end = offset + batch_size + 8;
+            if end == buf.len() || (end < buf.len() && is_intact(end)) {
+                return end;
+            }
+        }
+        (offset + 1..buf.len())
+            .find(|start| is_intact(*start))
+            .unwrap_or(buf.len())
+    }
+
+    /// Drop all records of a WAL written after records dropped from an earlier WAL in
+    /// point-in-time recovery.
+    pub(crate) fn discard(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .context("failed to discard WAL")?;
+        let len = file.metadata()?.len();
+        if len > 0 {
+            file.set_len(0)?;
+            file.sync_all()?;
+            println!(
+                "{}: dropped {} bytes written after corrupted records of an earlier WAL",
+                path.display(),
+                len
+            );
+        }
+        Ok(())
+    }
+
+    /// Decode the batch at the start of `rbuf` and advance past it. The checksum is verified before
+    /// decoding the entries, so that a corrupted length cannot overrun the batch.
+    fn decode_batch(rbuf: &mut &[u8]) -> Result<WalBatch> {
+        if rbuf.remaining() < 4 {
+            bail!("incomplete WAL");
+        }
+        let batch_header = rbuf.get_u32();
+        let tagged = batch_header & WAL_BATCH_TAGGED != 0;
+        let range_tombstone = batch_header & WAL_BATCH_RANGE_TOMBSTONE != 0;
+        let varint = batch_header & WAL_BATCH_VARINT != 0;
+        let batch_size = (batch_header & !WAL_BATCH_FLAGS) as usize;
+        if rbuf.remaining() < batch_size + 4 {
+            bail!("incomplete WAL");
+        }
+        let mut batch_buf = &rbuf[..batch_size];
+        rbuf.advance(batch_size);
+        let expected_checksum = rbuf.get_u32();
+        // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
+        // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
+        let single_checksum = crc32fast::hash(batch_buf);
+        if single_checksum != expected_checksum {
+            bail!("checksum mismatch");
+        }
+        let mut kv_pairs = Vec::new();
+        let mut hasher = crc32fast::Hasher::new();
+        while batch_buf.has_remaining() {
+            let memtable_id = if tagged {
+                let memtable_id = batch_buf.get_u64();
+                hasher.write(&memtable_id.to_be_bytes());
+                Some(memtable_id as usize)
+            } else {
+                None
+            };
+            let key_len = Self::get_len(&mut batch_buf, varint, &mut hasher)?;
+            let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
+            hasher.write(&key);
+            batch_buf.advance(key_len);
+            let ts = batch_buf.get_u64();
+            hasher.write(&ts.to_be_bytes());
+            let value_len = Self::get_len(&mut batch_buf, varint, &mut hasher)?;
+            let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
+            hasher.write(&value);
+            kv_pairs.push((memtable_id, key, ts, value));
+            batch_buf.advance(value_len);
+        }
+        let component_checksum = hasher.finalize();
+        assert_eq!(component_checksum, single_checksum);
+        Ok(WalBatch {
+            range_tombstone,
+            kv_pairs,
         })
     }
 
+    /// Read a key or value length of a batch entry and feed its encoding to `hasher`.
+    fn get_len(buf: &mut &[u8], varint: bool, hasher: &mut crc32fast::Hasher) -> Result<usize> {
+        let start = *buf;
+        let len = if varint {
+            get_varint(buf)? as usize
+        } else {
+            buf.get_u16() as usize
+        };
+        hasher.write(&start[..start.len() - buf.len()]);
+        Ok(len)
+    }
+
+    fn apply_batch(batch: WalBatch, mut apply: impl FnMut(Option<usize>, bool, KeyBytes, Bytes)) {
+        for (memtable_id, key, ts, value) in batch.kv_pairs {
+            apply(
+                memtable_id,
+                batch.range_tombstone,
+                KeyBytes::from_bytes_with_ts(key, ts),
+                value,
+            );
+        }
+    }
+
     /// Implement this in week 3, day 5.
     pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
-        let mut file = self.file.lock();
+        self.put_batches(&[data])
+    }
+
+    /// Write the batches of a commit group with one append. Each batch is a separate record, so
+    /// that it is recovered atomically on its own.
+    pub fn put_batches(&self, batches: &[&[(KeySlice, &[u8])]]) -> Result<()> {
+        let mut records = Vec::new();
         let mut buf = Vec::<u8>::new();
-        for (key, value) in data {
-            buf.put_u16(key.key_len() as u16);
-            buf.put_slice(key.key_ref());
-            buf.put_u64(key.ts());
-            buf.put_u16(value.len() as u16);
-            buf.put_slice(value);
+        for data in batches {
+            buf.clear();
+            for (key, value) in *data {
+                put_varint(&mut buf, key.key_len() as u64);
+                buf.put_slice(key.key_ref());
+                buf.put_u64(key.ts());
+                put_varint(&mut buf, value.len() as u64);
+                buf.put_slice(value);
+            }
+            Self::encode_batch(&mut records, &buf, 0)?;
+        }
+        self.file.lock().write_all(&records)?;
+        Ok(())
+    }
+
+    /// Write a batch body with the batch size header and the checksum.
+    fn write_batch(file: &mut BufWriter<File>, buf: &[u8], flags: u32) -> Result<()> {
+        let mut record = Vec::with_capacity(buf.len() + 8);
+        Self::encode_batch(&mut record, buf, flags)?;
+        file.write_all(&record)?;
+        Ok(())
+    }
+
+    /// Append a batch body with the batch size header and the checksum to `record`.
+    fn encode_batch(record: &mut Vec<u8>, buf: &[u8], flags: u32) -> Result<()> {
+        if buf.len() as u64 > (!WAL_BATCH_FLAGS) as u64 {
+            bail!("WAL batch of {} bytes is too large", buf.len());
         }
         // write batch_size header (u32)
-        file.write_all(&(buf.len() as u32).to_be_bytes())?;
+        record.put_u32(buf.len() as u32 | WAL_BATCH_VARINT | flags);
         // write key-value pairs body
-        file.write_all(&buf)?;
+        record.put_slice(buf);
         // write checksum (u32)
-        file.write_all(&crc32fast::hash(&buf).to_be_bytes())?;
+        record.put_u32(crc32fast::hash(buf));
         Ok(())
     }
 
+    /// Write a batch whose entries may belong to different memtables sharing this WAL. Each entry
+    /// is tagged with the id of its memtable.
+    pub fn put_batch_tagged(&self, data: &[(usize, KeySlice, &[u8])]) -> Result<()> {
+        let mut file = self.file.lock();
+        let mut buf = Vec::<u8>::new();
+        for (memtable_id, key, value) in data {
+            buf.put_u64(*memtable_id as u64);
+            put_varint(&mut buf, key.key_len() as u64);
+            buf.put_slice(key.key_ref());
+            buf.put_u64(key.ts());
+            put_varint(&mut buf, value.len() as u64);
+            buf.put_slice(value);
+        }
+        Self::write_batch(&mut file, &buf, WAL_BATCH_TAGGED)
+    }
+
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
         self.put_batch(&[(key, value)])
     }
 
+    /// Write a range tombstone deleting the keys in `[start, end)` before the timestamp of `start`.
+    pub fn put_range_tombstone(&self, start: KeySlice, end: &[u8]) -> Result<()> {
+        let mut file = self.file.lock();
+        let mut buf = Vec::<u8>::new();
+        put_varint(&mut buf, start.key_len() as u64);
+        buf.put_slice(start.key_ref());
+        buf.put_u64(start.ts());
+        put_varint(&mut buf, end.len() as u64);
+        buf.put_slice(end);
+        Self::write_batch(&mut file, &buf, WAL_BATCH_RANGE_TOMBSTONE)
+    }
+
+    /// Write a range tombstone of another memtable sharing this WAL, see `put_range_tombstone`.
+    pub fn put_range_tombstone_tagged(
+        &self,
+        memtable_id: usize,
+        start: KeySlice,
+        end: &[u8],
+    ) -> Result<()> {
+        let mut file = self.file.lock();
+        let mut buf = Vec::<u8>::new();
+        buf.put_u64(memtable_id as u64);
+        put_varint(&mut buf, start.key_len() as u64);
+        buf.put_slice(start.key_ref());
+        buf.put_u64(start.ts());
+        put_varint(&mut buf, end.len() as u64);
+        buf.put_slice(end);
+        Self::write_batch(
+            &mut file,
+            &buf,
+            WAL_BATCH_TAGGED | WAL_BATCH_RANGE_TOMBSTONE,
+        )
+    }
+
     pub fn sync(&self) -> Result<()> {
         let mut file = self.file.lock();
         file.flush()?;
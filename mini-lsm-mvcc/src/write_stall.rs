@@ -0,0 +1,231 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use parking_lot::{Condvar, Mutex};
+
+use crate::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use crate::lsm_storage::LsmStorageInner;
+
+/// Limits on the backlog of flushes and compactions. Writes are delayed at the soft (`slowdown_*`)
+/// limits and blocked at the hard (`stop_*`) limits until the background threads catch up. All
+/// limits are disabled by default.
+#[derive(Debug, Clone)]
+pub struct WriteStallOptions {
+    /// Number of immutable memtables at which writes are delayed.
+    pub slowdown_imm_memtables: usize,
+    /// Number of immutable memtables at which writes are blocked. Must be at least
+    /// `num_memtable_limit`, where memtables start to be flushed.
+    pub stop_imm_memtables: usize,
+    /// Number of L0 SSTs, or of tiers in tiered compaction, at which writes are delayed.
+    pub slowdown_l0_files: usize,
+    /// Number of L0 SSTs, or of tiers in tiered compaction, at which writes are blocked. Must be
+    /// above the number that triggers a compaction.
+    pub stop_l0_files: usize,
+    /// Estimated bytes waiting to be compacted at which writes are delayed.
+    pub slowdown_pending_compaction_bytes: u64,
+    /// Estimated bytes waiting to be compacted at which writes are blocked.
+    pub stop_pending_compaction_bytes: u64,
+    /// How long each write is delayed at a soft limit.
+    pub slowdown_delay: Duration,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> Self {
+        Self {
+            slowdown_imm_memtables: usize::MAX,
+            stop_imm_memtables: usize::MAX,
+            slowdown_l0_files: usize::MAX,
+            stop_l0_files: usize::MAX,
+            slowdown_pending_compaction_bytes: u64::MAX,
+            stop_pending_compaction_bytes: u64::MAX,
+            slowdown_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+impl WriteStallOptions {
+    /// Check that the hard limits are lifted by flushes and compactions with the given options, as
+    /// blocked writes would wait forever otherwise.
+    pub(crate) fn validate(
+        &self,
+        compaction_options: &CompactionOptions,
+        num_memtable_limit: usize,
+    ) -> Result<()> {
+        if self.stop_imm_memtables < num_memtable_limit {
+            bail!(
+                "stop_imm_memtables ({}) must be at least num_memtable_limit ({})",
+                self.stop_imm_memtables,
+                num_memtable_limit
+            );
+        }
+        let compaction_trigger = match compaction_options {
+            CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger,
+                ..
+            })
+            | CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                level0_file_num_compaction_trigger,
+                ..
+            }) => *level0_file_num_compaction_trigger,
+            CompactionOptions::Tiered(TieredCompactionOptions { num_tiers, .. }) => *num_tiers,
+            CompactionOptions::NoCompaction => {
+                if self.stop_l0_files < usize::MAX || self.stop_pending_compaction_bytes < u64::MAX
+                {
+                    bail!("stop_l0_files and stop_pending_compaction_bytes require compaction");
+                }
+                return Ok(());
+            }
+        };
+        if self.stop_l0_files <= compaction_trigger {
+            bail!(
+                "stop_l0_files ({}) must be above the number of files that triggers a compaction ({})",
+                self.stop_l0_files,
+                compaction_trigger
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Wakes up the writes blocked at a hard limit when flushes or compactions make progress or stop
+/// on an error, shared by all column families.
+#[derive(Default)]
+pub(crate) struct WriteStallSignal {
+    lock: Mutex<()>,
+    progress: Condvar,
+}
+
+impl WriteStallSignal {
+    /// Wake up the blocked writes to check the limits again.
+    pub(crate) fn notify(&self) {
+        // Taken so that a write cannot miss the wakeup between checking the limits and waiting.
+        let _guard = self.lock.lock();
+        self.progress.notify_all();
+    }
+}
+
+/// How many writes were delayed or blocked by `WriteStallOptions`, and for how long in total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    pub delayed_writes: u64,
+    pub delayed_duration: Duration,
+    pub stopped_writes: u64,
+    pub stopped_duration: Duration,
+}
+
+/// The counters behind `WriteStallStats`, shared by all column families.
+#[derive(Default)]
+pub(crate) struct WriteStallCounters {
+    delayed_writes: AtomicU64,
+    delayed_micros: AtomicU64,
+    stopped_writes: AtomicU64,
+    stopped_micros: AtomicU64,
+}
+
+impl WriteStallCounters {
+    fn record(&self, writes: &AtomicU64, micros: &AtomicU64, duration: Duration) {
+        writes.fetch_add(1, Ordering::Relaxed);
+        micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> WriteStallStats {
+        WriteStallStats {
+            delayed_writes: self.delayed_writes.load(Ordering::Relaxed),
+            delayed_duration: Duration::from_micros(self.delayed_micros.load(Ordering::Relaxed)),
+            stopped_writes: self.stopped_writes.load(Ordering::Relaxed),
+            stopped_duration: Duration::from_micros(self.stopped_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+enum WriteStall {
+    None,
+    Delay,
+    Stop,
+}
+
+impl LsmStorageInner {
+    fn write_stall(&self) -> WriteStall {
+        let options = &self.options.write_stall;
+        let snapshot = self.state.read().clone();
+        let imm_memtables = snapshot.imm_memtables.len();
+        let l0_files = if self.compaction_controller.flush_to_l0() {
+            snapshot.l0_sstables.len()
+        } else {
+            snapshot.levels.len()
+        };
+        // Estimating the pending bytes walks all SSTs, so skip it unless it is limited.
+        let pending_compaction_bytes = if options.slowdown_pending_compaction_bytes < u64::MAX
+            || options.stop_pending_compaction_bytes < u64::MAX
+        {
+            self.compaction_controller
+                .pending_compaction_bytes(&snapshot)
+        } else {
+            0
+        };
+        if imm_memtables >= options.stop_imm_memtables
+            || l0_files >= options.stop_l0_files
+            || pending_compaction_bytes >= options.stop_pending_compaction_bytes
+        {
+            WriteStall::Stop
+        } else if imm_memtables >= options.slowdown_imm_memtables
+            || l0_files >= options.slowdown_l0_files
+            || pending_compaction_bytes >= options.slowdown_pending_compaction_bytes
+        {
+            WriteStall::Delay
+        } else {
+            WriteStall::None
+        }
+    }
+
+    /// Delay or block a write while flushes or compactions are behind the limits in
+    /// `WriteStallOptions`. With `no_slowdown`, the write fails instead.
+    pub(crate) fn stall_write(&self, no_slowdown: bool) -> Result<()> {
+        let start = Instant::now();
+        let counters = &self.write_stall_counters;
+        match self.write_stall() {
+            WriteStall::None => {}
+            WriteStall::Delay | WriteStall::Stop if no_slowdown => {
+                bail!("write stalled because flushes or compactions fall behind")
+            }
+            WriteStall::Delay => {
+                std::thread::sleep(self.options.write_stall.slowdown_delay);
+                counters.record(
+                    &counters.delayed_writes,
+                    &counters.delayed_micros,
+                    start.elapsed(),
+                );
+            }
+            WriteStall::Stop => {
+                let signal = &self.write_stall_signal;
+                let mut guard = signal.lock.lock();
+                let result = loop {
+                    // The backlog only shrinks while background work succeeds.
+                    if let Err(e) = self.check_writable() {
+                        break Err(e);
+                    }
+                    if !matches!(self.write_stall(), WriteStall::Stop) {
+                        break Ok(());
+                    }
+                    signal.progress.wait(&mut guard);
+                };
+                drop(guard);
+                counters.record(
+                    &counters.stopped_writes,
+                    &counters.stopped_micros,
+                    start.elapsed(),
+                );
+                return result;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        self.write_stall_counters.stats()
+    }
+}